@@ -0,0 +1,170 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/acpi: fadt.rs                                                                           #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::paging::PhysicalAddress;
+use super::Table;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+const DSDT: usize = 40;
+const SCI_INTERRUPT: usize = 46;
+const PM_TIMER_BLOCK: usize = 76;
+const CENTURY: usize = 108;
+const BOOT_ARCHITECTURE: usize = 109;           // Reserved before ACPI 2.0, and so zero
+const FLAGS: usize = 112;
+const X_DSDT: usize = 140;                      // 64-bit DSDT address, from ACPI 2.0
+
+const BOOT_LEGACY_DEVICES: u16 = 1 << 0;        // ISA devices such as the serial ports exist
+const BOOT_8042: u16 = 1 << 1;                  // There is a PS/2 controller
+const BOOT_NO_CMOS_RTC: u16 = 1 << 5;           // The CMOS RTC is absent
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Clone, Copy, Debug)]
+//==================================================================================================
+pub struct Fadt {
+//--------------------------------------------------------------------------------------------------
+// Fields of the fixed ACPI description table the kernel has use for. Fields past the end of an
+// older, shorter table read as zero.
+//==================================================================================================
+
+    pub dsdt: PhysicalAddress,          // Differentiated system description table
+    pub sci_interrupt: u16,             // ISA IRQ of the system control interrupt
+    pub pm_timer_block: u32,            // Port of the ACPI power management timer, 0 if none
+    pub century_register: Option<u8>,   // CMOS register holding the RTC's century
+    pub boot_architecture: u16,         // IA-PC boot architecture flags
+    pub flags: u32,
+}
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl Fadt {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn parse(table: &Table) -> Fadt {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for Fadt, reading the fields out of the table.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   table -> table signed "FACP"
+    //
+    // RETURNS: the fields
+    //==============================================================================================
+
+        let fits = |offset: usize, size: usize| offset + size <= table.length();
+
+        let x_dsdt = if (fits(X_DSDT, 8)) { table.read_u64(X_DSDT) as usize } else { 0 };
+        let dsdt = if (x_dsdt != 0) { x_dsdt } else { table.read_u32(DSDT) as usize };
+
+        let century = if (fits(CENTURY, 1)) { table.read_u8(CENTURY) } else { 0 };
+
+        Fadt {
+            dsdt: PhysicalAddress::new(dsdt),
+            sci_interrupt: table.read_u16(SCI_INTERRUPT),
+            pm_timer_block: if (fits(PM_TIMER_BLOCK, 4)) {
+                table.read_u32(PM_TIMER_BLOCK)
+            }
+            else {
+                0
+            },
+            century_register: if (century != 0) { Some(century) } else { None },
+            boot_architecture: if (fits(BOOT_ARCHITECTURE, 2)) {
+                table.read_u16(BOOT_ARCHITECTURE)
+            }
+            else {
+                0
+            },
+            flags: if (fits(FLAGS, 4)) { table.read_u32(FLAGS) } else { 0 },
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn has_legacy_devices(&self) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Determine whether ISA devices, such as the serial ports, may be present.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: true if they may, false otherwise
+    //==============================================================================================
+
+        self.boot_architecture & BOOT_LEGACY_DEVICES != 0
+    }
+
+
+    //==============================================================================================
+    pub fn has_8042(&self) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Determine whether there is a PS/2 keyboard controller.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: true if there is, false otherwise
+    //==============================================================================================
+
+        self.boot_architecture & BOOT_8042 != 0
+    }
+
+
+    //==============================================================================================
+    pub fn has_cmos_rtc(&self) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Determine whether the CMOS real-time clock is present.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: true if it is, false otherwise
+    //==============================================================================================
+
+        self.boot_architecture & BOOT_NO_CMOS_RTC == 0
+    }
+}
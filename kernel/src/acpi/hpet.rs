@@ -0,0 +1,115 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/acpi: hpet.rs                                                                           #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::paging::PhysicalAddress;
+use super::Table;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+const EVENT_TIMER_BLOCK_ID: usize = 36;
+const ADDRESS_SPACE: usize = 40;                // Generic address structure of the registers
+const ADDRESS: usize = 44;
+const HPET_NUMBER: usize = 52;
+const MINIMUM_TICK: usize = 53;
+const TABLE_SIZE: usize = 56;
+
+const SYSTEM_MEMORY: u8 = 0;                    // Address space of memory mapped registers
+const COMPARATORS_SHIFT: u32 = 8;               // Index of the last comparator, bits 8-12
+const COMPARATORS_MASK: u32 = 0x1F;
+const COUNTER_64: u32 = 1 << 13;
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Clone, Copy, Debug)]
+//==================================================================================================
+pub struct HpetDescription {
+//--------------------------------------------------------------------------------------------------
+// High precision event timer described by the HPET table.
+//==================================================================================================
+
+    pub address: PhysicalAddress,       // Register window
+    pub number: u8,                     // Sequence number of this HPET
+    pub comparators: u8,
+    pub counter_64: bool,               // Main counter is 64 bits wide
+    pub minimum_tick: u16,              // Shortest periodic interval, in counter increments
+}
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl HpetDescription {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn parse(table: &Table) -> Option<HpetDescription> {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for HpetDescription, reading the fields out of the table.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   table -> table signed "HPET"
+    //
+    // RETURNS: Some(...) -> the description
+    //          None      -> the table is truncated, or the registers are not memory mapped
+    //==============================================================================================
+
+        if (table.length() < TABLE_SIZE || table.read_u8(ADDRESS_SPACE) != SYSTEM_MEMORY) {
+            return None;
+        }
+
+        let id = table.read_u32(EVENT_TIMER_BLOCK_ID);
+
+        Some(HpetDescription {
+            address: PhysicalAddress::new(table.read_u64(ADDRESS) as usize),
+            number: table.read_u8(HPET_NUMBER),
+            comparators: ((id >> COMPARATORS_SHIFT) & COMPARATORS_MASK) as u8 + 1,
+            counter_64: id & COUNTER_64 != 0,
+            minimum_tick: table.read_u16(MINIMUM_TICK),
+        })
+    }
+}
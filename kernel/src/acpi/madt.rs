@@ -0,0 +1,272 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/acpi: madt.rs                                                                           #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use interrupts::{InterruptSourceOverride, TriggerMode, Polarity};
+use memory::paging::PhysicalAddress;
+use super::Table;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+const LOCAL_APIC_ADDRESS: usize = 36;
+const FLAGS: usize = 40;
+const ENTRIES: usize = 44;
+const FLAG_PCAT_COMPAT: u32 = 1 << 0;           // The system also has a pair of 8259 PICs
+
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_SOURCE_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_NMI: u8 = 4;
+const ENTRY_LOCAL_APIC_ADDRESS: u8 = 5;
+const ENTRY_LOCAL_X2APIC: u8 = 9;
+
+const PROCESSOR_ENABLED: u32 = 1 << 0;
+const INTI_POLARITY: u16 = 0b11;                // Bits 0-1 of MPS INTI flags
+const INTI_ACTIVE_LOW: u16 = 0b11;
+const INTI_TRIGGER: u16 = 0b11 << 2;            // Bits 2-3 of MPS INTI flags
+const INTI_LEVEL: u16 = 0b11 << 2;
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub struct Madt {
+//--------------------------------------------------------------------------------------------------
+// Multiple APIC description table, listing the interrupt controllers and processors.
+//==================================================================================================
+
+    table: Table,
+}
+
+
+#[derive(Clone, Copy, Debug)]
+//==================================================================================================
+pub enum MadtEntry {
+//--------------------------------------------------------------------------------------------------
+// Entry of the MADT. Kinds the kernel has no use for are reported only by their type.
+//==================================================================================================
+
+    Processor {                         // Local APIC or local x2APIC of a processor
+        processor_id: u32,
+        apic_id: u32,
+        enabled: bool,                  // Usable now, rather than only after hot-plugging
+    },
+    IoApic {
+        id: u8,
+        address: PhysicalAddress,
+        gsi_base: u32,                  // First global system interrupt it serves
+    },
+    Override(InterruptSourceOverride),  // ISA IRQ wired to another input or signalled oddly
+    LocalApicNmi {
+        processor_id: u8,               // 0xFF for every processor
+        lint: u8,                       // LINT pin the NMI arrives on
+    },
+    LocalApicAddress(PhysicalAddress),  // 64-bit address replacing the table's 32-bit one
+    Other(u8),
+}
+
+
+//==================================================================================================
+pub struct MadtEntryIter<'a> {
+//--------------------------------------------------------------------------------------------------
+// Iterator over the entries of the MADT.
+//==================================================================================================
+
+    table: &'a Table,
+    offset: usize,                      // Offset of the next entry
+}
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl Madt {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn new(table: Table) -> Madt {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for Madt.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   table -> table signed "APIC"
+    //
+    // RETURNS: the MADT
+    //==============================================================================================
+
+        Madt { table: table }
+    }
+
+
+    //==============================================================================================
+    pub fn local_apic_address(&self) -> PhysicalAddress {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the physical address of every processor's local APIC register window.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the address, from an address override entry if there is one
+    //==============================================================================================
+
+        self.entries().filter_map(|entry| match entry {
+            MadtEntry::LocalApicAddress(address) => Some(address),
+            _                                    => None,
+        }).next().unwrap_or(PhysicalAddress::new(self.table.read_u32(LOCAL_APIC_ADDRESS) as usize))
+    }
+
+
+    //==============================================================================================
+    pub fn has_legacy_pics(&self) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Determine whether the system has 8259 PICs, which must be masked if the APICs are used.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: true if it does, false otherwise
+    //==============================================================================================
+
+        self.table.read_u32(FLAGS) & FLAG_PCAT_COMPAT != 0
+    }
+
+
+    //==============================================================================================
+    pub fn entries(&self) -> MadtEntryIter {
+    //----------------------------------------------------------------------------------------------
+    // Obtain an iterator over the table's entries.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the iterator
+    //==============================================================================================
+
+        MadtEntryIter { table: &self.table, offset: ENTRIES }
+    }
+}
+
+
+//==================================================================================================
+impl<'a> Iterator for MadtEntryIter<'a> {
+//==================================================================================================
+
+
+    type Item = MadtEntry;
+
+
+    //==============================================================================================
+    fn next(&mut self) -> Option<MadtEntry> {
+    //----------------------------------------------------------------------------------------------
+    // Parse the next entry. Iteration stops early at an entry overrunning the table.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: Some(...) -> the entry
+    //          None      -> every entry has been parsed
+    //==============================================================================================
+
+        let table = self.table;
+        let offset = self.offset;
+
+        if (offset + 2 > table.length()) {
+            return None;
+        }
+
+        let length = table.read_u8(offset + 1) as usize;
+        if (length < 2 || offset + length > table.length()) {
+            return None;
+        }
+        self.offset += length;
+
+        let kind = table.read_u8(offset);
+
+        Some(match kind {
+            ENTRY_LOCAL_APIC if (length >= 8) => MadtEntry::Processor {
+                processor_id: table.read_u8(offset + 2) as u32,
+                apic_id: table.read_u8(offset + 3) as u32,
+                enabled: table.read_u32(offset + 4) & PROCESSOR_ENABLED != 0,
+            },
+            ENTRY_LOCAL_X2APIC if (length >= 16) => MadtEntry::Processor {
+                processor_id: table.read_u32(offset + 12),
+                apic_id: table.read_u32(offset + 4),
+                enabled: table.read_u32(offset + 8) & PROCESSOR_ENABLED != 0,
+            },
+            ENTRY_IO_APIC if (length >= 12) => MadtEntry::IoApic {
+                id: table.read_u8(offset + 2),
+                address: PhysicalAddress::new(table.read_u32(offset + 4) as usize),
+                gsi_base: table.read_u32(offset + 8),
+            },
+            ENTRY_SOURCE_OVERRIDE if (length >= 10) => {
+                let flags = table.read_u16(offset + 8);
+
+                // Signalling that conforms to the bus is edge triggered and active high for ISA
+                MadtEntry::Override(InterruptSourceOverride {
+                    irq: table.read_u8(offset + 3),
+                    gsi: table.read_u32(offset + 4),
+                    trigger: if (flags & INTI_TRIGGER == INTI_LEVEL) {
+                        TriggerMode::Level
+                    }
+                    else {
+                        TriggerMode::Edge
+                    },
+                    polarity: if (flags & INTI_POLARITY == INTI_ACTIVE_LOW) {
+                        Polarity::ActiveLow
+                    }
+                    else {
+                        Polarity::ActiveHigh
+                    },
+                })
+            },
+            ENTRY_LOCAL_APIC_NMI if (length >= 6) => MadtEntry::LocalApicNmi {
+                processor_id: table.read_u8(offset + 2),
+                lint: table.read_u8(offset + 5),
+            },
+            ENTRY_LOCAL_APIC_ADDRESS if (length >= 12) => {
+                let address = table.read_u64(offset + 4) as usize;
+                MadtEntry::LocalApicAddress(PhysicalAddress::new(address))
+            },
+            _ => MadtEntry::Other(kind),
+        })
+    }
+}
@@ -0,0 +1,195 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/acpi: mcfg.rs                                                                           #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::paging::PhysicalAddress;
+use super::Table;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+const ENTRIES: usize = 44;                      // Past the header and 8 reserved bytes
+const ENTRY_SIZE: usize = 16;
+const ENTRY_ADDRESS: usize = 0;
+const ENTRY_SEGMENT: usize = 8;
+const ENTRY_START_BUS: usize = 10;
+const ENTRY_END_BUS: usize = 11;
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub struct Mcfg {
+//--------------------------------------------------------------------------------------------------
+// Table of the memory mapped configuration space windows of PCI Express.
+//==================================================================================================
+
+    table: Table,
+}
+
+
+#[derive(Clone, Copy, Debug)]
+//==================================================================================================
+pub struct McfgEntry {
+//--------------------------------------------------------------------------------------------------
+// Configuration space window of a range of buses in a PCI segment group.
+//==================================================================================================
+
+    pub address: PhysicalAddress,       // Window of bus 0, even if start_bus is higher
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,                    // Last bus decoded, inclusive
+}
+
+
+//==================================================================================================
+pub struct McfgEntryIter<'a> {
+//--------------------------------------------------------------------------------------------------
+// Iterator over the entries of the MCFG.
+//==================================================================================================
+
+    table: &'a Table,
+    offset: usize,                      // Offset of the next entry
+}
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl Mcfg {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn new(table: Table) -> Mcfg {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for Mcfg.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   table -> table signed "MCFG"
+    //
+    // RETURNS: the MCFG
+    //==============================================================================================
+
+        Mcfg { table: table }
+    }
+
+
+    //==============================================================================================
+    pub fn entries(&self) -> McfgEntryIter {
+    //----------------------------------------------------------------------------------------------
+    // Obtain an iterator over the table's entries.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the iterator
+    //==============================================================================================
+
+        McfgEntryIter { table: &self.table, offset: ENTRIES }
+    }
+}
+
+
+//==================================================================================================
+impl McfgEntry {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn function_address(&self, bus: u8, device: u8, function: u8) -> Option<PhysicalAddress> {
+    //----------------------------------------------------------------------------------------------
+    // Locate the 4 KiB configuration space of a PCI function in the window.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   bus      -> bus of the function
+    //          device   -> device on the bus, 0-31
+    //          function -> function of the device, 0-7
+    //
+    // RETURNS: Some(...) -> physical address of the function's configuration space
+    //          None      -> the window does not decode the bus
+    //==============================================================================================
+
+        if (bus < self.start_bus || bus > self.end_bus || device >= 32 || function >= 8) {
+            return None;
+        }
+
+        Some(self.address + ((bus as usize) << 20 | (device as usize) << 15
+                             | (function as usize) << 12))
+    }
+}
+
+
+//==================================================================================================
+impl<'a> Iterator for McfgEntryIter<'a> {
+//==================================================================================================
+
+
+    type Item = McfgEntry;
+
+
+    //==============================================================================================
+    fn next(&mut self) -> Option<McfgEntry> {
+    //----------------------------------------------------------------------------------------------
+    // Parse the next entry.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: Some(...) -> the entry
+    //          None      -> every entry has been parsed
+    //==============================================================================================
+
+        let offset = self.offset;
+
+        if (offset + ENTRY_SIZE > self.table.length()) {
+            return None;
+        }
+        self.offset += ENTRY_SIZE;
+
+        Some(McfgEntry {
+            address: PhysicalAddress::new(self.table.read_u64(offset + ENTRY_ADDRESS) as usize),
+            segment: self.table.read_u16(offset + ENTRY_SEGMENT),
+            start_bus: self.table.read_u8(offset + ENTRY_START_BUS),
+            end_bus: self.table.read_u8(offset + ENTRY_END_BUS),
+        })
+    }
+}
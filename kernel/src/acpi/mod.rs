@@ -0,0 +1,753 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/acpi: mod.rs                                                                            #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//************************************* CRATES & SUBMODULES ****************************************
+//##################################################################################################
+
+
+mod fadt;
+mod hpet;
+mod madt;
+mod mcfg;
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+pub use self::fadt::Fadt;
+pub use self::hpet::HpetDescription;
+pub use self::madt::{Madt, MadtEntry, MadtEntryIter};
+pub use self::mcfg::{Mcfg, McfgEntry, McfgEntryIter};
+use memory::{IoMapping, MemoryType, ioremap};
+use memory::paging::PhysicalAddress;
+use multiboot2::BootInformation;
+use core::{ptr, str};
+use spin::Once;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+const RSDP_SIGNATURE: &'static [u8; 8] = b"RSD PTR ";
+const RSDP_V1_SIZE: usize = 20;                 // Bytes covered by the original checksum
+const RSDP_V2_SIZE: usize = 36;                 // Bytes covered by the extended checksum
+const RSDP_REVISION: usize = 15;
+const RSDP_RSDT_ADDRESS: usize = 16;
+const RSDP_XSDT_ADDRESS: usize = 24;
+
+const HEADER_SIZE: usize = 36;                  // Header common to every system description table
+const HEADER_LENGTH: usize = 4;
+const HEADER_REVISION: usize = 8;
+
+const MULTIBOOT_TAG_END: u32 = 0;
+const MULTIBOOT_TAG_RSDP_V1: u32 = 14;          // Copy of an ACPI 1.0 RSDP
+const MULTIBOOT_TAG_RSDP_V2: u32 = 15;          // Copy of an ACPI 2.0 or later RSDP
+const MULTIBOOT_TAG_HEADER: usize = 8;          // Type and size precede each tag's contents
+const MULTIBOOT_FIXED_PART: usize = 8;          // Total size and reserved field precede the tags
+const MULTIBOOT_TAG_ALIGN: usize = 8;
+
+const EBDA_POINTER: usize = 0x40E;              // BIOS data area word holding the EBDA segment
+const EBDA_SEARCH_SIZE: usize = 0x400;          // The RSDP lies in the EBDA's first KiB or ...
+const BIOS_AREA_START: usize = 0xE_0000;        // ... in the BIOS read-only area below 1 MiB
+const BIOS_AREA_SIZE: usize = 0x2_0000;
+const RSDP_ALIGN: usize = 16;
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+struct Acpi {
+//--------------------------------------------------------------------------------------------------
+// Root of the ACPI tables, found by init.
+//==================================================================================================
+
+    revision: u8,                       // Revision of the RSDP, 0 for ACPI 1.0
+    root: PhysicalAddress,              // XSDT, or the RSDT if there is no XSDT
+    entry_size: usize,                  // Width of the root table's entries, 8 for the XSDT
+}
+
+
+//==================================================================================================
+pub struct Table {
+//--------------------------------------------------------------------------------------------------
+// System description table, mapped in full and checked against its checksum. Fields are read a
+// byte at a time, as ACPI packs them without regard for alignment.
+//==================================================================================================
+
+    mapping: IoMapping,
+}
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+static ACPI: Once<Acpi> = Once::new();
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl Table {
+//==================================================================================================
+
+
+    //==============================================================================================
+    fn map(address: PhysicalAddress) -> Option<Table> {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for Table. Maps the header to learn the table's length, then the whole
+    // table.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   address -> physical address of the table
+    //
+    // RETURNS: Some(...) -> the table
+    //          None      -> it could not be mapped, is shorter than its header, or fails its
+    //                       checksum
+    //==============================================================================================
+
+        let length = match ioremap(address, HEADER_SIZE, MemoryType::WriteBack) {
+            Some(header) => Table { mapping: header }.read_u32(HEADER_LENGTH) as usize,
+            None         => return None,
+        };
+
+        if (length < HEADER_SIZE) {
+            return None;
+        }
+
+        let table = match ioremap(address, length, MemoryType::WriteBack) {
+            Some(mapping) => Table { mapping: mapping },
+            None          => return None,
+        };
+
+        if (checksum((0 .. length).map(|offset| table.read_u8(offset))) != 0) {
+            return None;
+        }
+
+        Some(table)
+    }
+
+
+    //==============================================================================================
+    fn map_header(address: PhysicalAddress) -> Option<Table> {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for Table, mapping only the header, so that the signature can be checked
+    // before the table is mapped in full.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   address -> physical address of the table
+    //
+    // RETURNS: Some(...) -> the header, unchecked
+    //          None      -> it could not be mapped
+    //==============================================================================================
+
+        ioremap(address, HEADER_SIZE, MemoryType::WriteBack)
+            .map(|mapping| Table { mapping: mapping })
+    }
+
+
+    //==============================================================================================
+    pub fn signature(&self) -> [u8; 4] {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the four characters identifying the kind of table.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the signature, such as "APIC" for the MADT
+    //==============================================================================================
+
+        [self.read_u8(0), self.read_u8(1), self.read_u8(2), self.read_u8(3)]
+    }
+
+
+    //==============================================================================================
+    pub fn length(&self) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the size of the table, header included.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the size in bytes
+    //==============================================================================================
+
+        self.read_u32(HEADER_LENGTH) as usize
+    }
+
+
+    //==============================================================================================
+    pub fn revision(&self) -> u8 {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the revision of the table's layout.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the revision
+    //==============================================================================================
+
+        self.read_u8(HEADER_REVISION)
+    }
+
+
+    //==============================================================================================
+    pub fn physical_address(&self) -> PhysicalAddress {
+    //----------------------------------------------------------------------------------------------
+    // Obtain where the table lies in physical memory.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the physical address of its header
+    //==============================================================================================
+
+        self.mapping.physical_address()
+    }
+
+
+    //==============================================================================================
+    pub fn read_u8(&self, offset: usize) -> u8 {
+    //----------------------------------------------------------------------------------------------
+    // Read a byte of the table.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   offset -> offset from the start of the header
+    //
+    // RETURNS: the byte
+    //==============================================================================================
+
+        self.mapping.read::<u8>(offset)
+    }
+
+
+    //==============================================================================================
+    pub fn read_u16(&self, offset: usize) -> u16 {
+    //----------------------------------------------------------------------------------------------
+    // Read a little-endian 16-bit field of the table.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   offset -> offset from the start of the header
+    //
+    // RETURNS: the field
+    //==============================================================================================
+
+        self.read_u8(offset) as u16 | (self.read_u8(offset + 1) as u16) << 8
+    }
+
+
+    //==============================================================================================
+    pub fn read_u32(&self, offset: usize) -> u32 {
+    //----------------------------------------------------------------------------------------------
+    // Read a little-endian 32-bit field of the table.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   offset -> offset from the start of the header
+    //
+    // RETURNS: the field
+    //==============================================================================================
+
+        self.read_u16(offset) as u32 | (self.read_u16(offset + 2) as u32) << 16
+    }
+
+
+    //==============================================================================================
+    pub fn read_u64(&self, offset: usize) -> u64 {
+    //----------------------------------------------------------------------------------------------
+    // Read a little-endian 64-bit field of the table.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   offset -> offset from the start of the header
+    //
+    // RETURNS: the field
+    //==============================================================================================
+
+        self.read_u32(offset) as u64 | (self.read_u32(offset + 4) as u64) << 32
+    }
+}
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn init(boot_info: &BootInformation) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Find the RSDP, preferring the copy the bootloader placed in the multiboot information and
+// otherwise scanning the EBDA and BIOS area for it, then check the root table it points to. Must
+// be called once memory is initialized, as tables are mapped with ioremap.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   boot_info -> multiboot information structure handed to the kernel
+//
+// RETURNS: true  -> ACPI tables may be looked up
+//          false -> no valid RSDP or root table was found
+//==================================================================================================
+
+    if (ACPI.try().is_some()) {
+        return true;
+    }
+
+    let rsdp = match rsdp_from_multiboot(boot_info).or_else(rsdp_from_bios) {
+        Some(rsdp) => rsdp,
+        None       => return false,
+    };
+
+    let revision = rsdp[RSDP_REVISION];
+    let xsdt = read_u64(&rsdp, RSDP_XSDT_ADDRESS);
+
+    let (root, entry_size) = if (revision >= 2 && xsdt != 0) {
+        (PhysicalAddress::new(xsdt as usize), 8)
+    }
+    else {
+        (PhysicalAddress::new(read_u64(&rsdp, RSDP_RSDT_ADDRESS) as u32 as usize), 4)
+    };
+
+    if (Table::map(root).is_none()) {
+        return false;
+    }
+
+    ACPI.call_once(|| Acpi { revision: revision, root: root, entry_size: entry_size });
+    true
+}
+
+
+//==================================================================================================
+pub fn find_table(signature: &[u8; 4]) -> Option<Table> {
+//--------------------------------------------------------------------------------------------------
+// Look up a table listed in the root table by its signature. Where several share a signature,
+// the first that passes its checksum is returned.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   signature -> four characters identifying the table, such as b"APIC"
+//
+// RETURNS: Some(...) -> the table, mapped
+//          None      -> ACPI is not initialized or lists no valid such table
+//==================================================================================================
+
+    let acpi = match ACPI.try() {
+        Some(acpi) => acpi,
+        None       => return None,
+    };
+
+    let root = match Table::map(acpi.root) {
+        Some(root) => root,
+        None       => return None,
+    };
+
+    (0 .. (root.length() - HEADER_SIZE) / acpi.entry_size)
+        .map(|index| root_entry(&root, acpi.entry_size, index))
+        .filter(|address| {
+            Table::map_header(*address).map_or(false, |header| &header.signature() == signature)
+        })
+        .filter_map(Table::map)
+        .next()
+}
+
+
+//==================================================================================================
+pub fn madt() -> Option<Madt> {
+//--------------------------------------------------------------------------------------------------
+// Look up the multiple APIC description table.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: Some(...) -> the MADT
+//          None      -> there is none
+//==================================================================================================
+
+    find_table(b"APIC").map(Madt::new)
+}
+
+
+//==================================================================================================
+pub fn fadt() -> Option<Fadt> {
+//--------------------------------------------------------------------------------------------------
+// Look up and parse the fixed ACPI description table.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: Some(...) -> the FADT's fields
+//          None      -> there is none
+//==================================================================================================
+
+    find_table(b"FACP").map(|table| Fadt::parse(&table))
+}
+
+
+//==================================================================================================
+pub fn hpet() -> Option<HpetDescription> {
+//--------------------------------------------------------------------------------------------------
+// Look up and parse the HPET description table.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: Some(...) -> the description of the first HPET
+//          None      -> there is none, or it is not memory mapped
+//==================================================================================================
+
+    find_table(b"HPET").and_then(|table| HpetDescription::parse(&table))
+}
+
+
+//==================================================================================================
+pub fn mcfg() -> Option<Mcfg> {
+//--------------------------------------------------------------------------------------------------
+// Look up the table of PCI Express configuration space windows.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: Some(...) -> the MCFG
+//          None      -> there is none
+//==================================================================================================
+
+    find_table(b"MCFG").map(Mcfg::new)
+}
+
+
+//==================================================================================================
+pub fn print_tables() {
+//--------------------------------------------------------------------------------------------------
+// Print every table listed in the root table.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let acpi = match ACPI.try() {
+        Some(acpi) => acpi,
+        None       => return,
+    };
+
+    let root = match Table::map(acpi.root) {
+        Some(root) => root,
+        None       => return,
+    };
+
+    println!("ACPI tables (RSDP revision {}):", acpi.revision);
+
+    for index in 0 .. (root.length() - HEADER_SIZE) / acpi.entry_size {
+        let address = root_entry(&root, acpi.entry_size, index);
+
+        if let Some(header) = Table::map_header(address) {
+            let signature = header.signature();
+            println!("    {} at {:#x}, {} bytes", str::from_utf8(&signature).unwrap_or("????"),
+                     address.as_usize(), header.length());
+        }
+    }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn root_entry(root: &Table, entry_size: usize, index: usize) -> PhysicalAddress {
+//--------------------------------------------------------------------------------------------------
+// Read an entry of the RSDT or XSDT.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   root       -> the root table
+//          entry_size -> width of its entries
+//          index      -> entry to read
+//
+// RETURNS: the physical address of the table the entry points to
+//==================================================================================================
+
+    let offset = HEADER_SIZE + index * entry_size;
+
+    if (entry_size == 8) {
+        PhysicalAddress::new(root.read_u64(offset) as usize)
+    }
+    else {
+        PhysicalAddress::new(root.read_u32(offset) as usize)
+    }
+}
+
+
+//==================================================================================================
+fn rsdp_from_multiboot(boot_info: &BootInformation) -> Option<[u8; RSDP_V2_SIZE]> {
+//--------------------------------------------------------------------------------------------------
+// Walk the multiboot tags for the bootloader's copy of the RSDP, preferring an ACPI 2.0 one.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   boot_info -> multiboot information structure handed to the kernel
+//
+// RETURNS: Some(...) -> the RSDP, zero-padded if it is an ACPI 1.0 one
+//          None      -> there is no valid RSDP tag
+//==================================================================================================
+
+    let mut found = None;
+    let mut address = boot_info.start_address() + MULTIBOOT_FIXED_PART;
+
+    while (address + MULTIBOOT_TAG_HEADER <= boot_info.end_address()) {
+        let (kind, size) = unsafe {
+            (ptr::read(address as *const u32), ptr::read((address + 4) as *const u32) as usize)
+        };
+
+        if (kind == MULTIBOOT_TAG_END || size < MULTIBOOT_TAG_HEADER) {
+            break;
+        }
+
+        if (kind == MULTIBOOT_TAG_RSDP_V1 || kind == MULTIBOOT_TAG_RSDP_V2) {
+            let mut rsdp = [0; RSDP_V2_SIZE];
+            let length = (size - MULTIBOOT_TAG_HEADER).min(RSDP_V2_SIZE);
+
+            for offset in 0 .. length {
+                rsdp[offset] = unsafe {
+                    ptr::read((address + MULTIBOOT_TAG_HEADER + offset) as *const u8)
+                };
+            }
+
+            if (is_valid_rsdp(&rsdp)) {
+                found = Some(rsdp);
+                if (kind == MULTIBOOT_TAG_RSDP_V2) {
+                    break;
+                }
+            }
+        }
+
+        address += (size + MULTIBOOT_TAG_ALIGN - 1) & !(MULTIBOOT_TAG_ALIGN - 1);
+    }
+
+    found
+}
+
+
+//==================================================================================================
+fn rsdp_from_bios() -> Option<[u8; RSDP_V2_SIZE]> {
+//--------------------------------------------------------------------------------------------------
+// Scan the first KiB of the EBDA, then the BIOS area from 0xE0000 to 0xFFFFF, for the RSDP
+// signature on a 16-byte boundary.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: Some(...) -> the first valid RSDP found
+//          None      -> there is none
+//==================================================================================================
+
+    let ebda = ioremap(PhysicalAddress::new(EBDA_POINTER), 2, MemoryType::WriteBack)
+        .map(|bda| (bda.read::<u8>(0) as usize | (bda.read::<u8>(1) as usize) << 8) << 4);
+
+    let ebda_rsdp = match ebda {
+        Some(ebda) if (ebda != 0) => scan_for_rsdp(PhysicalAddress::new(ebda), EBDA_SEARCH_SIZE),
+        _                         => None,
+    };
+
+    ebda_rsdp.or_else(|| scan_for_rsdp(PhysicalAddress::new(BIOS_AREA_START), BIOS_AREA_SIZE))
+}
+
+
+//==================================================================================================
+fn scan_for_rsdp(start: PhysicalAddress, size: usize) -> Option<[u8; RSDP_V2_SIZE]> {
+//--------------------------------------------------------------------------------------------------
+// Search a range of physical memory for a valid RSDP on a 16-byte boundary.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   start -> first physical address of the range, 16-byte aligned
+//          size  -> length of the range in bytes
+//
+// RETURNS: Some(...) -> the first valid RSDP found, zero-padded if it is an ACPI 1.0 one
+//          None      -> there is none in the range
+//==================================================================================================
+
+    let area = match ioremap(start, size, MemoryType::WriteBack) {
+        Some(area) => area,
+        None       => return None,
+    };
+
+    for candidate in (0 .. size - RSDP_V1_SIZE + 1).filter(|offset| offset % RSDP_ALIGN == 0) {
+        let matches = (0 .. RSDP_SIGNATURE.len())
+            .all(|i| area.read::<u8>(candidate + i) == RSDP_SIGNATURE[i]);
+        if (!matches) {
+            continue;
+        }
+
+        let mut rsdp = [0; RSDP_V2_SIZE];
+        for offset in 0 .. RSDP_V2_SIZE.min(size - candidate) {
+            rsdp[offset] = area.read::<u8>(candidate + offset);
+        }
+
+        if (is_valid_rsdp(&rsdp)) {
+            return Some(rsdp);
+        }
+    }
+
+    None
+}
+
+
+//==================================================================================================
+fn is_valid_rsdp(rsdp: &[u8; RSDP_V2_SIZE]) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check an RSDP's signature and checksum, and for ACPI 2.0 or later its extended checksum.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   rsdp -> the RSDP, zero-padded if it is an ACPI 1.0 one
+//
+// RETURNS: true if it is valid, false otherwise
+//==================================================================================================
+
+    &rsdp[.. RSDP_SIGNATURE.len()] == &RSDP_SIGNATURE[..]
+        && checksum(rsdp[.. RSDP_V1_SIZE].iter().cloned()) == 0
+        && (rsdp[RSDP_REVISION] < 2 || checksum(rsdp.iter().cloned()) == 0)
+}
+
+
+//==================================================================================================
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Read a little-endian 64-bit field from a byte array.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   bytes  -> the array
+//          offset -> offset of the field
+//
+// RETURNS: the field
+//==================================================================================================
+
+    (0 .. 8).fold(0, |value, i| value | (bytes[offset + i] as u64) << (8 * i))
+}
+
+
+//==================================================================================================
+fn checksum<I: Iterator<Item = u8>>(bytes: I) -> u8 {
+//--------------------------------------------------------------------------------------------------
+// Sum bytes modulo 256, as ACPI checksums do. A structure is valid if its bytes sum to zero.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   bytes -> the bytes to sum
+//
+// RETURNS: the sum
+//==================================================================================================
+
+    bytes.fold(0, |sum: u8, byte| sum.wrapping_add(byte))
+}
+
+
+//##################################################################################################
+//******************************************** TESTS ***********************************************
+//##################################################################################################
+
+
+#[cfg(test)]
+mod tests {
+
+
+    use super::*;
+
+
+    //==============================================================================================
+    fn rsdp(revision: u8) -> [u8; RSDP_V2_SIZE] {
+    //----------------------------------------------------------------------------------------------
+    // Build an RSDP with valid checksums, and for revisions below 2 no extended part.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   revision -> ACPI revision to claim
+    //
+    // RETURNS: the RSDP, zero-padded to the ACPI 2.0 size
+    //==============================================================================================
+
+        let mut rsdp = [0; RSDP_V2_SIZE];
+        rsdp[.. RSDP_SIGNATURE.len()].copy_from_slice(&RSDP_SIGNATURE[..]);
+        rsdp[RSDP_REVISION] = revision;
+        rsdp[RSDP_RSDT_ADDRESS] = 0xE0;
+
+        rsdp[8] = 0u8.wrapping_sub(checksum(rsdp[.. RSDP_V1_SIZE].iter().cloned()));
+        if (revision >= 2) {
+            rsdp[RSDP_XSDT_ADDRESS] = 0xF0;
+            rsdp[32] = 0u8.wrapping_sub(checksum(rsdp.iter().cloned()));
+        }
+        rsdp
+    }
+
+
+    #[test]
+    //==============================================================================================
+    fn checksum_sums_modulo_256() {
+    //----------------------------------------------------------------------------------------------
+    // Bytes wrap around rather than overflow, and no bytes at all sum to zero.
+    //==============================================================================================
+
+        assert_eq!(checksum([0u8; 0].iter().cloned()), 0);
+        assert_eq!(checksum([0xFF, 0x01].iter().cloned()), 0);
+        assert_eq!(checksum([0x80, 0x80, 0x01].iter().cloned()), 1);
+        assert_eq!(checksum([0x12, 0x34].iter().cloned()), 0x46);
+    }
+
+
+    #[test]
+    //==============================================================================================
+    fn rsdps_with_valid_checksums_are_accepted() {
+    //----------------------------------------------------------------------------------------------
+    // Both an ACPI 1.0 RSDP and a 2.0 one with its extended part pass.
+    //==============================================================================================
+
+        assert!(is_valid_rsdp(&rsdp(0)));
+        assert!(is_valid_rsdp(&rsdp(2)));
+    }
+
+
+    #[test]
+    //==============================================================================================
+    fn rsdp_signature_must_match() {
+    //----------------------------------------------------------------------------------------------
+    // A structure with valid checksums but the wrong signature is not an RSDP.
+    //==============================================================================================
+
+        let mut bad = rsdp(0);
+        bad[0] = b'X';
+        bad[8] = bad[8].wrapping_add(b'R').wrapping_sub(b'X');
+        assert_eq!(checksum(bad[.. RSDP_V1_SIZE].iter().cloned()), 0);
+        assert!(!is_valid_rsdp(&bad));
+    }
+
+
+    #[test]
+    //==============================================================================================
+    fn rsdp_checksums_must_sum_to_zero() {
+    //----------------------------------------------------------------------------------------------
+    // A corrupt byte in the original part fails any revision; one in the extended part fails only
+    // revision 2 and later, as earlier RSDPs have no extended part to check.
+    //==============================================================================================
+
+        let mut bad = rsdp(0);
+        bad[RSDP_RSDT_ADDRESS] ^= 1;
+        assert!(!is_valid_rsdp(&bad));
+
+        let mut bad = rsdp(2);
+        bad[RSDP_XSDT_ADDRESS] ^= 1;
+        assert!(!is_valid_rsdp(&bad));
+
+        let mut ignored = rsdp(1);
+        ignored[RSDP_XSDT_ADDRESS] = 0xF0;
+        assert!(is_valid_rsdp(&ignored));
+    }
+}
@@ -9,17 +9,23 @@ BITS 32
 	%define STACK_SIZE 4096 * 4
 	%define PAGE_SIZE 4096
 
+	%define KERNEL_OFFSET 0xFFFF800000000000	; Must match linker.ld
+	%define KERNEL_PML4_INDEX 256			; Page map entry covering KERNEL_OFFSET
+
 extern long_mode_start
 	
 global _start
+global stack_top
 
 	
 ;;; ;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
-;;; text is for runnable code
+;;; boot is for code run before paging, linked at its physical address. Every
+;;; other symbol lives in the higher half, so its physical address is found by
+;;; subtracting KERNEL_OFFSET
 ;;; ;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
-section .text
+section .boot progbits alloc exec nowrite
 _start:
-	mov esp, stack_top	; Set up stack
+	mov esp, stack_top - KERNEL_OFFSET	; Set up stack
 	mov edi, ebx		; Move multiboot info addr into first param reg
 
 	;; confirm in post-multiboot env, long mode supported
@@ -70,7 +76,7 @@ enableSSE:
 ;;; enable paging
 enablePaging:
 	;; Make page table cpu accessable
-	mov eax, page_map	; CPU reads page map out of cr3
+	mov eax, page_map - KERNEL_OFFSET	; CPU reads page map out of cr3
 	mov cr3, eax
 
 	;; Enable physical address extension (PAE)
@@ -97,19 +103,22 @@ enablePaging:
 initPageTables:
 	
 	;; Recursively map the page map 
-	mov eax, page_map
+	mov eax, page_map - KERNEL_OFFSET
 	or eax, 0b11
-	mov [page_map + 511 * 8], eax
+	mov [page_map - KERNEL_OFFSET + 511 * 8], eax
 
-	mov eax, pointer_table
+	mov eax, pointer_table - KERNEL_OFFSET
 	or eax, 0b11		    ; set 'entry present' and 'writable' bits
-	mov [page_map], eax	    ; link pointer table as first entry in page 
-	                            ; map 
+	mov [page_map - KERNEL_OFFSET], eax	    ; link pointer table as first
+						    ; entry in page map
+
+	;; Map the same first GiB again at KERNEL_OFFSET, where the kernel runs
+	mov [page_map - KERNEL_OFFSET + KERNEL_PML4_INDEX * 8], eax
 
-	mov eax, page_directory
+	mov eax, page_directory - KERNEL_OFFSET
 	or eax, 0b11		    ; set 'entry present' and 'writable' bits
-	mov [pointer_table], eax    ; link page directory as first entry in
-				    ; pointer table
+	mov [pointer_table - KERNEL_OFFSET], eax    ; link page directory as first
+						    ; entry in pointer table
 
 	mov ecx, 0
 	.entryLoop:
@@ -117,7 +126,7 @@ initPageTables:
 		mul ecx
 		or eax, 0b10000011 ; set 'present', 'writable', and 'huge' bits
 		; entries are 8 bytes, advance appropriately + copy entry there
-		mov [page_directory + ecx * 8], eax
+		mov [page_directory - KERNEL_OFFSET + ecx * 8], eax
 
 		inc ecx
 		cmp ecx, 512
@@ -227,9 +236,9 @@ stack_top:
 
 	
 ;;; ;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
-;;; read-only data
+;;; read-only data, kept with the boot code as lgdt runs before paging
 ;;; ;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
-section .rodata
+section .boot progbits alloc exec nowrite
 gdt64:
 	dq 0			; GDT must always start with a 0 entry 
 .code: equ $ - gdt64		; offset of code segment in GDT
@@ -0,0 +1,340 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel: cpu.rs                                                                                 #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+const BASIC_LEAF_BASE: u32 = 0x0;               // Reports the highest basic leaf
+const BASIC_FEATURE_LEAF: u32 = 0x1;
+const STRUCTURED_FEATURE_LEAF: u32 = 0x7;
+const EXTENDED_LEAF_BASE: u32 = 0x8000_0000;    // Reports the highest extended leaf
+const EXTENDED_FEATURE_LEAF: u32 = 0x8000_0001;
+const POWER_MANAGEMENT_LEAF: u32 = 0x8000_0007;
+
+const EDX_APIC: u32 = 1 << 9;                  // Local APIC is present
+const EDX_PAT: u32 = 1 << 16;                  // Page attribute table selects memory types
+const ECX_PCID: u32 = 1 << 17;                 // CR3 may tag TLB entries with a context ID
+const ECX_X2APIC: u32 = 1 << 21;               // Local APIC may be driven through MSRs
+const LEAF7_EBX_SMEP: u32 = 1 << 7;            // Supervisor mode execution prevention
+const LEAF7_EBX_INVPCID: u32 = 1 << 10;        // invpcid instruction is available
+const LEAF7_EBX_SMAP: u32 = 1 << 20;           // Supervisor mode access prevention
+const LEAF7_ECX_UMIP: u32 = 1 << 2;            // User mode instruction prevention
+const EXT_EDX_PAGE_1GB: u32 = 1 << 26;         // 1 GiB pages may be mapped in pointer tables
+const PM_EDX_INVARIANT_TSC: u32 = 1 << 8;      // TSC ticks at a constant rate in every state
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Clone, Copy, Debug)]
+//==================================================================================================
+pub struct CpuidResult {
+//--------------------------------------------------------------------------------------------------
+// Registers returned by the cpuid instruction.
+//==================================================================================================
+
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+//--------------------------------------------------------------------------------------------------
+// Execute the cpuid instruction. Leaves above the highest supported leaf return meaningless data,
+// so callers must check the maximum leaf first.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   leaf    -> value of EAX, selecting the information returned
+//          subleaf -> value of ECX, selecting a sub-leaf where the leaf has any
+//
+// RETURNS: the values cpuid left in EAX, EBX, ECX, and EDX
+//==================================================================================================
+
+    let (eax, ebx, ecx, edx): (u32, u32, u32, u32);
+
+    unsafe {
+        asm!("cpuid"
+             : "={eax}" (eax), "={ebx}" (ebx), "={ecx}" (ecx), "={edx}" (edx)
+             : "{eax}" (leaf), "{ecx}" (subleaf));
+    }
+
+    CpuidResult { eax: eax, ebx: ebx, ecx: ecx, edx: edx }
+}
+
+
+//==================================================================================================
+pub fn max_extended_leaf() -> u32 {
+//--------------------------------------------------------------------------------------------------
+// Obtain the highest extended cpuid leaf supported by the processor.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: the highest supported leaf at or above 0x80000000
+//==================================================================================================
+
+    cpuid(EXTENDED_LEAF_BASE, 0).eax
+}
+
+
+//==================================================================================================
+pub fn supports_1g_pages() -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether pointer table entries may map 1 GiB pages.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: true  -> 1 GiB pages are supported
+//          false -> only 4 KiB and 2 MiB pages may be mapped
+//==================================================================================================
+
+    max_extended_leaf() >= EXTENDED_FEATURE_LEAF
+        && cpuid(EXTENDED_FEATURE_LEAF, 0).edx & EXT_EDX_PAGE_1GB != 0
+}
+
+
+//==================================================================================================
+pub fn supports_pat() -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether the page attribute table is available to select memory types for pages.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: true  -> the PAT MSR exists and the PAT bit of page entries is honoured
+//          false -> only the WRITETHROUGH and NO_CACHE bits select memory types
+//==================================================================================================
+
+    cpuid(BASIC_FEATURE_LEAF, 0).edx & EDX_PAT != 0
+}
+
+
+//==================================================================================================
+pub fn supports_apic() -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether the processor has a local APIC.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: true  -> a local APIC is present
+//          false -> only the 8259 PICs can deliver IRQs
+//==================================================================================================
+
+    cpuid(BASIC_FEATURE_LEAF, 0).edx & EDX_APIC != 0
+}
+
+
+//==================================================================================================
+pub fn supports_x2apic() -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether the local APIC can be put in x2APIC mode.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: true  -> the local APIC may be driven through MSRs
+//          false -> the local APIC is only reachable through its memory-mapped registers
+//==================================================================================================
+
+    cpuid(BASIC_FEATURE_LEAF, 0).ecx & ECX_X2APIC != 0
+}
+
+
+//==================================================================================================
+pub fn supports_pcid() -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether TLB entries may be tagged with process-context identifiers.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: true  -> CR4.PCIDE may be set
+//          false -> every CR3 write flushes the TLB
+//==================================================================================================
+
+    cpuid(BASIC_FEATURE_LEAF, 0).ecx & ECX_PCID != 0
+}
+
+
+//==================================================================================================
+pub fn supports_invpcid() -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether the invpcid instruction is available.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: true  -> TLB entries may be invalidated by PCID
+//          false -> invpcid raises an invalid opcode exception
+//==================================================================================================
+
+    structured_features().ebx & LEAF7_EBX_INVPCID != 0
+}
+
+
+//==================================================================================================
+pub fn supports_smep() -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether the kernel can be kept from executing user pages.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: true  -> CR4.SMEP may be set
+//          false -> the kernel can execute any page it can read
+//==================================================================================================
+
+    structured_features().ebx & LEAF7_EBX_SMEP != 0
+}
+
+
+//==================================================================================================
+pub fn supports_smap() -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether the kernel can be kept from accessing user pages outside of stac/clac.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: true  -> CR4.SMAP may be set, and stac and clac exist
+//          false -> the kernel can access any user page
+//==================================================================================================
+
+    structured_features().ebx & LEAF7_EBX_SMAP != 0
+}
+
+
+//==================================================================================================
+pub fn supports_umip() -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether user code can be kept from reading descriptor table registers.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: true  -> CR4.UMIP may be set
+//          false -> sgdt, sidt, sldt, smsw and str work in user mode
+//==================================================================================================
+
+    structured_features().ecx & LEAF7_ECX_UMIP != 0
+}
+
+
+//==================================================================================================
+pub fn supports_invariant_tsc() -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether the time stamp counter ticks at a constant rate, whatever the frequency or power
+// state of the processor.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: true  -> the TSC may be used to measure time once calibrated
+//          false -> the TSC rate may change while the kernel runs
+//==================================================================================================
+
+    max_extended_leaf() >= POWER_MANAGEMENT_LEAF
+        && cpuid(POWER_MANAGEMENT_LEAF, 0).edx & PM_EDX_INVARIANT_TSC != 0
+}
+
+
+//==================================================================================================
+pub fn rdtsc() -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Read the time stamp counter.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: cycles counted since the processor was reset
+//==================================================================================================
+
+    let (low, high): (u32, u32);
+    unsafe { asm!("rdtsc" : "={eax}" (low), "={edx}" (high) ::: "volatile"); }
+
+    (high as u64) << 32 | low as u64
+}
+
+
+//==================================================================================================
+pub fn cr4() -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Read control register 4.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: the value of CR4
+//==================================================================================================
+
+    let value: u64;
+    unsafe { asm!("mov $0, cr4" : "=r" (value) ::: "intel"); }
+    value
+}
+
+
+//==================================================================================================
+pub unsafe fn cr4_write(value: u64) {
+//--------------------------------------------------------------------------------------------------
+// Write control register 4. Unsafe, as it changes how paging and protection behave.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   value -> new value of CR4
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    asm!("mov cr4, $0" :: "r" (value) : "memory" : "intel", "volatile");
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn structured_features() -> CpuidResult {
+//--------------------------------------------------------------------------------------------------
+// Obtain the structured extended feature flags, which older processors do not report.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: leaf 7, sub-leaf 0, or all zeroes if the leaf is unsupported
+//==================================================================================================
+
+    if (cpuid(BASIC_LEAF_BASE, 0).eax >= STRUCTURED_FEATURE_LEAF) {
+        cpuid(STRUCTURED_FEATURE_LEAF, 0)
+    }
+    else {
+        CpuidResult { eax: 0, ebx: 0, ecx: 0, edx: 0 }
+    }
+}
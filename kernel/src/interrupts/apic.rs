@@ -0,0 +1,318 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/interrupts: apic.rs                                                                     #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::{IoMapping, MemoryType, ioremap};
+use memory::paging::PhysicalAddress;
+use x86::shared::msr::{rdmsr, wrmsr};
+use spin::Once;
+use cpu;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const SPURIOUS_VECTOR: u8 = 0xFF;           // Raised for an interrupt withdrawn before delivery
+
+// Register offsets in the xAPIC window. In x2APIC mode each is an MSR at X2APIC_MSR_BASE plus the
+// offset divided by 16.
+pub const ID: u32 = 0x020;
+pub const VERSION: u32 = 0x030;
+pub const TASK_PRIORITY: u32 = 0x080;
+pub const END_OF_INTERRUPT: u32 = 0x0B0;
+pub const SPURIOUS_INTERRUPT: u32 = 0x0F0;
+pub const ERROR_STATUS: u32 = 0x280;
+pub const LVT_TIMER: u32 = 0x320;
+pub const LVT_LINT0: u32 = 0x350;
+pub const LVT_LINT1: u32 = 0x360;
+pub const LVT_ERROR: u32 = 0x370;
+pub const TIMER_INITIAL_COUNT: u32 = 0x380;
+pub const TIMER_CURRENT_COUNT: u32 = 0x390;
+pub const TIMER_DIVIDE: u32 = 0x3E0;
+
+pub const LVT_MASKED: u32 = 1 << 16;            // Local interrupt is not delivered
+pub const LVT_TIMER_PERIODIC: u32 = 1 << 17;    // Timer reloads its initial count on expiry
+pub const TIMER_DIVIDE_16: u32 = 0b0011;        // Timer counts once every 16 bus clocks
+const LVT_NMI: u32 = 0b100 << 8;                // Deliver the local interrupt as an NMI
+const LVT_EXTINT: u32 = 0b111 << 8;             // Pass the 8259 PIC's interrupts straight through
+const SVR_ENABLE: u32 = 1 << 8;                 // Software enable of the local APIC
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_X2APIC: u64 = 1 << 10;          // Registers are MSRs rather than memory
+const APIC_BASE_ENABLE: u64 = 1 << 11;          // Global enable of the local APIC
+const APIC_BASE_ADDRESS: u64 = 0x000F_FFFF_FFFF_F000;
+const X2APIC_MSR_BASE: u32 = 0x800;
+const XAPIC_WINDOW_SIZE: usize = 0x400;
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+enum Registers {
+//--------------------------------------------------------------------------------------------------
+// Way of reaching the local APIC's registers.
+//==================================================================================================
+
+    XApic(IoMapping),                   // Uncached window at the APIC base address
+    X2Apic,                             // MSRs, which need no mapping
+}
+
+
+//==================================================================================================
+pub struct LocalApic {
+//--------------------------------------------------------------------------------------------------
+// Local APIC of the processor, in whichever mode it was brought up in.
+//==================================================================================================
+
+    registers: Registers,
+}
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+static LOCAL_APIC: Once<LocalApic> = Once::new();
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl LocalApic {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn read(&self, register: u32) -> u32 {
+    //----------------------------------------------------------------------------------------------
+    // Read a register.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   register -> xAPIC offset of the register
+    //
+    // RETURNS: value of the register
+    //==============================================================================================
+
+        match self.registers {
+            Registers::XApic(ref window) => window.read::<u32>(register as usize),
+            Registers::X2Apic            => unsafe { rdmsr(x2apic_msr(register)) as u32 },
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn write(&self, register: u32, value: u32) {
+    //----------------------------------------------------------------------------------------------
+    // Write a register.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   register -> xAPIC offset of the register
+    //          value    -> value to write
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        match self.registers {
+            Registers::XApic(ref window) => window.write::<u32>(register as usize, value),
+            Registers::X2Apic            => unsafe { wrmsr(x2apic_msr(register), value as u64) },
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn id(&self) -> u32 {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the APIC ID of the processor, as IO APIC redirection entries address it.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the APIC ID
+    //==============================================================================================
+
+        match self.registers {
+            Registers::XApic(_) => self.read(ID) >> 24,
+            Registers::X2Apic   => self.read(ID),
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn is_x2apic(&self) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check which mode the local APIC runs in.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: true  -> x2APIC mode, registers are MSRs
+    //          false -> xAPIC mode, registers are memory-mapped
+    //==============================================================================================
+
+        match self.registers {
+            Registers::XApic(_) => false,
+            Registers::X2Apic   => true,
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn end_of_interrupt(&self) {
+    //----------------------------------------------------------------------------------------------
+    // Tell the local APIC the interrupt in service has been handled.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        self.write(END_OF_INTERRUPT, 0);
+    }
+
+
+    //==============================================================================================
+    pub fn pass_through_pic(&self) {
+    //----------------------------------------------------------------------------------------------
+    // Unmask LINT0 as ExtINT, so the 8259 PICs keep delivering IRQs through the enabled local
+    // APIC. For when the IO APIC cannot take over from them.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        self.write(LVT_LINT0, LVT_EXTINT);
+    }
+}
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn init() -> Option<&'static LocalApic> {
+//--------------------------------------------------------------------------------------------------
+// Bring up the local APIC, in x2APIC mode where supported and otherwise through its register
+// window, mapped uncached. Every local interrupt is masked except LINT1, which carries NMIs.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: Some(...) -> the local APIC
+//          None      -> the processor has no local APIC, or its window could not be mapped
+//==================================================================================================
+
+    if (!cpu::supports_apic()) {
+        return None;
+    }
+
+    let base = unsafe { rdmsr(IA32_APIC_BASE) };
+
+    let registers = if (cpu::supports_x2apic()) {
+        // xAPIC mode must be entered before x2APIC mode
+        unsafe {
+            wrmsr(IA32_APIC_BASE, base | APIC_BASE_ENABLE);
+            wrmsr(IA32_APIC_BASE, base | APIC_BASE_ENABLE | APIC_BASE_X2APIC);
+        }
+        Registers::X2Apic
+    }
+    else {
+        unsafe { wrmsr(IA32_APIC_BASE, base | APIC_BASE_ENABLE); }
+
+        let address = PhysicalAddress::new((base & APIC_BASE_ADDRESS) as usize);
+        match ioremap(address, XAPIC_WINDOW_SIZE, MemoryType::Uncacheable) {
+            Some(window) => Registers::XApic(window),
+            None         => return None,
+        }
+    };
+
+    let apic = LOCAL_APIC.call_once(|| LocalApic { registers: registers });
+
+    apic.write(TASK_PRIORITY, 0);
+    apic.write(LVT_TIMER, LVT_MASKED);
+    apic.write(LVT_LINT0, LVT_MASKED);
+    apic.write(LVT_LINT1, LVT_NMI);
+    apic.write(LVT_ERROR, LVT_MASKED);
+
+    // The error status register latches on write, so it takes two writes to clear
+    apic.write(ERROR_STATUS, 0);
+    apic.write(ERROR_STATUS, 0);
+
+    apic.write(SPURIOUS_INTERRUPT, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+    apic.end_of_interrupt();
+
+    Some(apic)
+}
+
+
+//==================================================================================================
+pub fn local_apic() -> Option<&'static LocalApic> {
+//--------------------------------------------------------------------------------------------------
+// Obtain the local APIC, once init has brought it up.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: Some(...) -> the local APIC
+//          None      -> the local APIC is not in use
+//==================================================================================================
+
+    LOCAL_APIC.try()
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn x2apic_msr(register: u32) -> u32 {
+//--------------------------------------------------------------------------------------------------
+// Obtain the MSR through which a register is reached in x2APIC mode.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   register -> xAPIC offset of the register
+//
+// RETURNS: the MSR number
+//==================================================================================================
+
+    X2APIC_MSR_BASE + (register >> 4)
+}
@@ -0,0 +1,405 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/interrupts: exceptions.rs                                                               #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use super::idt::{Idt, ExceptionStackFrame};
+use super::gdt::{DOUBLE_FAULT_IST_INDEX, NMI_IST_INDEX, MACHINE_CHECK_IST_INDEX};
+use memory::paging::VirtualAddress;
+use memory::paging::cow;
+use memory::paging::demand::{self, PageFaultAccess};
+use x86::shared::control_regs::cr2;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const EXCEPTION_COUNT: usize = 32;
+
+pub const NMI_VECTOR: u8 = 2;
+pub const DOUBLE_FAULT_VECTOR: u8 = 8;
+pub const PAGE_FAULT_VECTOR: u8 = 14;
+pub const MACHINE_CHECK_VECTOR: u8 = 18;
+
+
+//==================================================================================================
+
+
+const EXCEPTION_NAMES: [(&'static str, &'static str); EXCEPTION_COUNT] = [
+    ("Divide Error",                   "#DE"),
+    ("Debug",                          "#DB"),
+    ("Non-Maskable Interrupt",         "NMI"),
+    ("Breakpoint",                     "#BP"),
+    ("Overflow",                       "#OF"),
+    ("Bound Range Exceeded",           "#BR"),
+    ("Invalid Opcode",                 "#UD"),
+    ("Device Not Available",           "#NM"),
+    ("Double Fault",                   "#DF"),
+    ("Coprocessor Segment Overrun",    "---"),
+    ("Invalid TSS",                    "#TS"),
+    ("Segment Not Present",            "#NP"),
+    ("Stack-Segment Fault",            "#SS"),
+    ("General Protection Fault",       "#GP"),
+    ("Page Fault",                     "#PF"),
+    ("Reserved",                       "---"),
+    ("x87 Floating-Point Exception",   "#MF"),
+    ("Alignment Check",                "#AC"),
+    ("Machine Check",                  "#MC"),
+    ("SIMD Floating-Point Exception",  "#XM"),
+    ("Virtualization Exception",       "#VE"),
+    ("Control Protection Exception",   "#CP"),
+    ("Reserved",                       "---"),
+    ("Reserved",                       "---"),
+    ("Reserved",                       "---"),
+    ("Reserved",                       "---"),
+    ("Reserved",                       "---"),
+    ("Reserved",                       "---"),
+    ("Hypervisor Injection Exception", "#HV"),
+    ("VMM Communication Exception",    "#VC"),
+    ("Security Exception",             "#SX"),
+    ("Reserved",                       "---"),
+];
+
+
+//==================================================================================================
+
+
+// Page fault error code bits
+const PF_PROTECTION_VIOLATION: u64 = 1 << 0;    // Set if page was present, clear if not
+const PF_WRITE: u64                = 1 << 1;    // Set if access was a write
+const PF_USER: u64                 = 1 << 2;    // Set if access came from ring 3
+const PF_RESERVED_BIT: u64         = 1 << 3;    // Set if a reserved bit was set in an entry
+const PF_INSTRUCTION_FETCH: u64    = 1 << 4;    // Set if access was an instruction fetch
+const PF_PROTECTION_KEY: u64       = 1 << 5;    // Set if a protection key forbade the access
+const PF_SHADOW_STACK: u64         = 1 << 6;    // Set if access was a shadow stack access
+const PF_SGX: u64                  = 1 << 15;   // Set if an SGX access check failed
+
+
+//##################################################################################################
+//******************************************** MACROS **********************************************
+//##################################################################################################
+
+
+//==================================================================================================
+macro_rules! exception_handler {
+//--------------------------------------------------------------------------------------------------
+// Define a handler for a vector on which the CPU does not push an error code.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   name   -> name of the handler function
+//          vector -> vector the handler is installed on
+//==================================================================================================
+
+    ($name:ident, $vector:expr) => {
+        extern "x86-interrupt" fn $name(stack_frame: &mut ExceptionStackFrame) {
+            report_exception($vector, None, stack_frame);
+        }
+    };
+}
+
+
+//==================================================================================================
+macro_rules! exception_handler_with_error_code {
+//--------------------------------------------------------------------------------------------------
+// Define a handler for a vector on which the CPU pushes an error code.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   name   -> name of the handler function
+//          vector -> vector the handler is installed on
+//==================================================================================================
+
+    ($name:ident, $vector:expr) => {
+        extern "x86-interrupt" fn $name(stack_frame: &mut ExceptionStackFrame, error_code: u64) {
+            report_exception($vector, Some(error_code), stack_frame);
+        }
+    };
+}
+
+
+//##################################################################################################
+//******************************************* HANDLERS *********************************************
+//##################################################################################################
+
+
+exception_handler!(divide_error_handler, 0);
+exception_handler!(debug_handler, 1);
+exception_handler!(non_maskable_interrupt_handler, 2);
+exception_handler!(breakpoint_handler, 3);
+exception_handler!(overflow_handler, 4);
+exception_handler!(bound_range_exceeded_handler, 5);
+exception_handler!(invalid_opcode_handler, 6);
+exception_handler!(device_not_available_handler, 7);
+exception_handler_with_error_code!(double_fault_handler, 8);
+exception_handler!(coprocessor_segment_overrun_handler, 9);
+exception_handler_with_error_code!(invalid_tss_handler, 10);
+exception_handler_with_error_code!(segment_not_present_handler, 11);
+exception_handler_with_error_code!(stack_segment_fault_handler, 12);
+exception_handler_with_error_code!(general_protection_fault_handler, 13);
+exception_handler!(reserved_15_handler, 15);
+exception_handler!(x87_floating_point_handler, 16);
+exception_handler_with_error_code!(alignment_check_handler, 17);
+exception_handler!(machine_check_handler, 18);
+exception_handler!(simd_floating_point_handler, 19);
+exception_handler!(virtualization_handler, 20);
+exception_handler_with_error_code!(control_protection_handler, 21);
+exception_handler!(reserved_22_handler, 22);
+exception_handler!(reserved_23_handler, 23);
+exception_handler!(reserved_24_handler, 24);
+exception_handler!(reserved_25_handler, 25);
+exception_handler!(reserved_26_handler, 26);
+exception_handler!(reserved_27_handler, 27);
+exception_handler!(hypervisor_injection_handler, 28);
+exception_handler_with_error_code!(vmm_communication_handler, 29);
+exception_handler_with_error_code!(security_handler, 30);
+exception_handler!(reserved_31_handler, 31);
+
+
+//==================================================================================================
+extern "x86-interrupt" fn page_fault_handler(stack_frame: &mut ExceptionStackFrame,
+                                              error_code: u64) {
+//--------------------------------------------------------------------------------------------------
+// Back the faulting page if it lies in a demand-paged region and the region permits the access,
+// or give the writer its own copy of a copy-on-write page. Any other fault is reported along with
+// the faulting address, the decoded cause, and the region involved, then the kernel panics.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   stack_frame -> state pushed by the CPU
+//          error_code  -> page fault error code pushed by the CPU
+//
+// RETURNS: only if the fault was resolved
+//==================================================================================================
+
+    let address = VirtualAddress::new(unsafe { cr2() });
+    let access = PageFaultAccess {
+        protection_violation: error_code & PF_PROTECTION_VIOLATION != 0,
+        write: error_code & PF_WRITE != 0,
+        user: error_code & PF_USER != 0,
+        instruction_fetch: error_code & PF_INSTRUCTION_FETCH != 0,
+    };
+
+    // Present pages only fault on writes to copy-on-write pages; the rest are demand paged
+    let result = if (access.protection_violation) {
+        cow::handle_page_fault(address, access)
+    }
+    else {
+        demand::handle_page_fault(address, access)
+    };
+
+    let error = match result {
+        Ok(()) => return,
+        Err(error) => error,
+    };
+
+    print_exception(PAGE_FAULT_VECTOR, Some(error_code), stack_frame);
+    print_page_fault_details(error_code);
+
+    if let Some(region) = demand::find_region(address) {
+        println!("   region: '{}' {:#x}-{:#x} {:?}", region.name, region.start, region.end,
+                 region.flags);
+    }
+
+    panic!("unhandled page fault at {:#x}: {}", address, error);
+}
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn install(idt: &mut Idt) {
+//--------------------------------------------------------------------------------------------------
+// Install a handler for every architectural exception vector. Double faults, NMIs, and machine
+// checks run on their own interrupt stacks, as the interrupted stack may be unusable.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   idt -> table to install the handlers in
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    idt.set_handler(0, divide_error_handler);
+    idt.set_handler(1, debug_handler);
+    idt.set_handler(NMI_VECTOR, non_maskable_interrupt_handler)
+        .set_stack_index(NMI_IST_INDEX);
+    idt.set_handler(3, breakpoint_handler);
+    idt.set_handler(4, overflow_handler);
+    idt.set_handler(5, bound_range_exceeded_handler);
+    idt.set_handler(6, invalid_opcode_handler);
+    idt.set_handler(7, device_not_available_handler);
+    idt.set_handler_with_error_code(DOUBLE_FAULT_VECTOR, double_fault_handler)
+        .set_stack_index(DOUBLE_FAULT_IST_INDEX);
+    idt.set_handler(9, coprocessor_segment_overrun_handler);
+    idt.set_handler_with_error_code(10, invalid_tss_handler);
+    idt.set_handler_with_error_code(11, segment_not_present_handler);
+    idt.set_handler_with_error_code(12, stack_segment_fault_handler);
+    idt.set_handler_with_error_code(13, general_protection_fault_handler);
+    idt.set_handler_with_error_code(PAGE_FAULT_VECTOR, page_fault_handler);
+    idt.set_handler(15, reserved_15_handler);
+    idt.set_handler(16, x87_floating_point_handler);
+    idt.set_handler_with_error_code(17, alignment_check_handler);
+    idt.set_handler(MACHINE_CHECK_VECTOR, machine_check_handler)
+        .set_stack_index(MACHINE_CHECK_IST_INDEX);
+    idt.set_handler(19, simd_floating_point_handler);
+    idt.set_handler(20, virtualization_handler);
+    idt.set_handler_with_error_code(21, control_protection_handler);
+    idt.set_handler(22, reserved_22_handler);
+    idt.set_handler(23, reserved_23_handler);
+    idt.set_handler(24, reserved_24_handler);
+    idt.set_handler(25, reserved_25_handler);
+    idt.set_handler(26, reserved_26_handler);
+    idt.set_handler(27, reserved_27_handler);
+    idt.set_handler(28, hypervisor_injection_handler);
+    idt.set_handler_with_error_code(29, vmm_communication_handler);
+    idt.set_handler_with_error_code(30, security_handler);
+    idt.set_handler(31, reserved_31_handler);
+}
+
+
+//==================================================================================================
+pub fn print_exception(vector: u8, error_code: Option<u64>, stack_frame: &ExceptionStackFrame) {
+//--------------------------------------------------------------------------------------------------
+// Print the name of an exception, its error code, and the interrupted state.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   vector      -> vector of the exception
+//          error_code  -> error code pushed by the CPU, if any
+//          stack_frame -> state pushed by the CPU
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let (name, mnemonic) = EXCEPTION_NAMES[vector as usize];
+
+    println!("\n\nEXCEPTION: {} ({}, vector {})", name, mnemonic, vector);
+
+    if let Some(code) = error_code {
+        println!("   error code: {:#x}", code);
+    }
+
+    println!("   RIP: {:#018x}   CS: {:#06x}", stack_frame.instruction_pointer,
+             stack_frame.code_segment);
+    println!("   RSP: {:#018x}   SS: {:#06x}", stack_frame.stack_pointer,
+             stack_frame.stack_segment);
+    println!("   RFLAGS: {:#x}", stack_frame.cpu_flags);
+}
+
+
+//==================================================================================================
+pub fn print_page_fault_details(error_code: u64) {
+//--------------------------------------------------------------------------------------------------
+// Print the faulting address held in CR2 along with the meaning of each page fault error bit.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   error_code -> page fault error code pushed by the CPU
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    println!("   CR2: {:#018x}", unsafe { cr2() });
+    println!("   cause: {} {} in {} mode",
+             if (error_code & PF_PROTECTION_VIOLATION != 0) { "protection violation on" }
+             else { "non-present page on" },
+             if (error_code & PF_INSTRUCTION_FETCH != 0) { "instruction fetch" }
+             else if (error_code & PF_WRITE != 0) { "write" }
+             else { "read" },
+             if (error_code & PF_USER != 0) { "user" } else { "supervisor" });
+
+    if (error_code & PF_RESERVED_BIT != 0) {
+        println!("   a reserved bit was set in a page table entry");
+    }
+    if (error_code & PF_PROTECTION_KEY != 0) {
+        println!("   access denied by protection key");
+    }
+    if (error_code & PF_SHADOW_STACK != 0) {
+        println!("   access was a shadow stack access");
+    }
+    if (error_code & PF_SGX != 0) {
+        println!("   access violated SGX restrictions");
+    }
+}
+
+
+//==================================================================================================
+pub fn halt() -> ! {
+//--------------------------------------------------------------------------------------------------
+// Stop doing anything useful after an unrecoverable exception.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: never
+//==================================================================================================
+
+    loop {
+        unsafe { asm!("cli; hlt" :::: "volatile"); }
+    }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn report_exception(vector: u8, error_code: Option<u64>, stack_frame: &ExceptionStackFrame) {
+//--------------------------------------------------------------------------------------------------
+// Print an exception and halt, unless the exception is a trap that execution may continue after.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   vector      -> vector of the exception
+//          error_code  -> error code pushed by the CPU, if any
+//          stack_frame -> state pushed by the CPU
+//
+// RETURNS: nothing, if the exception was recoverable
+//==================================================================================================
+
+    print_exception(vector, error_code, stack_frame);
+
+    // Selector error codes name the descriptor that caused the fault
+    match (vector, error_code) {
+        (10, Some(code)) | (11, Some(code)) | (12, Some(code)) | (13, Some(code)) if code != 0 => {
+            let table = match (code >> 1) & 0b11 {
+                0b00 => "GDT",
+                0b10 => "LDT",
+                _    => "IDT",
+            };
+            println!("   selector: {} index {}{}", table, (code >> 3) & 0x1FFF,
+                     if (code & 1 != 0) { " (external event)" } else { "" });
+        },
+        _ => {},
+    }
+
+    match vector {
+        1 | 2 | 3 | 4 => {},
+        _             => halt(),
+    }
+}
@@ -0,0 +1,313 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/interrupts: gdt.rs                                                                      #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use super::idt::DescriptorTablePointer;
+use memory::{FrameAllocator, PAGE_SIZE};
+use memory::paging::{Page, ActivePageTable, VirtualAddress};
+use core::mem::size_of;
+use spin::Once;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const KERNEL_CODE_SELECTOR: u16 = 1 << 3;
+pub const KERNEL_DATA_SELECTOR: u16 = 2 << 3;
+pub const USER_DATA_SELECTOR: u16   = 3 << 3 | 3;  // User segments request privilege level 3
+pub const USER_CODE_SELECTOR: u16   = 4 << 3 | 3;
+pub const TSS_SELECTOR: u16         = 5 << 3;      // TSS descriptor fills entries 5 and 6
+
+pub const DOUBLE_FAULT_IST_INDEX: u16  = 0;
+pub const NMI_IST_INDEX: u16           = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+const GDT_ENTRY_COUNT: usize = 7;
+const IST_STACK_COUNT: usize = 3;
+const IST_STACK_SIZE: usize = 4 * PAGE_SIZE;
+
+
+//==================================================================================================
+
+
+// Segment descriptor bits
+const DESC_WRITABLE: u64   = 1 << 41;   // Data segment may be written, code segment may be read
+const DESC_EXECUTABLE: u64 = 1 << 43;   // Segment holds code
+const DESC_USER: u64       = 1 << 44;   // Code or data segment, rather than a system segment
+const DESC_RING_3: u64     = 3 << 45;   // Segment usable from ring 3
+const DESC_PRESENT: u64    = 1 << 47;   // Segment is valid
+const DESC_LONG_MODE: u64  = 1 << 53;   // Code segment runs 64-bit code
+const DESC_TSS_TYPE: u64   = 0b1001 << 40;
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[repr(C, packed)]
+//==================================================================================================
+pub struct TaskStateSegment {
+//--------------------------------------------------------------------------------------------------
+// 64-bit task state segment. In long mode it only supplies stacks for privilege changes and for
+// interrupts whose gates name an Interrupt Stack Table entry.
+//==================================================================================================
+
+    reserved_1: u32,
+    pub privilege_stack_table: [u64; 3],    // Stacks loaded when entering rings 0 through 2
+    reserved_2: u64,
+    pub interrupt_stack_table: [u64; 7],    // Stacks selectable by IDT gates
+    reserved_3: u64,
+    reserved_4: u16,
+    pub iomap_base: u16,                    // Offset of the I/O permission bitmap
+}
+
+
+//==================================================================================================
+pub struct Gdt([u64; GDT_ENTRY_COUNT]);
+//--------------------------------------------------------------------------------------------------
+// Global descriptor table with the kernel and user segments and a single TSS.
+//==================================================================================================
+
+
+#[repr(C, align(4096))]
+//==================================================================================================
+struct IstStack {
+//--------------------------------------------------------------------------------------------------
+// Interrupt stack preceded by a page that is unmapped once paging is set up, so that an overflow
+// faults instead of silently corrupting neighbouring memory.
+//==================================================================================================
+
+    guard: [u8; PAGE_SIZE],
+    stack: [u8; IST_STACK_SIZE],
+}
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+static mut IST_STACKS: [IstStack; IST_STACK_COUNT] = [
+    IstStack { guard: [0; PAGE_SIZE], stack: [0; IST_STACK_SIZE] },
+    IstStack { guard: [0; PAGE_SIZE], stack: [0; IST_STACK_SIZE] },
+    IstStack { guard: [0; PAGE_SIZE], stack: [0; IST_STACK_SIZE] },
+];
+
+static TSS: Once<TaskStateSegment> = Once::new();
+static GDT: Once<Gdt> = Once::new();
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl TaskStateSegment {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn new() -> TaskStateSegment {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for a TaskStateSegment with no stacks and no I/O permission bitmap.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: an empty TaskStateSegment
+    //==============================================================================================
+
+        TaskStateSegment {
+            reserved_1: 0,
+            privilege_stack_table: [0; 3],
+            reserved_2: 0,
+            interrupt_stack_table: [0; 7],
+            reserved_3: 0,
+            reserved_4: 0,
+            iomap_base: size_of::<TaskStateSegment>() as u16,
+        }
+    }
+}
+
+
+//==================================================================================================
+impl Gdt {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn new(tss: &'static TaskStateSegment) -> Gdt {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for the kernel's Gdt. Entry positions match the *_SELECTOR constants.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   tss -> task state segment the TSS descriptor refers to
+    //
+    // RETURNS: a Gdt ready to be loaded
+    //==============================================================================================
+
+        let tss_base = tss as *const _ as u64;
+        let tss_limit = (size_of::<TaskStateSegment>() - 1) as u64;
+
+        let tss_low = DESC_PRESENT | DESC_TSS_TYPE
+            | (tss_limit & 0xFFFF)
+            | ((tss_base & 0xFF_FFFF) << 16)
+            | ((tss_base >> 24 & 0xFF) << 56);
+        let tss_high = tss_base >> 32;
+
+        let code = DESC_USER | DESC_PRESENT | DESC_WRITABLE | DESC_EXECUTABLE | DESC_LONG_MODE;
+        let data = DESC_USER | DESC_PRESENT | DESC_WRITABLE;
+
+        Gdt([
+            0,                          // The first entry must always be null
+            code,
+            data,
+            data | DESC_RING_3,
+            code | DESC_RING_3,
+            tss_low,
+            tss_high,
+        ])
+    }
+
+
+    //==============================================================================================
+    pub fn load(&'static self) {
+    //----------------------------------------------------------------------------------------------
+    // Make this the CPU's global descriptor table, reload every segment register from it, and load
+    // the task register. The table must live forever, as the CPU keeps referring to it.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        let pointer = DescriptorTablePointer {
+            limit: (size_of::<Gdt>() - 1) as u16,
+            base: self as *const _ as u64,
+        };
+
+        unsafe {
+            asm!("lgdt ($0)" :: "r" (&pointer) : "memory");
+
+            // CS may only be changed by a far transfer, so return to the next instruction through
+            // the new code segment
+            asm!("pushq $0
+                  leaq 1f(%rip), %rax
+                  pushq %rax
+                  lretq
+                  1:" :: "r" (KERNEL_CODE_SELECTOR as u64) : "rax" "memory");
+
+            asm!("movw $0, %ds
+                  movw $0, %es
+                  movw $0, %ss" :: "r" (KERNEL_DATA_SELECTOR) : "memory");
+
+            asm!("ltr $0" :: "r" (TSS_SELECTOR));
+        }
+    }
+}
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn init() {
+//--------------------------------------------------------------------------------------------------
+// Build the task state segment with its interrupt stacks, then build and load the GDT. Replaces
+// the minimal GDT installed by boot.asm.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let tss = TSS.call_once(|| {
+        let mut interrupt_stacks = [0; 7];
+        for index in 0 .. IST_STACK_COUNT {
+            interrupt_stacks[index] = ist_stack_top(index) as u64;
+        }
+
+        let mut tss = TaskStateSegment::new();
+        tss.interrupt_stack_table = interrupt_stacks;
+        tss
+    });
+
+    GDT.call_once(|| Gdt::new(tss)).load();
+}
+
+
+//==================================================================================================
+pub fn unmap_guard_pages<A: FrameAllocator>(active_table: &mut ActivePageTable, allocator: &mut A) {
+//--------------------------------------------------------------------------------------------------
+// Unmap the guard page below every interrupt stack. Must be called once the kernel has been
+// remapped, as the boot page tables map the kernel with huge pages.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   active_table -> page table the kernel is mapped in
+//          allocator    -> allocator receiving any emptied tables
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    // Guard frames are part of the kernel image, so they are not handed to the allocator
+    for index in 0 .. IST_STACK_COUNT {
+        let guard = unsafe { &IST_STACKS[index].guard as *const _ as usize };
+        active_table.unmap_shared(Page::containing_address(VirtualAddress::new(guard)), allocator);
+    }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn ist_stack_top(index: usize) -> usize {
+//--------------------------------------------------------------------------------------------------
+// Obtain the initial stack pointer of an interrupt stack. Stacks grow down, so this is one past
+// the stack's last byte.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   index -> index of the stack in the interrupt stack table
+//
+// RETURNS: address of the top of the stack
+//==================================================================================================
+
+    unsafe { &IST_STACKS[index].stack as *const _ as usize + IST_STACK_SIZE }
+}
@@ -0,0 +1,393 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/interrupts: idt.rs                                                                      #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use core::mem::size_of;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const IDT_ENTRY_COUNT: usize = 256;
+
+
+//##################################################################################################
+//********************************** TYPE & STRUCT DEFINITIONS *************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub type HandlerFunc = extern "x86-interrupt" fn(&mut ExceptionStackFrame);
+//--------------------------------------------------------------------------------------------------
+// Interrupt handler for vectors on which the CPU does not push an error code.
+//==================================================================================================
+
+
+//==================================================================================================
+pub type HandlerFuncWithErrCode = extern "x86-interrupt" fn(&mut ExceptionStackFrame, u64);
+//--------------------------------------------------------------------------------------------------
+// Interrupt handler for vectors on which the CPU pushes an error code.
+//==================================================================================================
+
+
+#[derive(Debug)]
+#[repr(C)]
+//==================================================================================================
+pub struct ExceptionStackFrame {
+//--------------------------------------------------------------------------------------------------
+// State pushed onto the stack by the CPU before entering an interrupt handler.
+//==================================================================================================
+
+    pub instruction_pointer: u64,       // RIP at the time of the interrupt
+    pub code_segment: u64,              // CS at the time of the interrupt
+    pub cpu_flags: u64,                 // RFLAGS at the time of the interrupt
+    pub stack_pointer: u64,             // RSP at the time of the interrupt
+    pub stack_segment: u64,             // SS at the time of the interrupt
+}
+
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+//==================================================================================================
+pub struct IdtEntry {
+//--------------------------------------------------------------------------------------------------
+// Gate descriptor in the interrupt descriptor table.
+//==================================================================================================
+
+    pointer_low: u16,                   // Bits 0-15 of the handler address
+    gdt_selector: u16,                  // Code segment to run the handler in
+    options: EntryOptions,              // Stack, gate type, privilege, and present bits
+    pointer_middle: u16,                // Bits 16-31 of the handler address
+    pointer_high: u32,                  // Bits 32-63 of the handler address
+    reserved: u32,
+}
+
+
+#[derive(Clone, Copy)]
+//==================================================================================================
+pub struct EntryOptions(u16);
+//--------------------------------------------------------------------------------------------------
+// Option bits of a gate descriptor.
+//==================================================================================================
+
+
+//==================================================================================================
+pub struct Idt([IdtEntry; IDT_ENTRY_COUNT]);
+//--------------------------------------------------------------------------------------------------
+// Interrupt descriptor table holding a gate for every interrupt vector.
+//==================================================================================================
+
+
+#[repr(C, packed)]
+//==================================================================================================
+pub struct DescriptorTablePointer {
+//--------------------------------------------------------------------------------------------------
+// Operand of the lidt and lgdt instructions.
+//==================================================================================================
+
+    pub limit: u16,                     // Size of the table in bytes, minus one
+    pub base: u64,                      // Virtual address of the table
+}
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl EntryOptions {
+//==================================================================================================
+
+
+    //==============================================================================================
+    const fn minimal() -> EntryOptions {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for options describing a non-present interrupt gate. The gate type bits
+    // 9-11 must always be set.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: options of a missing gate
+    //==============================================================================================
+
+        EntryOptions(0b1110_0000_0000)
+    }
+
+
+    //==============================================================================================
+    fn new() -> EntryOptions {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for options describing a present interrupt gate, which disables further
+    // interrupts while its handler runs.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: options of a present gate
+    //==============================================================================================
+
+        let mut options = EntryOptions::minimal();
+        options.set_present(true).disable_interrupts(true);
+        options
+    }
+
+
+    //==============================================================================================
+    pub fn set_present(&mut self, present: bool) -> &mut EntryOptions {
+    //----------------------------------------------------------------------------------------------
+    // Set or clear the present bit.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   present -> whether the gate may be used
+    //
+    // RETURNS: these options, for chaining
+    //==============================================================================================
+
+        self.set_bit(15, present);
+        self
+    }
+
+
+    //==============================================================================================
+    pub fn disable_interrupts(&mut self, disable: bool) -> &mut EntryOptions {
+    //----------------------------------------------------------------------------------------------
+    // Choose between an interrupt gate, which clears IF on entry, and a trap gate, which does not.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   disable -> whether interrupts are disabled while the handler runs
+    //
+    // RETURNS: these options, for chaining
+    //==============================================================================================
+
+        self.set_bit(8, !disable);
+        self
+    }
+
+
+    //==============================================================================================
+    pub fn set_privilege_level(&mut self, dpl: u16) -> &mut EntryOptions {
+    //----------------------------------------------------------------------------------------------
+    // Set the least privileged ring allowed to invoke the gate with an int instruction.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   dpl -> descriptor privilege level, 0 through 3
+    //
+    // RETURNS: these options, for chaining
+    //==============================================================================================
+
+        self.0 = (self.0 & !(0b11 << 13)) | ((dpl & 0b11) << 13);
+        self
+    }
+
+
+    //==============================================================================================
+    pub fn set_stack_index(&mut self, index: u16) -> &mut EntryOptions {
+    //----------------------------------------------------------------------------------------------
+    // Make the gate switch to one of the TSS's interrupt stacks. The gate's IST field counts from
+    // one, as zero means the stack is not switched.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   index -> slot of the stack in the TSS's interrupt_stack_table, 0 through 6
+    //
+    // RETURNS: these options, for chaining
+    //==============================================================================================
+
+        assert!(index < 7, "no such interrupt stack");
+        self.0 = (self.0 & !0b111) | (index + 1);
+        self
+    }
+
+
+    //==============================================================================================
+    fn set_bit(&mut self, bit: u16, value: bool) {
+    //----------------------------------------------------------------------------------------------
+    // Set or clear a single option bit.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   bit   -> index of the bit
+    //          value -> whether the bit should be set
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        if (value) {
+            self.0 |= 1 << bit;
+        }
+        else {
+            self.0 &= !(1 << bit);
+        }
+    }
+}
+
+
+//==================================================================================================
+impl IdtEntry {
+//==================================================================================================
+
+
+    //==============================================================================================
+    const fn missing() -> IdtEntry {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for a gate that is not present. Using it raises a general protection
+    // fault.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: a non-present gate
+    //==============================================================================================
+
+        IdtEntry {
+            pointer_low: 0,
+            gdt_selector: 0,
+            options: EntryOptions::minimal(),
+            pointer_middle: 0,
+            pointer_high: 0,
+            reserved: 0,
+        }
+    }
+
+
+    //==============================================================================================
+    fn new(handler: u64) -> IdtEntry {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for a present gate running a handler in the current code segment.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   handler -> address of the handler
+    //
+    // RETURNS: a present gate
+    //==============================================================================================
+
+        IdtEntry {
+            pointer_low: handler as u16,
+            gdt_selector: current_code_segment(),
+            options: EntryOptions::new(),
+            pointer_middle: (handler >> 16) as u16,
+            pointer_high: (handler >> 32) as u32,
+            reserved: 0,
+        }
+    }
+}
+
+
+//==================================================================================================
+impl Idt {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub const fn new() -> Idt {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for an Idt with every gate missing.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: an empty Idt
+    //==============================================================================================
+
+        Idt([IdtEntry::missing(); IDT_ENTRY_COUNT])
+    }
+
+
+    //==============================================================================================
+    pub fn set_handler(&mut self, vector: u8, handler: HandlerFunc) -> &mut EntryOptions {
+    //----------------------------------------------------------------------------------------------
+    // Install a handler for a vector on which the CPU does not push an error code.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   vector  -> interrupt vector to handle
+    //          handler -> handler to run
+    //
+    // RETURNS: the options of the new gate, for further adjustment
+    //==============================================================================================
+
+        self.0[vector as usize] = IdtEntry::new(handler as u64);
+        &mut self.0[vector as usize].options
+    }
+
+
+    //==============================================================================================
+    pub fn set_handler_with_error_code(&mut self, vector: u8, handler: HandlerFuncWithErrCode)
+                                       -> &mut EntryOptions {
+    //----------------------------------------------------------------------------------------------
+    // Install a handler for a vector on which the CPU pushes an error code.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   vector  -> interrupt vector to handle
+    //          handler -> handler to run
+    //
+    // RETURNS: the options of the new gate, for further adjustment
+    //==============================================================================================
+
+        self.0[vector as usize] = IdtEntry::new(handler as u64);
+        &mut self.0[vector as usize].options
+    }
+
+
+    //==============================================================================================
+    pub fn load(&'static self) {
+    //----------------------------------------------------------------------------------------------
+    // Make this the CPU's interrupt descriptor table. The table must live forever, as the CPU keeps
+    // referring to it.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        let pointer = DescriptorTablePointer {
+            limit: (size_of::<Idt>() - 1) as u16,
+            base: self as *const _ as u64,
+        };
+
+        unsafe { asm!("lidt ($0)" :: "r" (&pointer) : "memory"); }
+    }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn current_code_segment() -> u16 {
+//--------------------------------------------------------------------------------------------------
+// Read the selector currently loaded in CS.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: the code segment selector
+//==================================================================================================
+
+    let segment: u16;
+    unsafe { asm!("mov %cs, $0" : "=r" (segment)); }
+    segment
+}
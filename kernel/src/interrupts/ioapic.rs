@@ -0,0 +1,370 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/interrupts: ioapic.rs                                                                   #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::{IoMapping, MemoryType, ioremap};
+use memory::paging::PhysicalAddress;
+use spin::Mutex;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const DEFAULT_ADDRESS: usize = 0xFEC0_0000; // Where firmware almost always places the IO APIC
+pub const ISA_IRQ_COUNT: usize = 16;
+
+const REGISTER_SELECT: usize = 0x00;            // Index of the register IO_WINDOW reaches
+const IO_WINDOW: usize = 0x10;
+const WINDOW_SIZE: usize = 0x20;
+
+const VERSION_REGISTER: u32 = 0x01;
+const NO_DEVICE: u32 = !0;                      // What reads of an empty bus address return
+const REDIRECTION_TABLE: u32 = 0x10;            // Two registers per entry, low half first
+
+const REDIRECTION_LEVEL: u64 = 1 << 15;         // Level triggered rather than edge triggered
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;    // Asserted low rather than high
+const REDIRECTION_MASKED: u64 = 1 << 16;        // Interrupt is not delivered
+const DESTINATION_SHIFT: u64 = 56;              // APIC ID of the receiving processor
+const MAX_DESTINATION: u32 = 0xFF;              // Widest APIC ID the 8-bit destination field holds
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+//==================================================================================================
+pub enum TriggerMode {
+//--------------------------------------------------------------------------------------------------
+// How a device signals an interrupt on its line.
+//==================================================================================================
+
+    Edge,                               // Once per transition
+    Level,                              // For as long as the line is held, until serviced
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+//==================================================================================================
+pub enum Polarity {
+//--------------------------------------------------------------------------------------------------
+// Which level of a line counts as asserted.
+//==================================================================================================
+
+    ActiveHigh,
+    ActiveLow,
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+//==================================================================================================
+pub struct InterruptSourceOverride {
+//--------------------------------------------------------------------------------------------------
+// Difference between how an ISA IRQ is wired and the identity mapping onto IO APIC inputs, with
+// ISA's edge triggered, active high signalling, as reported by the ACPI MADT.
+//==================================================================================================
+
+    pub irq: u8,                        // ISA IRQ being overridden
+    pub gsi: u32,                       // Global system interrupt the IRQ is wired to
+    pub trigger: TriggerMode,
+    pub polarity: Polarity,
+}
+
+
+//==================================================================================================
+struct IoApic {
+//--------------------------------------------------------------------------------------------------
+// IO APIC routing external interrupts to local APICs, along with how ISA IRQs reach it.
+//==================================================================================================
+
+    registers: IoMapping,               // Uncached register window
+    gsi_base: u32,                      // First global system interrupt served
+    entry_count: u32,                   // Number of redirection entries
+    destination: u32,                   // APIC ID every interrupt is sent to
+    isa_routes: [InterruptSourceOverride; ISA_IRQ_COUNT],   // Wiring of every ISA IRQ
+}
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+// Registers are reached through a select/window pair, so every access happens under the lock
+static IO_APIC: Mutex<Option<IoApic>> = Mutex::new(None);
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl IoApic {
+//==================================================================================================
+
+
+    //==============================================================================================
+    fn read(&self, register: u32) -> u32 {
+    //----------------------------------------------------------------------------------------------
+    // Read an indirect register.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   register -> index of the register
+    //
+    // RETURNS: value of the register
+    //==============================================================================================
+
+        self.registers.write::<u32>(REGISTER_SELECT, register);
+        self.registers.read::<u32>(IO_WINDOW)
+    }
+
+
+    //==============================================================================================
+    fn write(&self, register: u32, value: u32) {
+    //----------------------------------------------------------------------------------------------
+    // Write an indirect register.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   register -> index of the register
+    //          value    -> value to write
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        self.registers.write::<u32>(REGISTER_SELECT, register);
+        self.registers.write::<u32>(IO_WINDOW, value);
+    }
+
+
+    //==============================================================================================
+    fn serves(&self, gsi: u32) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether a global system interrupt arrives at one of this IO APIC's inputs.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   gsi -> global system interrupt
+    //
+    // RETURNS: true if it does, false otherwise
+    //==============================================================================================
+
+        gsi >= self.gsi_base && gsi - self.gsi_base < self.entry_count
+    }
+
+
+    //==============================================================================================
+    fn entry_index(&self, gsi: u32) -> u32 {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the redirection entry serving a global system interrupt.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   gsi -> global system interrupt
+    //
+    // RETURNS: index of the entry; panics if this IO APIC does not serve the interrupt
+    //==============================================================================================
+
+        assert!(self.serves(gsi), "GSI {} not served by the IO APIC", gsi);
+        gsi - self.gsi_base
+    }
+
+
+    //==============================================================================================
+    fn read_entry(&self, gsi: u32) -> u64 {
+    //----------------------------------------------------------------------------------------------
+    // Read the redirection entry of a global system interrupt.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   gsi -> global system interrupt
+    //
+    // RETURNS: the 64-bit entry
+    //==============================================================================================
+
+        let register = REDIRECTION_TABLE + 2 * self.entry_index(gsi);
+        self.read(register) as u64 | (self.read(register + 1) as u64) << 32
+    }
+
+
+    //==============================================================================================
+    fn write_entry(&self, gsi: u32, entry: u64) {
+    //----------------------------------------------------------------------------------------------
+    // Write the redirection entry of a global system interrupt. The high half, holding the
+    // destination, goes first, so the entry is never live with a stale destination.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   gsi   -> global system interrupt
+    //          entry -> the 64-bit entry
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        let register = REDIRECTION_TABLE + 2 * self.entry_index(gsi);
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+}
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn init(address: PhysicalAddress, gsi_base: u32, destination: u32,
+            overrides: &[InterruptSourceOverride]) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Map the IO APIC uncached, mask every redirection entry, and record how ISA IRQs are wired.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address     -> physical address of the register window
+//          gsi_base    -> first global system interrupt the IO APIC serves
+//          destination -> APIC ID of the processor to deliver every interrupt to
+//          overrides   -> ISA IRQs not wired to the input of the same number
+//
+// RETURNS: true  -> the IO APIC is ready for routes
+//          false -> its window could not be mapped, no IO APIC answers there, or the destination's
+//                   APIC ID does not fit a physical destination
+//==================================================================================================
+
+    if (destination > MAX_DESTINATION) {
+        return false;
+    }
+
+    let registers = match ioremap(address, WINDOW_SIZE, MemoryType::Uncacheable) {
+        Some(registers) => registers,
+        None            => return false,
+    };
+
+    let mut isa_routes = [InterruptSourceOverride {
+        irq: 0, gsi: 0, trigger: TriggerMode::Edge, polarity: Polarity::ActiveHigh,
+    }; ISA_IRQ_COUNT];
+
+    for (irq, route) in isa_routes.iter_mut().enumerate() {
+        route.irq = irq as u8;
+        route.gsi = irq as u32;
+    }
+    for route in overrides.iter().filter(|route| (route.irq as usize) < ISA_IRQ_COUNT) {
+        isa_routes[route.irq as usize] = *route;
+    }
+
+    let mut io_apic = IoApic {
+        registers: registers,
+        gsi_base: gsi_base,
+        entry_count: 0,
+        destination: destination,
+        isa_routes: isa_routes,
+    };
+
+    let version = io_apic.read(VERSION_REGISTER);
+    if (version == NO_DEVICE) {
+        return false;
+    }
+    io_apic.entry_count = (version >> 16 & 0xFF) + 1;
+
+    for index in 0 .. io_apic.entry_count {
+        io_apic.write_entry(gsi_base + index, REDIRECTION_MASKED);
+    }
+
+    *IO_APIC.lock() = Some(io_apic);
+    true
+}
+
+
+//==================================================================================================
+pub fn isa_route(irq: u8) -> Option<InterruptSourceOverride> {
+//--------------------------------------------------------------------------------------------------
+// Find where an ISA IRQ arrives at the IO APIC, and how it is signalled.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   irq -> ISA IRQ, 0-15
+//
+// RETURNS: Some(...) -> the IRQ's wiring
+//          None      -> the IO APIC is not in use
+//==================================================================================================
+
+    IO_APIC.lock().as_ref().map(|io_apic| io_apic.isa_routes[irq as usize])
+}
+
+
+//==================================================================================================
+pub fn serves(gsi: u32) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether a global system interrupt can be routed through the IO APIC.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   gsi -> global system interrupt
+//
+// RETURNS: true if it can, false if the IO APIC does not serve it or is not in use
+//==================================================================================================
+
+    IO_APIC.lock().as_ref().map_or(false, |io_apic| io_apic.serves(gsi))
+}
+
+
+//==================================================================================================
+pub fn route(gsi: u32, vector: u8, trigger: TriggerMode, polarity: Polarity) {
+//--------------------------------------------------------------------------------------------------
+// Deliver a global system interrupt to a vector, unmasked.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   gsi      -> global system interrupt
+//          vector   -> vector to raise on the destination processor
+//          trigger  -> how the device signals the interrupt
+//          polarity -> which level of the line is asserted
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let io_apic = IO_APIC.lock();
+    let io_apic = io_apic.as_ref().expect("IO APIC not initialized");
+
+    let mut entry = vector as u64 | (io_apic.destination as u64) << DESTINATION_SHIFT;
+    if (trigger == TriggerMode::Level) { entry |= REDIRECTION_LEVEL; }
+    if (polarity == Polarity::ActiveLow) { entry |= REDIRECTION_ACTIVE_LOW; }
+
+    io_apic.write_entry(gsi, entry);
+}
+
+
+//==================================================================================================
+pub fn mask(gsi: u32) {
+//--------------------------------------------------------------------------------------------------
+// Stop a global system interrupt from being delivered, keeping the rest of its route.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   gsi -> global system interrupt
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let io_apic = IO_APIC.lock();
+    let io_apic = io_apic.as_ref().expect("IO APIC not initialized");
+
+    let entry = io_apic.read_entry(gsi);
+    io_apic.write_entry(gsi, entry | REDIRECTION_MASKED);
+}
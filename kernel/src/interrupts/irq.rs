@@ -0,0 +1,550 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/interrupts: irq.rs                                                                      #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use interrupts::{Idt, ExceptionStackFrame, HandlerFunc, without_interrupts};
+use interrupts::{pic, apic, ioapic};
+use interrupts::ioapic::{InterruptSourceOverride, TriggerMode, Polarity};
+use memory::paging::PhysicalAddress;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const IRQ_COUNT: usize = 16;                // Legacy IRQ lines, at vectors 32-47
+pub const DYNAMIC_COUNT: usize = 32;            // Vectors handed out by request_irq, 48-79
+pub const IRQ_BASE_VECTOR: u8 = pic::MASTER_OFFSET;
+pub const DYNAMIC_BASE_VECTOR: u8 = IRQ_BASE_VECTOR + IRQ_COUNT as u8;
+const SLOT_COUNT: usize = IRQ_COUNT + DYNAMIC_COUNT;
+
+
+//##################################################################################################
+//********************************** TYPE & STRUCT DEFINITIONS *************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub type IrqHandler = fn();
+//--------------------------------------------------------------------------------------------------
+// Function run, with interrupts disabled, whenever its IRQ is raised. The end of interrupt is
+// sent by the dispatcher once it returns.
+//==================================================================================================
+
+
+//==================================================================================================
+struct IrqTable {
+//--------------------------------------------------------------------------------------------------
+// Registered handlers and statistics for every vector IRQs may arrive on. Slot n serves vector
+// IRQ_BASE_VECTOR + n: the legacy lines first, then the vectors handed out by request_irq.
+//==================================================================================================
+
+    handlers: [Option<IrqHandler>; SLOT_COUNT], // Handler of each slot, None if unclaimed
+    gsis: [Option<u32>; SLOT_COUNT],            // Interrupt routed to each slot by the IO APIC
+    counts: [u64; SLOT_COUNT],                  // Genuine IRQs raised on each slot
+    spurious: u64,                              // Spurious IRQs from the PICs or local APIC
+}
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+// Only locked with interrupts disabled, so that an IRQ never finds it held on the same CPU
+static IRQS: Mutex<IrqTable> = Mutex::new(IrqTable {
+    handlers: [None; SLOT_COUNT],
+    gsis: [None; SLOT_COUNT],
+    counts: [0; SLOT_COUNT],
+    spurious: 0,
+});
+
+static APIC_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+
+//##################################################################################################
+//******************************************** MACROS **********************************************
+//##################################################################################################
+
+
+//==================================================================================================
+macro_rules! irq_handler {
+//--------------------------------------------------------------------------------------------------
+// Define the entry point of an IRQ slot, handing it to the dispatcher.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   name -> name of the entry point
+//          slot -> slot the entry point serves
+//==================================================================================================
+
+    ($name:ident, $slot:expr) => {
+        extern "x86-interrupt" fn $name(_stack_frame: &mut ExceptionStackFrame) {
+            dispatch($slot);
+        }
+    };
+}
+
+
+//##################################################################################################
+//******************************************* HANDLERS *********************************************
+//##################################################################################################
+
+
+irq_handler!(irq_0_handler, 0);
+irq_handler!(irq_1_handler, 1);
+irq_handler!(irq_2_handler, 2);
+irq_handler!(irq_3_handler, 3);
+irq_handler!(irq_4_handler, 4);
+irq_handler!(irq_5_handler, 5);
+irq_handler!(irq_6_handler, 6);
+irq_handler!(irq_7_handler, 7);
+irq_handler!(irq_8_handler, 8);
+irq_handler!(irq_9_handler, 9);
+irq_handler!(irq_10_handler, 10);
+irq_handler!(irq_11_handler, 11);
+irq_handler!(irq_12_handler, 12);
+irq_handler!(irq_13_handler, 13);
+irq_handler!(irq_14_handler, 14);
+irq_handler!(irq_15_handler, 15);
+irq_handler!(dynamic_0_handler, 16);
+irq_handler!(dynamic_1_handler, 17);
+irq_handler!(dynamic_2_handler, 18);
+irq_handler!(dynamic_3_handler, 19);
+irq_handler!(dynamic_4_handler, 20);
+irq_handler!(dynamic_5_handler, 21);
+irq_handler!(dynamic_6_handler, 22);
+irq_handler!(dynamic_7_handler, 23);
+irq_handler!(dynamic_8_handler, 24);
+irq_handler!(dynamic_9_handler, 25);
+irq_handler!(dynamic_10_handler, 26);
+irq_handler!(dynamic_11_handler, 27);
+irq_handler!(dynamic_12_handler, 28);
+irq_handler!(dynamic_13_handler, 29);
+irq_handler!(dynamic_14_handler, 30);
+irq_handler!(dynamic_15_handler, 31);
+irq_handler!(dynamic_16_handler, 32);
+irq_handler!(dynamic_17_handler, 33);
+irq_handler!(dynamic_18_handler, 34);
+irq_handler!(dynamic_19_handler, 35);
+irq_handler!(dynamic_20_handler, 36);
+irq_handler!(dynamic_21_handler, 37);
+irq_handler!(dynamic_22_handler, 38);
+irq_handler!(dynamic_23_handler, 39);
+irq_handler!(dynamic_24_handler, 40);
+irq_handler!(dynamic_25_handler, 41);
+irq_handler!(dynamic_26_handler, 42);
+irq_handler!(dynamic_27_handler, 43);
+irq_handler!(dynamic_28_handler, 44);
+irq_handler!(dynamic_29_handler, 45);
+irq_handler!(dynamic_30_handler, 46);
+irq_handler!(dynamic_31_handler, 47);
+
+
+//==================================================================================================
+extern "x86-interrupt" fn apic_spurious_handler(_stack_frame: &mut ExceptionStackFrame) {
+//--------------------------------------------------------------------------------------------------
+// Count an interrupt the local APIC withdrew before delivering it. No end of interrupt is sent,
+// as nothing was put in service.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   _stack_frame -> state pushed by the CPU
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    IRQS.lock().spurious += 1;
+}
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn install(idt: &mut Idt) {
+//--------------------------------------------------------------------------------------------------
+// Install the entry point of every IRQ slot, and the local APIC's spurious interrupt handler.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   idt -> table to install the entry points in
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let entries: [HandlerFunc; SLOT_COUNT] = [
+        irq_0_handler, irq_1_handler, irq_2_handler, irq_3_handler, irq_4_handler, irq_5_handler,
+        irq_6_handler, irq_7_handler, irq_8_handler, irq_9_handler, irq_10_handler, irq_11_handler,
+        irq_12_handler, irq_13_handler, irq_14_handler, irq_15_handler,
+        dynamic_0_handler, dynamic_1_handler, dynamic_2_handler, dynamic_3_handler,
+        dynamic_4_handler, dynamic_5_handler, dynamic_6_handler, dynamic_7_handler,
+        dynamic_8_handler, dynamic_9_handler, dynamic_10_handler, dynamic_11_handler,
+        dynamic_12_handler, dynamic_13_handler, dynamic_14_handler, dynamic_15_handler,
+        dynamic_16_handler, dynamic_17_handler, dynamic_18_handler, dynamic_19_handler,
+        dynamic_20_handler, dynamic_21_handler, dynamic_22_handler, dynamic_23_handler,
+        dynamic_24_handler, dynamic_25_handler, dynamic_26_handler, dynamic_27_handler,
+        dynamic_28_handler, dynamic_29_handler, dynamic_30_handler, dynamic_31_handler];
+
+    for (slot, entry) in entries.iter().enumerate() {
+        idt.set_handler(IRQ_BASE_VECTOR + slot as u8, *entry);
+    }
+
+    idt.set_handler(apic::SPURIOUS_VECTOR, apic_spurious_handler);
+}
+
+
+//==================================================================================================
+pub fn init_apic(io_apic_address: PhysicalAddress, gsi_base: u32,
+                 overrides: &[InterruptSourceOverride]) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Move IRQ delivery from the 8259 PICs to the local APIC and IO APIC. Handlers already
+// registered keep their IRQs, which are rerouted through the IO APIC.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   io_apic_address -> physical address of the IO APIC's register window
+//          gsi_base        -> first global system interrupt the IO APIC serves
+//          overrides       -> ISA IRQs not wired to the IO APIC input of the same number
+//
+// RETURNS: true  -> IRQs now arrive through the APICs
+//          false -> no local APIC or IO APIC could be brought up, and the PICs remain in use
+//==================================================================================================
+
+    without_interrupts(|| {
+        let local_apic = match apic::init() {
+            Some(local_apic) => local_apic,
+            None             => return false,
+        };
+
+        if (!ioapic::init(io_apic_address, gsi_base, local_apic.id(), overrides)) {
+            local_apic.pass_through_pic();
+            return false;
+        }
+
+        pic::disable();
+        APIC_ACTIVE.store(true, Ordering::SeqCst);
+
+        let mut irqs = IRQS.lock();
+        for irq in 0 .. IRQ_COUNT as u8 {
+            if (irqs.handlers[irq as usize].is_some()) {
+                irqs.gsis[irq as usize] = Some(route_isa_irq(irq));
+            }
+        }
+
+        true
+    })
+}
+
+
+//==================================================================================================
+pub fn register_irq_handler(irq: u8, handler: IrqHandler) {
+//--------------------------------------------------------------------------------------------------
+// Claim a legacy IRQ line and unmask it, at the PICs or, through any source override, at the IO
+// APIC. Panics if the line already has a handler.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   irq     -> IRQ line, 0-15
+//          handler -> function to run whenever the IRQ is raised
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    assert!((irq as usize) < IRQ_COUNT, "no such IRQ");
+
+    without_interrupts(|| {
+        let mut irqs = IRQS.lock();
+        assert!(irqs.handlers[irq as usize].is_none(), "IRQ {} already has a handler", irq);
+        irqs.handlers[irq as usize] = Some(handler);
+
+        if (APIC_ACTIVE.load(Ordering::SeqCst)) {
+            irqs.gsis[irq as usize] = Some(route_isa_irq(irq));
+        }
+        else {
+            pic::unmask(irq);
+        }
+    });
+}
+
+
+//==================================================================================================
+pub fn unregister_irq_handler(irq: u8) {
+//--------------------------------------------------------------------------------------------------
+// Mask a legacy IRQ line and drop its handler.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   irq -> IRQ line, 0-15
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    assert!((irq as usize) < IRQ_COUNT, "no such IRQ");
+    release_slot(irq as usize);
+}
+
+
+//==================================================================================================
+pub fn request_irq(gsi: u32, trigger: TriggerMode, polarity: Polarity, handler: IrqHandler)
+                   -> Option<u8> {
+//--------------------------------------------------------------------------------------------------
+// Route a global system interrupt, such as a PCI device's, to a free vector with the given
+// signalling, and run a handler whenever it is raised. Only possible once the APICs are in use.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   gsi      -> global system interrupt
+//          trigger  -> how the device signals the interrupt
+//          polarity -> which level of the line is asserted
+//          handler  -> function to run whenever the interrupt is raised
+//
+// RETURNS: Some(...) -> vector the interrupt was given
+//          None      -> the APICs are not in use, the IO APIC does not serve the interrupt, or
+//                       every vector is taken
+//==================================================================================================
+
+    if (!APIC_ACTIVE.load(Ordering::SeqCst) || !ioapic::serves(gsi)) {
+        return None;
+    }
+
+    without_interrupts(|| {
+        let vector = match claim_slot(handler, Some(gsi)) {
+            Some(slot) => IRQ_BASE_VECTOR + slot as u8,
+            None       => return None,
+        };
+
+        ioapic::route(gsi, vector, trigger, polarity);
+        Some(vector)
+    })
+}
+
+
+//==================================================================================================
+pub fn allocate_vector(handler: IrqHandler) -> Option<u8> {
+//--------------------------------------------------------------------------------------------------
+// Claim a free vector for an interrupt source wired to the local APIC, such as its timer, rather
+// than routed through the IO APIC. Only of use once the APICs are in use, as the end of interrupt
+// is sent to the local APIC.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   handler -> function to run whenever the vector is raised
+//
+// RETURNS: Some(...) -> the vector claimed
+//          None      -> the APICs are not in use, or every vector is taken
+//==================================================================================================
+
+    if (!APIC_ACTIVE.load(Ordering::SeqCst)) {
+        return None;
+    }
+
+    without_interrupts(|| claim_slot(handler, None).map(|slot| IRQ_BASE_VECTOR + slot as u8))
+}
+
+
+//==================================================================================================
+pub fn release_irq(vector: u8) {
+//--------------------------------------------------------------------------------------------------
+// Mask the interrupt routed to a vector by request_irq, if any, and free the vector.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   vector -> vector returned by request_irq or allocate_vector
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    assert!(vector >= DYNAMIC_BASE_VECTOR && vector < IRQ_BASE_VECTOR + SLOT_COUNT as u8,
+            "vector {} was not handed out dynamically", vector);
+    release_slot((vector - IRQ_BASE_VECTOR) as usize);
+}
+
+
+//==================================================================================================
+pub fn irq_count(irq: u8) -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Obtain the number of genuine IRQs raised on a legacy line since boot.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   irq -> IRQ line, 0-15
+//
+// RETURNS: the count, spurious IRQs excluded
+//==================================================================================================
+
+    without_interrupts(|| IRQS.lock().counts[irq as usize])
+}
+
+
+//==================================================================================================
+pub fn spurious_count() -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Obtain the number of spurious IRQs seen since boot.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: the count across the PICs' lines 7 and 15 and the local APIC's spurious vector
+//==================================================================================================
+
+    without_interrupts(|| IRQS.lock().spurious)
+}
+
+
+//==================================================================================================
+pub fn print_irq_counts() {
+//--------------------------------------------------------------------------------------------------
+// Print the count of every vector that has seen an IRQ, and the spurious count.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let (counts, spurious) = without_interrupts(|| {
+        let irqs = IRQS.lock();
+        (irqs.counts, irqs.spurious)
+    });
+
+    println!("vector     count");
+    for (slot, count) in counts.iter().enumerate().filter(|&(_, count)| *count != 0) {
+        println!("{:>6}  {:>8}", IRQ_BASE_VECTOR as usize + slot, count);
+    }
+    println!("spurious: {}", spurious);
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn route_isa_irq(irq: u8) -> u32 {
+//--------------------------------------------------------------------------------------------------
+// Route a legacy IRQ through the IO APIC to its usual vector.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   irq -> IRQ line, 0-15
+//
+// RETURNS: global system interrupt the IRQ arrives on
+//==================================================================================================
+
+    let route = ioapic::isa_route(irq).expect("IO APIC not initialized");
+    ioapic::route(route.gsi, IRQ_BASE_VECTOR + irq, route.trigger, route.polarity);
+    route.gsi
+}
+
+
+//==================================================================================================
+fn release_slot(slot: usize) {
+//--------------------------------------------------------------------------------------------------
+// Mask whatever is delivered to a slot and drop its handler.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   slot -> slot to free
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    without_interrupts(|| {
+        let mut irqs = IRQS.lock();
+
+        if (APIC_ACTIVE.load(Ordering::SeqCst)) {
+            if let Some(gsi) = irqs.gsis[slot] {
+                ioapic::mask(gsi);
+            }
+        }
+        else {
+            pic::mask(slot as u8);
+        }
+
+        irqs.handlers[slot] = None;
+        irqs.gsis[slot] = None;
+    });
+}
+
+
+//==================================================================================================
+fn claim_slot(handler: IrqHandler, gsi: Option<u32>) -> Option<usize> {
+//--------------------------------------------------------------------------------------------------
+// Hand the first free dynamic slot to a handler. Must be called with interrupts disabled.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   handler -> function to run whenever the slot's vector is raised
+//          gsi     -> interrupt the IO APIC is to route to the slot, if any
+//
+// RETURNS: Some(...) -> the slot claimed
+//          None      -> every dynamic slot is taken
+//==================================================================================================
+
+    let mut irqs = IRQS.lock();
+
+    let slot = match (IRQ_COUNT .. SLOT_COUNT).find(|slot| irqs.handlers[*slot].is_none()) {
+        Some(slot) => slot,
+        None       => return None,
+    };
+
+    irqs.handlers[slot] = Some(handler);
+    irqs.gsis[slot] = gsi;
+
+    Some(slot)
+}
+
+
+//==================================================================================================
+fn dispatch(slot: usize) {
+//--------------------------------------------------------------------------------------------------
+// Account for an IRQ, run its handler, and send the end of interrupt to whichever controller
+// delivered it. Spurious IRQs from the PICs are only counted. Runs with interrupts disabled, as
+// every IRQ gate is an interrupt gate.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   slot -> slot whose vector was raised
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let apic_active = APIC_ACTIVE.load(Ordering::SeqCst);
+
+    if (!apic_active && pic::is_spurious(slot as u8)) {
+        IRQS.lock().spurious += 1;
+
+        if (slot as u8 == pic::SPURIOUS_SLAVE_IRQ) {
+            pic::master_end_of_interrupt();
+        }
+        return;
+    }
+
+    // The lock is dropped before the handler runs, so that it may use this module itself
+    let handler = {
+        let mut irqs = IRQS.lock();
+        irqs.counts[slot] += 1;
+        irqs.handlers[slot]
+    };
+
+    if let Some(handler) = handler {
+        handler();
+    }
+
+    if (apic_active) {
+        apic::local_apic().unwrap().end_of_interrupt();
+    }
+    else {
+        pic::end_of_interrupt(slot as u8);
+    }
+}
@@ -0,0 +1,177 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/interrupts: mod.rs                                                                      #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//************************************* CRATES & SUBMODULES ****************************************
+//##################################################################################################
+
+
+mod idt;
+mod gdt;
+mod exceptions;
+mod irq;
+pub mod apic;
+pub mod ioapic;
+pub mod pic;
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+pub use self::idt::{Idt, ExceptionStackFrame, HandlerFunc, HandlerFuncWithErrCode};
+pub use self::gdt::unmap_guard_pages;
+pub use self::irq::{IrqHandler, init_apic, register_irq_handler, unregister_irq_handler,
+                    request_irq, allocate_vector, release_irq, irq_count, spurious_count,
+                    print_irq_counts};
+pub use self::ioapic::{InterruptSourceOverride, TriggerMode, Polarity};
+use spin::Once;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+const RFLAGS_INTERRUPT: u64 = 1 << 9;           // Maskable interrupts are enabled
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+static IDT: Once<Idt> = Once::new();
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn init() {
+//--------------------------------------------------------------------------------------------------
+// Load the kernel's GDT and TSS, then build the interrupt descriptor table with handlers for every
+// CPU exception and IRQ line and load it. The TSS must be loaded first, as some handlers use its
+// stacks. The PICs are remapped with every IRQ masked; interrupts stay disabled until enable.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    gdt::init();
+
+    let idt = IDT.call_once(|| {
+        let mut idt = Idt::new();
+        exceptions::install(&mut idt);
+        irq::install(&mut idt);
+        idt
+    });
+
+    idt.load();
+
+    pic::init();
+}
+
+
+//==================================================================================================
+pub fn enable() {
+//--------------------------------------------------------------------------------------------------
+// Allow maskable interrupts.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    unsafe { asm!("sti" :::: "volatile"); }
+}
+
+
+//==================================================================================================
+pub fn are_enabled() -> bool {
+//--------------------------------------------------------------------------------------------------
+// Determine whether maskable interrupts are enabled.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: true if they are, false otherwise
+//==================================================================================================
+
+    let flags: u64;
+    unsafe { asm!("pushfq; pop $0" : "=r" (flags) ::: "intel", "volatile"); }
+
+    flags & RFLAGS_INTERRUPT != 0
+}
+
+
+//==================================================================================================
+pub fn without_interrupts<F: FnOnce() -> R, R>(lambda: F) -> R {
+//--------------------------------------------------------------------------------------------------
+// Run a closure with maskable interrupts disabled, restoring them afterwards if they were enabled.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   lambda -> closure to run
+//
+// RETURNS: whatever the closure returns
+//==================================================================================================
+
+    let flags: u64;
+    unsafe { asm!("pushfq; pop $0; cli" : "=r" (flags) ::: "intel", "volatile"); }
+
+    let result = lambda();
+
+    if (flags & RFLAGS_INTERRUPT != 0) {
+        enable();
+    }
+
+    result
+}
+
+
+//==================================================================================================
+pub fn idle() -> ! {
+//--------------------------------------------------------------------------------------------------
+// Enable interrupts and halt until each one arrives, for when the kernel has nothing left to do.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: never
+//==================================================================================================
+
+    loop {
+        // sti takes effect after the next instruction, so no interrupt slips in before hlt
+        unsafe { asm!("sti; hlt" :::: "volatile"); }
+    }
+}
@@ -0,0 +1,254 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/interrupts: pic.rs                                                                      #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use x86::shared::io::{inb, outb};
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const MASTER_OFFSET: u8 = 32;               // Vector of IRQ 0, just past the exceptions
+pub const SLAVE_OFFSET: u8 = MASTER_OFFSET + 8; // Vector of IRQ 8
+
+pub const SPURIOUS_MASTER_IRQ: u8 = 7;          // Raised by the master for a vanished request
+pub const SPURIOUS_SLAVE_IRQ: u8 = 15;          // Raised by the slave for a vanished request
+
+const MASTER_COMMAND: u16 = 0x20;
+const MASTER_DATA: u16 = 0x21;
+const SLAVE_COMMAND: u16 = 0xA0;
+const SLAVE_DATA: u16 = 0xA1;
+const WAIT_PORT: u16 = 0x80;                    // Unused POST port, written to pause briefly
+
+const ICW1_INIT: u8 = 0x11;                     // Begin initialization, ICW4 follows
+const ICW3_MASTER: u8 = 1 << 2;                 // Slave is wired to the master's IRQ 2
+const ICW3_SLAVE: u8 = 2;                       // Slave's cascade identity
+const ICW4_8086: u8 = 0x01;                     // 8086 mode rather than 8080 mode
+const OCW3_READ_ISR: u8 = 0x0B;                 // Next command port read returns the ISR
+const END_OF_INTERRUPT: u8 = 0x20;
+
+const CASCADE_IRQ: u8 = 2;                      // Master input the slave is wired to
+const IRQS_PER_PIC: u8 = 8;
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn init() {
+//--------------------------------------------------------------------------------------------------
+// Remap both PICs so that IRQs 0-15 raise vectors MASTER_OFFSET through SLAVE_OFFSET + 7 rather
+// than colliding with the CPU exceptions, then mask every IRQ but the cascade. IRQs are unmasked
+// as handlers are registered for them.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    unsafe {
+        outb(MASTER_COMMAND, ICW1_INIT);
+        wait();
+        outb(SLAVE_COMMAND, ICW1_INIT);
+        wait();
+
+        outb(MASTER_DATA, MASTER_OFFSET);
+        wait();
+        outb(SLAVE_DATA, SLAVE_OFFSET);
+        wait();
+
+        outb(MASTER_DATA, ICW3_MASTER);
+        wait();
+        outb(SLAVE_DATA, ICW3_SLAVE);
+        wait();
+
+        outb(MASTER_DATA, ICW4_8086);
+        wait();
+        outb(SLAVE_DATA, ICW4_8086);
+        wait();
+
+        outb(MASTER_DATA, !(1 << CASCADE_IRQ));
+        outb(SLAVE_DATA, 0xFF);
+    }
+}
+
+
+//==================================================================================================
+pub fn disable() {
+//--------------------------------------------------------------------------------------------------
+// Mask every IRQ on both PICs, for when another interrupt controller takes over.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    unsafe {
+        outb(MASTER_DATA, 0xFF);
+        outb(SLAVE_DATA, 0xFF);
+    }
+}
+
+
+//==================================================================================================
+pub fn mask(irq: u8) {
+//--------------------------------------------------------------------------------------------------
+// Stop an IRQ from being raised.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   irq -> IRQ line, 0-15
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let port = data_port(irq);
+    unsafe { outb(port, inb(port) | 1 << (irq % IRQS_PER_PIC)); }
+}
+
+
+//==================================================================================================
+pub fn unmask(irq: u8) {
+//--------------------------------------------------------------------------------------------------
+// Allow an IRQ to be raised.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   irq -> IRQ line, 0-15
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let port = data_port(irq);
+    unsafe { outb(port, inb(port) & !(1 << (irq % IRQS_PER_PIC))); }
+}
+
+
+//==================================================================================================
+pub fn end_of_interrupt(irq: u8) {
+//--------------------------------------------------------------------------------------------------
+// Tell the PICs an IRQ has been handled, so that they raise further IRQs of its priority or
+// lower. IRQs from the slave pass through the master, so both are told.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   irq -> IRQ that was handled
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    unsafe {
+        if (irq >= IRQS_PER_PIC) {
+            outb(SLAVE_COMMAND, END_OF_INTERRUPT);
+        }
+        outb(MASTER_COMMAND, END_OF_INTERRUPT);
+    }
+}
+
+
+//==================================================================================================
+pub fn is_spurious(irq: u8) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether an IRQ 7 or IRQ 15 was spurious: a request that went away before the CPU
+// acknowledged it, which the PIC reports on its lowest priority line without marking it in
+// service. A spurious IRQ must not be acknowledged by the PIC that raised it, though a spurious
+// IRQ 15 still needs an end of interrupt for the master, which saw a real cascade request.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   irq -> IRQ that was raised
+//
+// RETURNS: true  -> the IRQ was spurious
+//          false -> the IRQ is in service, or cannot be spurious
+//==================================================================================================
+
+    let (port, line) = match irq {
+        SPURIOUS_MASTER_IRQ => (MASTER_COMMAND, SPURIOUS_MASTER_IRQ),
+        SPURIOUS_SLAVE_IRQ  => (SLAVE_COMMAND, SPURIOUS_SLAVE_IRQ - IRQS_PER_PIC),
+        _                   => return false,
+    };
+
+    let in_service = unsafe {
+        outb(port, OCW3_READ_ISR);
+        inb(port)
+    };
+
+    in_service & 1 << line == 0
+}
+
+
+//==================================================================================================
+pub fn master_end_of_interrupt() {
+//--------------------------------------------------------------------------------------------------
+// Acknowledge the cascade on the master alone, as needed after a spurious IRQ 15.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    unsafe { outb(MASTER_COMMAND, END_OF_INTERRUPT); }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn data_port(irq: u8) -> u16 {
+//--------------------------------------------------------------------------------------------------
+// Obtain the data port, holding the interrupt mask, of the PIC an IRQ belongs to.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   irq -> IRQ line, 0-15
+//
+// RETURNS: the master's or the slave's data port
+//==================================================================================================
+
+    assert!(irq < 2 * IRQS_PER_PIC, "no such IRQ");
+    if (irq < IRQS_PER_PIC) { MASTER_DATA } else { SLAVE_DATA }
+}
+
+
+//==================================================================================================
+unsafe fn wait() {
+//--------------------------------------------------------------------------------------------------
+// Pause for roughly a microsecond, giving an older PIC time to act on the last command.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    outb(WAIT_PORT, 0);
+}
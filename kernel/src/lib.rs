@@ -43,9 +43,14 @@
 
 
 #![feature(lang_items)] // allows us access to feature-gated modifications to core desugared functions
-#![feature(unique)]
+#![feature(ptr_internals)]
+#![feature(panic_info_message)]
 #![feature(const_fn)]
-#![no_std]              // disallow linking to standard libraries, we need to be static
+#![feature(alloc)]
+#![feature(alloc_error_handler)]
+#![feature(abi_x86_interrupt)]
+#![feature(asm)]
+#![cfg_attr(not(test), no_std)] // disallow linking to standard libraries, bar host unit tests
 #![allow(unused_parens)]
 
 
@@ -54,6 +59,7 @@
 //##################################################################################################
 
 
+#[cfg(not(test))]
 extern crate rlibc;                     // minimal libc-style ops 
 extern crate volatile;                  // mark operations as volatile to prevent optimizations
 extern crate spin;                      // minimal "busy-loop" mutex support
@@ -61,6 +67,7 @@ extern crate multiboot2;                // module to parse multiboot v2 info fro
 #[macro_use]
 extern crate bitflags;                  // bitflags used in paging
 extern crate x86;
+extern crate alloc;
 
 
 //==================================================================================================
@@ -69,21 +76,40 @@ extern crate x86;
 #[macro_use]
 pub mod vga_interface;                  // interface for more easily interacting with vga buffer
 mod memory;
+mod interrupts;
+mod cpu;
+mod protection;
+mod time;
+mod acpi;
 
 
 //==================================================================================================
 
 
-use memory::AlphaFrameAllocator;
+#[cfg(not(test))]
+use core::alloc::Layout;
+#[cfg(not(test))]
+use core::panic::PanicInfo;
+use alloc::vec::Vec;
+use memory::paging::PhysicalAddress;
+use interrupts::{InterruptSourceOverride, TriggerMode, Polarity};
+use acpi::MadtEntry;
 use x86::shared::msr::{IA32_EFER,rdmsr,wrmsr};
 use x86::shared::control_regs::{cr0,cr0_write,CR0_WRITE_PROTECT};
 
 
+//==================================================================================================
+
+
+const KERNEL_STACK_PAGES: usize = 16;   // Size of the stack rust_main hands over to kernel_main
+
+
 //##################################################################################################
 //***************************** BOILER-PLATE NO_STDLIB REQUIREMENTS ********************************
 //##################################################################################################
 
 
+#[cfg(not(test))]
 #[allow(non_snake_case)]
 #[no_mangle]
 //==================================================================================================
@@ -100,6 +126,7 @@ pub extern "C" fn _Unwind_Resume() -> ! {
 }
 
 
+#[cfg(not(test))]
 #[lang = "eh_personality"]
 //==================================================================================================
 extern fn eh_personality() {
@@ -115,26 +142,47 @@ extern fn eh_personality() {
 }
 
 
-#[lang = "panic_fmt"]
-#[no_mangle]
+#[cfg(not(test))]
+#[panic_handler]
 //==================================================================================================
-pub extern fn panic_fmt(fmt: core::fmt::Arguments, file: &'static str, line: u32)  -> ! {
+fn panic(info: &PanicInfo) -> ! {
 //--------------------------------------------------------------------------------------------------
 // Prints a message handed to the panic! macro, as well as info about where the panic! occured.
 //--------------------------------------------------------------------------------------------------
-// TAKES:   fmt  -> panic message as a series of arguments
-//          file -> file in which panic occured
-//          line -> line on which panic occured
+// TAKES:   info -> panic message and the file and line on which panic occured
 //
 // RETURNS: never
 //==================================================================================================
 
-    println!("\n\nPANIC in {} at line {}:", file, line);
-    println!("   {}", fmt);
+    match info.location() {
+        Some(location) => println!("\n\nPANIC in {} at line {}:", location.file(), location.line()),
+        None           => println!("\n\nPANIC:"),
+    }
+
+    if let Some(fmt) = info.message() {
+        println!("   {}", fmt);
+    }
     loop {};
 }
 
 
+#[cfg(not(test))]
+#[alloc_error_handler]
+//==================================================================================================
+fn alloc_error(layout: Layout) -> ! {
+//--------------------------------------------------------------------------------------------------
+// Called when the kernel heap cannot satisfy an allocation, even after attempting to grow.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   layout -> size and alignment of the failed allocation
+//
+// RETURNS: never
+//==================================================================================================
+
+    panic!("kernel heap exhausted allocating {} bytes aligned to {}",
+           layout.size(), layout.align());
+}
+
+
 //##################################################################################################
 //********************************************* UTILITIES ******************************************
 //##################################################################################################
@@ -156,6 +204,49 @@ fn enable_write_protection() {
     }
 }
 
+
+//==================================================================================================
+fn interrupt_wiring() -> (PhysicalAddress, u32, Vec<InterruptSourceOverride>) {
+//--------------------------------------------------------------------------------------------------
+// Learn from the MADT where the IO APIC serving the ISA IRQs lies and how the IRQs are wired to
+// it. Without an MADT, assume the usual wiring: a single IO APIC at its default address, with the
+// PIT on input 2.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: the IO APIC's address, the first interrupt it serves, and the ISA IRQ overrides
+//==================================================================================================
+
+    let madt = match acpi::madt() {
+        Some(madt) => madt,
+        None       => {
+            let pit = InterruptSourceOverride {
+                irq: 0, gsi: 2, trigger: TriggerMode::Edge, polarity: Polarity::ActiveHigh,
+            };
+            let mut overrides = Vec::new();
+            overrides.push(pit);
+            return (PhysicalAddress::new(interrupts::ioapic::DEFAULT_ADDRESS), 0, overrides);
+        },
+    };
+
+    let (address, gsi_base) = madt.entries()
+        .filter_map(|entry| match entry {
+            MadtEntry::IoApic { address, gsi_base, .. } => Some((address, gsi_base)),
+            _                                           => None,
+        })
+        .min_by_key(|&(_, gsi_base)| gsi_base)
+        .unwrap_or((PhysicalAddress::new(interrupts::ioapic::DEFAULT_ADDRESS), 0));
+
+    let overrides = madt.entries()
+        .filter_map(|entry| match entry {
+            MadtEntry::Override(source_override) => Some(source_override),
+            _                                    => None,
+        })
+        .collect();
+
+    (address, gsi_base, overrides)
+}
+
 //##################################################################################################
 //*********************************************** MAIN *********************************************
 //##################################################################################################
@@ -168,31 +259,67 @@ pub extern fn rust_main(multiboot_info_start: usize) {
 
     vga_interface::WRITER.lock().clear_screen();
 
-    let boot_info = unsafe { multiboot2::load(multiboot_info_start) };
+    // The boot page tables map the first GiB of physical memory at KERNEL_OFFSET as well
+    let boot_info = unsafe {
+        multiboot2::load(memory::paging::KERNEL_OFFSET + multiboot_info_start)
+    };
 
-    let memory_map_tag = boot_info.memory_map_tag().expect("Need memory map tag!");
+    interrupts::init();
 
-    let elf_sections_tag = boot_info.elf_sections_tag().expect("Need ELF sections tag");
+    enable_write_protection();
+    protection::init();
 
-    let kernel_start = elf_sections_tag.sections().map(|s| s.addr).min().unwrap();
+    memory::init(boot_info);
 
-    let kernel_end = elf_sections_tag.sections().map(|s| s.addr + s.size).max().unwrap();
-    
-    let multiboot_end = multiboot_info_start + (boot_info.total_size as usize);
+    // The multiboot information is only at hand here, so find the RSDP before leaving
+    if (!acpi::init(boot_info)) {
+        println!("no ACPI tables found");
+    }
 
-    let mut frame_allocator = memory::AlphaFrameAllocator::new(kernel_start as usize, kernel_end as usize,
-                                                               multiboot_info_start, multiboot_end,
-                                                               memory_map_tag.memory_areas());
-    
-    enable_write_protection();
+    // The boot stack has no guard page, so leave it before it can overflow into the page tables
+    let stack = memory::allocate_stack(KERNEL_STACK_PAGES).expect("no memory for kernel stack");
+    unsafe { stack.switch_to(kernel_main); }
+}
 
-    memory::paging::remap_kernel(&mut frame_allocator, boot_info);
+
+//==================================================================================================
+fn kernel_main() -> ! {
+//--------------------------------------------------------------------------------------------------
+// Remainder of start-up, run on a kernel stack guarded against overflow.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: never
+//==================================================================================================
+
+    {
+        let mut controller = memory::MEMORY_CONTROLLER.lock();
+        let controller = controller.as_mut().unwrap();
+        interrupts::unmap_guard_pages(&mut controller.active_table,
+                                      &mut controller.frame_allocator);
+        protection::audit_wx(&controller.active_table);
+    }
+
+    acpi::print_tables();
+
+    let (io_apic_address, gsi_base, overrides) = interrupt_wiring();
+    if (!interrupts::init_apic(io_apic_address, gsi_base, &overrides)) {
+        println!("no APIC, IRQs remain with the 8259 PICs");
+    }
+
+    // Without an HPET table, look for one where firmware usually places it
+    let hpet_address = acpi::hpet().map_or(PhysicalAddress::new(time::hpet::DEFAULT_ADDRESS),
+                                           |hpet| hpet.address);
+    // Without a FADT to say otherwise, assume the RTC every PC has
+    let fadt = acpi::fadt();
+    time::init(hpet_address, fadt.as_ref().map_or(true, |fadt| fadt.has_cmos_rtc()),
+               fadt.and_then(|fadt| fadt.century_register));
+    time::print_clocks();
+
+    memory::frame_table::print_usage();
 
     println!("It works!");
-    use memory::FrameAllocator;
-    frame_allocator.allocate_frame();
-    
-    println!("Still working!");
-    loop {}
+
+    interrupts::idle();
 }
 
@@ -1,12 +1,26 @@
 BITS 64
 	
+	%define KERNEL_OFFSET 0xFFFF800000000000	; Must match linker.ld
+
 global long_mode_start
 
-section .text
+extern stack_top
+extern rust_main		; obtain access to rust_main procedure
+
+;; Entered from 32-bit code, so this must still run at its physical address
+section .boot progbits alloc exec nowrite
 long_mode_start:
-	extern rust_main		; obtain access to rust_main procedure
+	mov rax, qword higher_half_start
+	jmp rax
+
+section .text
+higher_half_start:
+	;; Switch to the stack's higher half address
+	mov rsp, qword stack_top
+
 	call rust_main
 	
 	mov rax, 0x2f592f412f4b2f4f 	; OKAY in white on green
-	mov qword [0xb8000], rax
+	mov rdi, qword KERNEL_OFFSET + 0xb8000
+	mov qword [rdi], rax
 	hlt
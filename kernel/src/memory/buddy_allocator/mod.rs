@@ -0,0 +1,501 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/memory: buddy_allocator.rs                                                              #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::{Frame, FrameAllocator, PAGE_SIZE, MAX_FRAME_COUNT};
+use memory::paging::{PhysicalAddress, FrameRange};
+use memory::frame_table::{self, FrameUsage};
+use multiboot2::MemoryAreaIter;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const MAX_ORDER: usize = 10;        // Largest block is 2^10 frames (4 MiB)
+pub const HUGE_PAGE_ORDER: usize = 9;   // Order of a block backing a 2 MiB huge page
+const ORDER_COUNT: usize = MAX_ORDER + 1;
+const BITS_PER_WORD: usize = 64;
+
+// The bitmap for order n holds MAX_FRAME_COUNT >> n bits, so all orders together fit in twice the
+// space of the order 0 bitmap.
+const BITMAP_WORDS: usize = 2 * MAX_FRAME_COUNT / BITS_PER_WORD;
+const USABLE_WORDS: usize = MAX_FRAME_COUNT / BITS_PER_WORD;
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+// Backing storage for the per-order free bitmaps. Lives in .bss so that it is covered by the kernel
+// mapping and never needs frames of its own.
+static mut BUDDY_BITMAPS: [u64; BITMAP_WORDS] = [0; BITMAP_WORDS];
+
+// One bit per frame, set for every frame seeded into the allocator at construction. Frames without
+// their bit were never usable memory and must never be handed back.
+static mut USABLE_FRAMES: [u64; USABLE_WORDS] = [0; USABLE_WORDS];
+static BITMAPS_CLAIMED: AtomicBool = AtomicBool::new(false);
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub struct BuddyAllocator {
+//--------------------------------------------------------------------------------------------------
+// Frame allocator handing out naturally aligned blocks of 2^order physically contiguous frames.
+// Each order keeps a bitmap with one bit per block of that order; a set bit marks the block as
+// free. Blocks are split when a smaller order runs dry and merged with their buddy when both
+// halves are free again.
+//==================================================================================================
+
+    bitmaps: &'static mut [u64; BITMAP_WORDS],
+    usable: &'static mut [u64; USABLE_WORDS],
+    word_offsets: [usize; ORDER_COUNT], // Index of the first word of each order's bitmap
+    free_blocks: [usize; ORDER_COUNT],  // Number of free blocks of each order
+    next_word: [usize; ORDER_COUNT],    // Word at which the next search of each order begins
+    frame_limit: usize,                 // One past the highest usable frame number
+    total_frames: usize,                // Number of usable frames found in the memory map
+    free_frames: usize,                 // Number of usable frames not currently allocated
+}
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl BuddyAllocator {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn new(kernel_start: PhysicalAddress, kernel_end: PhysicalAddress,
+               multiboot_start: PhysicalAddress, multiboot_end: PhysicalAddress,
+               memory_areas: MemoryAreaIter) -> BuddyAllocator {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for BuddyAllocator. Every frame lying entirely inside a usable memory area
+    // is freed into the allocator, except for frames overlapping the kernel or the multiboot
+    // information structure. May only be called once, as all instances share the same bitmaps.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   kernel_start    -> starting address of the kernel
+    //          kernel_end      -> ending address of the kernel
+    //          multiboot_start -> starting address of the multiboot information structure
+    //          multiboot_end   -> ending address of the multiboot information structure
+    //          memory_areas    -> iterator over usable sections of memory
+    //
+    // RETURNS: a buddy allocator seeded with all usable memory
+    //==============================================================================================
+
+        assert!(!BITMAPS_CLAIMED.swap(true, Ordering::SeqCst),
+                "BuddyAllocator may only be constructed once");
+
+        let mut word_offsets = [0; ORDER_COUNT];
+        for order in 1 .. ORDER_COUNT {
+            word_offsets[order] = word_offsets[order - 1] + words_in_order(order - 1);
+        }
+
+        let mut allocator = BuddyAllocator {
+            bitmaps: unsafe { &mut BUDDY_BITMAPS },
+            usable: unsafe { &mut USABLE_FRAMES },
+            word_offsets: word_offsets,
+            free_blocks: [0; ORDER_COUNT],
+            next_word: [0; ORDER_COUNT],
+            frame_limit: 0,
+            total_frames: 0,
+            free_frames: 0,
+        };
+
+        for area in memory_areas {
+            // Only frames fully contained in the area may be used
+            let first_frame = (area.base_addr as usize + PAGE_SIZE - 1) / PAGE_SIZE;
+            let end_frame = (area.base_addr + area.length) as usize / PAGE_SIZE;
+
+            for frame_num in first_frame .. end_frame {
+                if (frame_num >= MAX_FRAME_COUNT) {
+                    break;
+                }
+
+                let frame = Frame { frame_num: frame_num };
+                if (frame.overlaps(kernel_start, kernel_end) ||
+                    frame.overlaps(multiboot_start, multiboot_end)) {
+                    frame_table::set_usage(&frame, FrameUsage::Reserved);
+                    continue;
+                }
+
+                frame_table::set_usage(&frame, FrameUsage::Free);
+                allocator.usable[frame_num / BITS_PER_WORD] |= bit_mask(frame_num);
+
+                // Freeing frames one by one lets the buddy merging build the largest blocks
+                allocator.free_block(frame_num, 0);
+                allocator.total_frames += 1;
+                allocator.free_frames += 1;
+
+                if (frame_num >= allocator.frame_limit) {
+                    allocator.frame_limit = frame_num + 1;
+                }
+            }
+        }
+
+        allocator
+    }
+
+
+    //==============================================================================================
+    pub fn allocate_frames(&mut self, order: usize) -> Option<Frame> {
+    //----------------------------------------------------------------------------------------------
+    // Attempt to allocate a block of 2^order physically contiguous frames, aligned to its size.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   order -> base two logarithm of the number of frames in the block
+    //
+    // RETURNS: Some(...) -> first frame of the allocated block
+    //          None      -> no block of the requested order could be formed
+    //==============================================================================================
+
+        assert!(order <= MAX_ORDER, "buddy allocations are limited to order {}", MAX_ORDER);
+
+        // Find the smallest order at least as large as requested with a free block
+        let mut current = order;
+        while (current <= MAX_ORDER && self.free_blocks[current] == 0) {
+            current += 1;
+        }
+
+        if (current > MAX_ORDER) {
+            return None;
+        }
+
+        let mut block = self.find_free_block(current).expect("buddy free counts out of sync");
+        self.mark_used(current, block);
+
+        // Split the block down to the requested order, freeing the upper half at each step
+        while (current > order) {
+            current -= 1;
+            block *= 2;
+            self.mark_free(current, block + 1);
+        }
+
+        self.free_frames -= 1 << order;
+
+        let frame_num = block << order;
+        let end = Frame { frame_num: frame_num + (1 << order) };
+        frame_table::set_range_usage(FrameRange::new(Frame { frame_num: frame_num }, end),
+                                     FrameUsage::Allocated);
+
+        Some(Frame { frame_num: frame_num })
+    }
+
+
+    //==============================================================================================
+    pub fn deallocate_frames(&mut self, frame: Frame, order: usize) {
+    //----------------------------------------------------------------------------------------------
+    // Return a block previously obtained from allocate_frames, merging it with its buddy wherever
+    // possible. Panics if the block is misaligned, covers a frame that was never usable memory, or
+    // is wholly or partly free already, or if any of its frames is still mapped or pinned.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   frame -> first frame of the block
+    //          order -> order the block was allocated with
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        assert!(order <= MAX_ORDER, "buddy allocations are limited to order {}", MAX_ORDER);
+        assert!(frame.frame_num % (1 << order) == 0,
+                "frame {:#x} is not aligned to a block of order {}", frame.address(), order);
+        assert!(frame.frame_num + (1 << order) <= self.frame_limit,
+                "deallocated block {:#x} lies outside of managed memory", frame.address());
+
+        for frame_num in frame.frame_num .. frame.frame_num + (1 << order) {
+            assert!(self.usable[frame_num / BITS_PER_WORD] & bit_mask(frame_num) != 0,
+                    "deallocated block {:#x} covers frame {:#x}, which is not usable memory",
+                    frame.address(), frame_num * PAGE_SIZE);
+        }
+
+        // A free block at this order or above containing the frame means it was never allocated
+        for containing_order in order .. ORDER_COUNT {
+            assert!(!self.is_block_free(containing_order, frame.frame_num >> containing_order),
+                    "double free of block {:#x} with order {}", frame.address(), order);
+        }
+
+        // A free block below this order inside the block means part of it was already returned
+        for sub_order in 0 .. order {
+            let first = frame.frame_num >> sub_order;
+            for sub_block in first .. first + (1 << (order - sub_order)) {
+                assert!(!self.is_block_free(sub_order, sub_block),
+                        "double free of block {:#x} with order {}: sub-block {:#x} is already free",
+                        frame.address(), order, (sub_block << sub_order) * PAGE_SIZE);
+            }
+        }
+
+        let end = Frame { frame_num: frame.frame_num + (1 << order) };
+        frame_table::mark_free(FrameRange::new(Frame { frame_num: frame.frame_num }, end));
+
+        self.free_block(frame.frame_num >> order, order);
+        self.free_frames += 1 << order;
+    }
+
+
+    //==============================================================================================
+    pub fn total_frames(&self) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the number of usable frames managed by this allocator.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: count of usable frames
+    //==============================================================================================
+
+        self.total_frames
+    }
+
+
+    //==============================================================================================
+    pub fn free_frames(&self) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the number of frames currently available for allocation.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: count of free frames
+    //==============================================================================================
+
+        self.free_frames
+    }
+
+
+    //==============================================================================================
+    pub fn used_frames(&self) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the number of frames currently allocated.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: count of allocated frames
+    //==============================================================================================
+
+        self.total_frames - self.free_frames
+    }
+
+
+    //==============================================================================================
+    pub fn free_blocks(&self, order: usize) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the number of free blocks of exactly the given order.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   order -> order of the blocks to count
+    //
+    // RETURNS: count of free blocks
+    //==============================================================================================
+
+        self.free_blocks[order]
+    }
+
+
+    //==============================================================================================
+    fn free_block(&mut self, block: usize, order: usize) {
+    //----------------------------------------------------------------------------------------------
+    // Mark a block as free, repeatedly merging it with its buddy while the buddy is free as well.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   block -> index of the block within its order
+    //          order -> order of the block
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        let mut block = block;
+        let mut order = order;
+
+        while (order < MAX_ORDER && self.is_block_free(order, block ^ 1)) {
+            self.mark_used(order, block ^ 1);
+            block /= 2;
+            order += 1;
+        }
+
+        self.mark_free(order, block);
+    }
+
+
+    //==============================================================================================
+    fn find_free_block(&mut self, order: usize) -> Option<usize> {
+    //----------------------------------------------------------------------------------------------
+    // Search an order's bitmap for a free block, starting at the word of the last allocation and
+    // wrapping around to the beginning of the bitmap.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   order -> order of the block to find
+    //
+    // RETURNS: Some(...) -> index of a free block within the order
+    //          None      -> no free blocks of this order
+    //==============================================================================================
+
+        let word_count = words_in_order(order);
+        let start = self.next_word[order];
+
+        for word_num in (start .. word_count).chain(0 .. start) {
+            let word = self.bitmaps[self.word_offsets[order] + word_num];
+            if (word != 0) {
+                self.next_word[order] = word_num;
+                return Some(word_num * BITS_PER_WORD + word.trailing_zeros() as usize);
+            }
+        }
+
+        None
+    }
+
+
+    //==============================================================================================
+    fn is_block_free(&self, order: usize, block: usize) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether a block is free at exactly the given order.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   order -> order of the block
+    //          block -> index of the block within its order
+    //
+    // RETURNS: true  -> block is free
+    //          false -> block is allocated, split, or merged into a larger block
+    //==============================================================================================
+
+        if (block >= MAX_FRAME_COUNT >> order) {
+            return false;
+        }
+
+        self.bitmaps[self.word_offsets[order] + block / BITS_PER_WORD] & bit_mask(block) != 0
+    }
+
+
+    //==============================================================================================
+    fn mark_free(&mut self, order: usize, block: usize) {
+    //----------------------------------------------------------------------------------------------
+    // Set a block's bit in its order's bitmap.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   order -> order of the block
+    //          block -> index of the block within its order
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        self.bitmaps[self.word_offsets[order] + block / BITS_PER_WORD] |= bit_mask(block);
+        self.free_blocks[order] += 1;
+    }
+
+
+    //==============================================================================================
+    fn mark_used(&mut self, order: usize, block: usize) {
+    //----------------------------------------------------------------------------------------------
+    // Clear a block's bit in its order's bitmap.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   order -> order of the block
+    //          block -> index of the block within its order
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        self.bitmaps[self.word_offsets[order] + block / BITS_PER_WORD] &= !bit_mask(block);
+        self.free_blocks[order] -= 1;
+    }
+}
+
+
+//==================================================================================================
+impl FrameAllocator for BuddyAllocator {
+//==================================================================================================
+
+
+    //==============================================================================================
+    fn allocate_frame(&mut self) -> Option<Frame> {
+    //----------------------------------------------------------------------------------------------
+    // Attempt to allocate a frame.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: Some(...) -> A frame object, if a frame was available
+    //          None      -> No frames available
+    //==============================================================================================
+
+        self.allocate_frames(0)
+    }
+
+
+    //==============================================================================================
+    fn deallocate_frame(&mut self, frame: Frame) {
+    //----------------------------------------------------------------------------------------------
+    // Deallocate a frame.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   frame -> the frame to deallocate
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        self.deallocate_frames(frame, 0);
+    }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn words_in_order(order: usize) -> usize {
+//--------------------------------------------------------------------------------------------------
+// Obtain the number of bitmap words needed to track every block of an order.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   order -> order of the bitmap
+//
+// RETURNS: number of 64-bit words in the order's bitmap
+//==================================================================================================
+
+    ((MAX_FRAME_COUNT >> order) + BITS_PER_WORD - 1) / BITS_PER_WORD
+}
+
+
+//==================================================================================================
+fn bit_mask(block: usize) -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Obtain the mask selecting a block's bit within its bitmap word.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   block -> index of the block within its order
+//
+// RETURNS: mask with only the block's bit set
+//==================================================================================================
+
+    1 << (block % BITS_PER_WORD)
+}
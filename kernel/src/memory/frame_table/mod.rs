@@ -0,0 +1,413 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/memory: frame_table.rs                                                                  #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::{Frame, MAX_FRAME_COUNT};
+use memory::paging::FrameRange;
+use core::u16;
+use spin::Mutex;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+const USAGE_COUNT: usize = 7;
+
+const EMPTY_DESCRIPTOR: FrameDescriptor = FrameDescriptor {
+    refcount: 0,
+    usage: FrameUsage::Unusable,
+    flags: FrameFlags { bits: 0 },
+};
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+//==================================================================================================
+pub enum FrameUsage {
+//--------------------------------------------------------------------------------------------------
+// What a frame of physical memory is currently used for.
+//==================================================================================================
+
+    Unusable,                           // Not RAM handed to the frame allocator
+    Reserved,                           // Holds the kernel image or the multiboot information
+    Free,                               // Owned by the frame allocator
+    Allocated,                          // Handed out, but not yet mapped or put to a known use
+    PageTable,                          // Holds a page table of some level
+    Mapped,                             // Mapped by one or more 4 KiB page table entries
+    Slab,                               // Backs a slab of the slab allocator
+}
+
+
+//==================================================================================================
+bitflags! { pub flags FrameFlags: u8 {
+//--------------------------------------------------------------------------------------------------
+// Conditions attached to a frame, independent of its usage.
+//==================================================================================================
+
+    const PINNED = 1 << 0,              // Frame must stay allocated, such as a DMA buffer
+  }
+}
+
+
+#[derive(Clone, Copy)]
+//==================================================================================================
+pub struct FrameDescriptor {
+//--------------------------------------------------------------------------------------------------
+// Bookkeeping for one frame of physical memory.
+//==================================================================================================
+
+    pub refcount: u16,                  // 4 KiB mappings of the frame made through PTMapper
+    pub usage: FrameUsage,              // What the frame is used for
+    pub flags: FrameFlags,              // Conditions attached to the frame
+}
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+// One descriptor per frame the allocators may hand out, indexed by frame number. Always locked
+// after MEMORY_CONTROLLER when both are needed.
+static FRAME_TABLE: Mutex<[FrameDescriptor; MAX_FRAME_COUNT]> =
+    Mutex::new([EMPTY_DESCRIPTOR; MAX_FRAME_COUNT]);
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl FrameUsage {
+//==================================================================================================
+
+
+    //==============================================================================================
+    fn name(&self) -> &'static str {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the name printed for this usage in the usage dump.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: name of the usage
+    //==============================================================================================
+
+        match *self {
+            FrameUsage::Unusable  => "unusable",
+            FrameUsage::Reserved  => "reserved",
+            FrameUsage::Free      => "free",
+            FrameUsage::Allocated => "allocated",
+            FrameUsage::PageTable => "page table",
+            FrameUsage::Mapped    => "mapped",
+            FrameUsage::Slab      => "slab",
+        }
+    }
+}
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn descriptor(frame: &Frame) -> FrameDescriptor {
+//--------------------------------------------------------------------------------------------------
+// Obtain a copy of a frame's descriptor.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frame -> frame to look up
+//
+// RETURNS: the frame's descriptor; frames beyond the table are reported as unusable
+//==================================================================================================
+
+    if (frame.frame_num >= MAX_FRAME_COUNT) {
+        return EMPTY_DESCRIPTOR;
+    }
+
+    FRAME_TABLE.lock()[frame.frame_num]
+}
+
+
+//==================================================================================================
+pub fn set_usage(frame: &Frame, usage: FrameUsage) {
+//--------------------------------------------------------------------------------------------------
+// Record what a frame is used for.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frame -> frame to update
+//          usage -> new usage of the frame
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    if (frame.frame_num < MAX_FRAME_COUNT) {
+        FRAME_TABLE.lock()[frame.frame_num].usage = usage;
+    }
+}
+
+
+//==================================================================================================
+pub fn set_range_usage(frames: FrameRange, usage: FrameUsage) {
+//--------------------------------------------------------------------------------------------------
+// Record what every frame of a range is used for, under a single acquisition of the lock.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frames -> frames to update
+//          usage  -> new usage of the frames
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let mut table = FRAME_TABLE.lock();
+
+    for frame in frames.into_iter().take_while(|frame| frame.frame_num < MAX_FRAME_COUNT) {
+        table[frame.frame_num].usage = usage;
+    }
+}
+
+
+//==================================================================================================
+pub fn mark_free(frames: FrameRange) {
+//--------------------------------------------------------------------------------------------------
+// Record that a range of frames went back to the frame allocator. Panics if any of them is
+// still mapped or pinned, as freeing it would hand live memory to someone else.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frames -> frames being freed
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let mut table = FRAME_TABLE.lock();
+
+    for frame in frames.into_iter().take_while(|frame| frame.frame_num < MAX_FRAME_COUNT) {
+        let descriptor = &mut table[frame.frame_num];
+
+        assert!(descriptor.refcount == 0, "freed frame {:#x} is still mapped {} times",
+                frame.address(), descriptor.refcount);
+        assert!(!descriptor.flags.contains(PINNED), "freed frame {:#x} is pinned",
+                frame.address());
+
+        descriptor.usage = FrameUsage::Free;
+    }
+}
+
+
+//==================================================================================================
+pub fn map(frame: &Frame) -> usize {
+//--------------------------------------------------------------------------------------------------
+// Count a new 4 KiB mapping of a frame. A frame that was merely allocated becomes Mapped; any
+// more specific usage is kept.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frame -> frame being mapped
+//
+// RETURNS: number of mappings of the frame, including the new one
+//==================================================================================================
+
+    if (frame.frame_num >= MAX_FRAME_COUNT) {
+        return 1;
+    }
+
+    let mut table = FRAME_TABLE.lock();
+    let descriptor = &mut table[frame.frame_num];
+
+    assert!(descriptor.refcount < u16::MAX, "frame {:#x} mapped too many times",
+            frame.address());
+
+    descriptor.refcount += 1;
+    if (descriptor.usage == FrameUsage::Allocated) {
+        descriptor.usage = FrameUsage::Mapped;
+    }
+
+    descriptor.refcount as usize
+}
+
+
+//==================================================================================================
+pub fn unmap(frame: &Frame) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Drop one 4 KiB mapping of a frame. Frames mapped by huge pages, and frames beyond the table,
+// are not counted: a count of zero means the frame was never seen being mapped, not that it is
+// free, so it is left alone rather than reported as unreferenced.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frame -> frame being unmapped
+//
+// RETURNS: true  -> the last counted mapping went away and the frame came from the frame
+//                   allocator, so the allocator should have it back
+//          false -> the frame is still mapped elsewhere, pinned, untracked, or not allocator memory
+//==================================================================================================
+
+    if (frame.frame_num >= MAX_FRAME_COUNT) {
+        return false;
+    }
+
+    let mut table = FRAME_TABLE.lock();
+    let descriptor = &mut table[frame.frame_num];
+
+    if (descriptor.refcount == 0) {
+        return false;
+    }
+
+    descriptor.refcount -= 1;
+    descriptor.refcount == 0 && !descriptor.flags.contains(PINNED) &&
+        is_allocated(descriptor.usage)
+}
+
+
+//==================================================================================================
+pub fn is_reclaimable(frame: &Frame) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether an uncounted frame, such as one behind a huge mapping, may be handed back to the
+// frame allocator: the allocator handed it out, and nothing maps or pins it.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frame -> frame to check
+//
+// RETURNS: true  -> the frame may be freed
+//          false -> the frame is mapped, pinned, or not allocator memory
+//==================================================================================================
+
+    let descriptor = descriptor(frame);
+    descriptor.refcount == 0 && !descriptor.flags.contains(PINNED) &&
+        is_allocated(descriptor.usage)
+}
+
+
+//==================================================================================================
+pub fn refcount(frame: &Frame) -> usize {
+//--------------------------------------------------------------------------------------------------
+// Obtain the number of 4 KiB mappings of a frame.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frame -> frame to look up
+//
+// RETURNS: number of mappings made through PTMapper
+//==================================================================================================
+
+    descriptor(frame).refcount as usize
+}
+
+
+//==================================================================================================
+pub fn pin(frame: &Frame) {
+//--------------------------------------------------------------------------------------------------
+// Keep a frame from being freed when its last mapping goes away, for memory a device may access
+// behind the kernel's back. Pinning an already pinned frame does nothing.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frame -> frame to pin
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    assert!(frame.frame_num < MAX_FRAME_COUNT, "frame {:#x} cannot be pinned", frame.address());
+    FRAME_TABLE.lock()[frame.frame_num].flags.insert(PINNED);
+}
+
+
+//==================================================================================================
+pub fn unpin(frame: &Frame) {
+//--------------------------------------------------------------------------------------------------
+// Allow a pinned frame to be freed again. The caller frees it if it is no longer mapped.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frame -> frame to unpin
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    if (frame.frame_num < MAX_FRAME_COUNT) {
+        FRAME_TABLE.lock()[frame.frame_num].flags.remove(PINNED);
+    }
+}
+
+
+//==================================================================================================
+pub fn print_usage() {
+//--------------------------------------------------------------------------------------------------
+// Print the number of frames in each usage category, along with how many are shared by several
+// mappings and how many are pinned. Walks the whole table, so it is meant for debugging only.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let usages = [FrameUsage::Unusable, FrameUsage::Reserved, FrameUsage::Free,
+                  FrameUsage::Allocated, FrameUsage::PageTable, FrameUsage::Mapped,
+                  FrameUsage::Slab];
+
+    let mut counts = [0usize; USAGE_COUNT];
+    let mut shared = 0;
+    let mut pinned = 0;
+
+    {
+        let table = FRAME_TABLE.lock();
+
+        for descriptor in table.iter() {
+            counts[descriptor.usage as usize] += 1;
+            if (descriptor.refcount > 1) { shared += 1; }
+            if (descriptor.flags.contains(PINNED)) { pinned += 1; }
+        }
+    }
+
+    println!("     usage    frames");
+    for usage in usages.iter() {
+        println!("{:>10}  {:>8}", usage.name(), counts[*usage as usize]);
+    }
+    println!("{:>10}  {:>8}", "shared", shared);
+    println!("{:>10}  {:>8}", "pinned", pinned);
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn is_allocated(usage: FrameUsage) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether a usage marks a frame handed out by the frame allocator for general use, as
+// opposed to a page table, a slab, the kernel image or memory the allocator never owned.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   usage -> usage of the frame
+//
+// RETURNS: true if the frame belongs back with the allocator once unused, false otherwise
+//==================================================================================================
+
+    usage == FrameUsage::Allocated || usage == FrameUsage::Mapped
+}
@@ -0,0 +1,474 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/memory: heap.rs                                                                         #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::PAGE_SIZE;
+use memory::paging::{demand, VirtualAddress};
+use memory::paging::entry::{WRITABLE, NO_EXEC};
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem;
+use core::ptr;
+use spin::Mutex;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const HEAP_START: usize = 0o177777_401_000_000_000_0000;  // Page map entry 257, higher half
+pub const HEAP_INITIAL_SIZE: usize = 64 * PAGE_SIZE;
+pub const HEAP_MAX_SIZE: usize = 0x1000_0000;               // 256 MiB of reserved virtual memory
+const HEAP_GROWTH_STEP: usize = 16 * PAGE_SIZE;             // Minimum size of a single growth
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+struct Hole {
+//--------------------------------------------------------------------------------------------------
+// Header written at the start of every free region of the heap, linking it into the free list.
+//==================================================================================================
+
+    size: usize,                        // Size of the region, including this header
+    next: *mut Hole,                    // Next free region at a higher address
+}
+
+
+//==================================================================================================
+pub struct Heap {
+//--------------------------------------------------------------------------------------------------
+// First-fit linked list allocator. Free regions are kept in a list sorted by address so that
+// neighbouring regions can be merged when memory is returned.
+//==================================================================================================
+
+    start: usize,                       // First address of the heap
+    end: usize,                         // One past the last address handed to the heap
+    limit: usize,                       // One past the last address the heap may grow to
+    used: usize,                        // Bytes currently handed out
+    head: Hole,                         // Empty placeholder heading the free list
+}
+
+
+//==================================================================================================
+pub struct LockedHeap(Mutex<Heap>);
+//--------------------------------------------------------------------------------------------------
+// Heap wrapped in a spinlock so that it may serve as the kernel's global allocator.
+//==================================================================================================
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+#[cfg_attr(not(test), global_allocator)]
+pub static HEAP_ALLOCATOR: LockedHeap = LockedHeap(Mutex::new(Heap::empty()));
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+// Holes live inside the heap itself and are only touched while the lock is held
+unsafe impl Send for Heap {}
+
+
+//==================================================================================================
+impl Heap {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub const fn empty() -> Heap {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for a heap with no memory. Must be given memory with init before use.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: an empty heap
+    //==============================================================================================
+
+        Heap {
+            start: 0,
+            end: 0,
+            limit: 0,
+            used: 0,
+            head: Hole { size: 0, next: 0 as *mut Hole },
+        }
+    }
+
+
+    //==============================================================================================
+    pub unsafe fn init(&mut self, start: usize, size: usize, limit: usize) {
+    //----------------------------------------------------------------------------------------------
+    // Hand the heap its initial memory. Unsafe, as [start, start + size) must be usable, either
+    // mapped or demand-paged, and [start, limit) must be reserved for the heap alone.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   start -> first address of the heap, aligned to a hole
+    //          size  -> number of bytes usable from the start
+    //          limit -> one past the last address the heap may ever grow to
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        assert!(start % mem::size_of::<Hole>() == 0, "heap start must be aligned to a hole");
+
+        self.start = start;
+        self.end = start;
+        self.limit = limit;
+        self.extend(size);
+    }
+
+
+    //==============================================================================================
+    pub unsafe fn extend(&mut self, size: usize) {
+    //----------------------------------------------------------------------------------------------
+    // Grow the heap by size bytes past its current end. Unsafe, as the new memory must be usable
+    // and lie within the heap's limit.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   size -> number of bytes to add, a multiple of the hole size
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        assert!(self.end + size <= self.limit, "heap extended past its limit");
+
+        let region = self.end;
+        self.end += size;
+        self.free_region(region, size);
+    }
+
+
+    //==============================================================================================
+    pub fn allocate(&mut self, layout: &Layout) -> Option<*mut u8> {
+    //----------------------------------------------------------------------------------------------
+    // Carve a block satisfying the layout out of the first free region large enough to hold it.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   layout -> size and alignment of the requested block
+    //
+    // RETURNS: Some(...) -> pointer to the start of the block
+    //          None      -> no free region can hold the block
+    //==============================================================================================
+
+        let size = hole_size(layout.size());
+        let align = if (layout.align() > mem::size_of::<Hole>()) { layout.align() }
+                    else { mem::size_of::<Hole>() };
+
+        let mut previous: *mut Hole = &mut self.head;
+
+        unsafe {
+            while (!(*previous).next.is_null()) {
+                let hole = (*previous).next;
+                let hole_start = hole as usize;
+                let hole_end = hole_start + (*hole).size;
+                let block_start = align_up(hole_start, align);
+                let block_end = block_start + size;
+
+                if (block_end <= hole_end) {
+                    // Unlink the hole, then hand back whatever the block does not cover. Both
+                    // remainders are multiples of the hole size, so each is either empty or large
+                    // enough to hold a header.
+                    (*previous).next = (*hole).next;
+
+                    if (block_end < hole_end) {
+                        let back = block_end as *mut Hole;
+                        let remainder = Hole { size: hole_end - block_end, next: (*previous).next };
+                        ptr::write(back, remainder);
+                        (*previous).next = back;
+                    }
+
+                    if (block_start > hole_start) {
+                        (*hole).size = block_start - hole_start;
+                        (*hole).next = (*previous).next;
+                        (*previous).next = hole;
+                    }
+
+                    self.used += size;
+                    return Some(block_start as *mut u8);
+                }
+
+                previous = hole;
+            }
+        }
+
+        None
+    }
+
+
+    //==============================================================================================
+    pub unsafe fn deallocate(&mut self, block: *mut u8, layout: &Layout) {
+    //----------------------------------------------------------------------------------------------
+    // Return a block to the heap. Unsafe, as the block must have been obtained from allocate with
+    // the same layout.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   block  -> pointer to the start of the block
+    //          layout -> layout the block was allocated with
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        let size = hole_size(layout.size());
+        assert!(block as usize >= self.start && block as usize + size <= self.end,
+                "deallocated block {:#x} lies outside of the heap", block as usize);
+
+        self.used -= size;
+        self.free_region(block as usize, size);
+    }
+
+
+    //==============================================================================================
+    pub fn size(&self) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the number of bytes currently handed to the heap.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: current size of the heap
+    //==============================================================================================
+
+        self.end - self.start
+    }
+
+
+    //==============================================================================================
+    pub fn used(&self) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the number of bytes currently handed out.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: bytes in use
+    //==============================================================================================
+
+        self.used
+    }
+
+
+    //==============================================================================================
+    unsafe fn free_region(&mut self, start: usize, size: usize) {
+    //----------------------------------------------------------------------------------------------
+    // Insert a region into the free list, keeping it sorted by address and merging the region with
+    // adjacent free regions.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   start -> first address of the region
+    //          size  -> size of the region
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        let head: *mut Hole = &mut self.head;
+        let mut previous = head;
+
+        while (!(*previous).next.is_null() && ((*previous).next as usize) < start) {
+            previous = (*previous).next;
+        }
+
+        let next = (*previous).next;
+        let hole = start as *mut Hole;
+        ptr::write(hole, Hole { size: size, next: next });
+
+        // Merge with the following region
+        if (!next.is_null() && start + size == next as usize) {
+            (*hole).size += (*next).size;
+            (*hole).next = (*next).next;
+        }
+
+        // Merge with the preceding region, which is never the placeholder head
+        if (previous != head && previous as usize + (*previous).size == start) {
+            (*previous).size += (*hole).size;
+            (*previous).next = (*hole).next;
+        }
+        else {
+            (*previous).next = hole;
+        }
+    }
+}
+
+
+//==================================================================================================
+impl LockedHeap {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub unsafe fn init(&self, start: usize, size: usize, limit: usize) {
+    //----------------------------------------------------------------------------------------------
+    // Hand the wrapped heap its initial memory. See Heap::init.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   start -> first address of the heap, aligned to a hole
+    //          size  -> number of bytes usable from the start
+    //          limit -> one past the last address the heap may ever grow to
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        self.0.lock().init(start, size, limit);
+    }
+}
+
+
+//==================================================================================================
+unsafe impl GlobalAlloc for LockedHeap {
+//==================================================================================================
+
+
+    //==============================================================================================
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    //----------------------------------------------------------------------------------------------
+    // Allocate a block from the heap, growing the heap as long as the request cannot be met.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   layout -> size and alignment of the requested block
+    //
+    // RETURNS: pointer to the block, or null if the heap could not grow to fit it
+    //==============================================================================================
+
+        let mut heap = self.0.lock();
+
+        loop {
+            if let Some(block) = heap.allocate(&layout) {
+                return block;
+            }
+
+            if (!grow(&mut heap, hole_size(layout.size()) + layout.align())) {
+                return ptr::null_mut();
+            }
+        }
+    }
+
+
+    //==============================================================================================
+    unsafe fn dealloc(&self, block: *mut u8, layout: Layout) {
+    //----------------------------------------------------------------------------------------------
+    // Return a block to the heap.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   block  -> pointer to the start of the block
+    //          layout -> layout the block was allocated with
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        self.0.lock().deallocate(block, &layout);
+    }
+}
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn init() {
+//--------------------------------------------------------------------------------------------------
+// Reserve the heap's virtual region for demand paging and hand its initial part to the global
+// allocator. Frames are only mapped as the heap touches its pages, so the memory controller must
+// already be published and the page fault handler installed.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    demand::register_region(VirtualAddress::new(HEAP_START),
+                            VirtualAddress::new(HEAP_START + HEAP_MAX_SIZE), WRITABLE | NO_EXEC,
+                            "heap");
+    unsafe { HEAP_ALLOCATOR.init(HEAP_START, HEAP_INITIAL_SIZE, HEAP_START + HEAP_MAX_SIZE); }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn grow(heap: &mut Heap, min_size: usize) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Extend the heap further into its reserved region. The new pages are backed by the page fault
+// handler when first touched, so growing never takes the memory controller's lock.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   heap     -> heap to grow
+//          min_size -> minimum number of bytes to add
+//
+// RETURNS: true  -> the heap grew by at least min_size bytes
+//          false -> the heap hit its limit
+//==================================================================================================
+
+    let size = align_up(if (min_size > HEAP_GROWTH_STEP) { min_size } else { HEAP_GROWTH_STEP },
+                        PAGE_SIZE);
+
+    if (heap.end + size > heap.limit) {
+        return false;
+    }
+
+    unsafe { heap.extend(size); }
+    true
+}
+
+
+//==================================================================================================
+fn hole_size(size: usize) -> usize {
+//--------------------------------------------------------------------------------------------------
+// Round a requested size up so that it can later be turned back into a hole.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   size -> requested size in bytes
+//
+// RETURNS: size rounded up to a multiple of the hole header's size
+//==================================================================================================
+
+    let size = if (size < mem::size_of::<Hole>()) { mem::size_of::<Hole>() } else { size };
+    align_up(size, mem::size_of::<Hole>())
+}
+
+
+//==================================================================================================
+fn align_up(addr: usize, align: usize) -> usize {
+//--------------------------------------------------------------------------------------------------
+// Round an address up to the next multiple of a power of two.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   addr  -> address to round
+//          align -> power of two to align to
+//
+// RETURNS: the smallest multiple of align not below addr
+//==================================================================================================
+
+    (addr + align - 1) & !(align - 1)
+}
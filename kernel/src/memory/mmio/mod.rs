@@ -0,0 +1,434 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/memory: mmio.rs                                                                         #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::{MEMORY_CONTROLLER, FrameAllocator, PAGE_SIZE};
+use memory::paging::{Page, PageRange, FrameRange, ActivePageTable, VirtualAddress,
+                     PhysicalAddress};
+use memory::paging::entry::{EntryFlags, WRITABLE, NO_EXEC, WRITETHROUGH, NO_CACHE, PAT};
+use ::x86::shared::msr::{rdmsr, wrmsr};
+use ::x86::shared::tlb;
+use core::ptr;
+use cpu;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const MMIO_START: usize = 0o177777_405_000_000_000_0000;   // Page map entry 261, higher half
+pub const MMIO_SIZE: usize = 0x40_0000_0000;                  // 256 GiB of reserved virtual memory
+const MAX_MAPPINGS: usize = 128;                // Number of mappings that may exist at once
+
+const IA32_PAT: u32 = 0x277;
+
+// Power-on PAT with entry 4 changed from write-back to write-combining. Entries 0-3 keep their
+// defaults, so WRITETHROUGH and NO_CACHE mean what they always have when the PAT bit is clear.
+const PAT_VALUE: u64 = 0x0007_0401_0007_0406;
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+//==================================================================================================
+pub enum MemoryType {
+//--------------------------------------------------------------------------------------------------
+// Caching behaviour of a mapping.
+//==================================================================================================
+
+    Uncacheable,                        // Every access goes to the device, in order
+    WriteCombining,                     // Writes may be buffered and merged, reads are uncached
+    WriteThrough,                       // Reads are cached, writes go straight through
+    WriteBack,                          // Fully cached, as for ordinary memory
+}
+
+
+//==================================================================================================
+pub struct IoMapping {
+//--------------------------------------------------------------------------------------------------
+// Physical range mapped into the MMIO window by ioremap. The range is unmapped when dropped,
+// which locks MEMORY_CONTROLLER; it must not be dropped while the lock is held.
+//==================================================================================================
+
+    pages: PageRange,                   // Pages of the window backing the mapping
+    address: VirtualAddress,            // Virtual address of the first byte mapped
+    physical: PhysicalAddress,          // Physical address of the first byte mapped
+    size: usize,                        // Length of the mapped range in bytes
+}
+
+
+//==================================================================================================
+pub struct MmioAllocator {
+//--------------------------------------------------------------------------------------------------
+// Allocator of virtual ranges in the MMIO window. In-use ranges are kept in a fixed table, and new
+// ranges are placed in the lowest gap large enough to hold them.
+//==================================================================================================
+
+    used: [Option<PageRange>; MAX_MAPPINGS],    // Ranges currently handed out
+}
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl MemoryType {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn entry_flags(&self) -> EntryFlags {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the page table entry bits selecting this memory type through the PAT programmed by
+    // init. Only valid in 4 KiB entries, as the PAT bit sits elsewhere in huge entries.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: flags to add to a 4 KiB entry
+    //==============================================================================================
+
+        match *self {
+            MemoryType::WriteBack      => EntryFlags::empty(),
+            MemoryType::WriteThrough   => WRITETHROUGH,
+            MemoryType::Uncacheable    => WRITETHROUGH | NO_CACHE,
+            MemoryType::WriteCombining => {
+                // Without a PAT the bit is ignored, so fall back to the strongest type
+                if (cpu::supports_pat()) { PAT } else { WRITETHROUGH | NO_CACHE }
+            },
+        }
+    }
+}
+
+
+//==================================================================================================
+impl IoMapping {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn virtual_address(&self) -> VirtualAddress {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the address at which the start of the physical range is mapped.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: virtual address of the first byte mapped
+    //==============================================================================================
+
+        self.address
+    }
+
+
+    //==============================================================================================
+    pub fn physical_address(&self) -> PhysicalAddress {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the start of the physical range mapped.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: physical address of the first byte mapped
+    //==============================================================================================
+
+        self.physical
+    }
+
+
+    //==============================================================================================
+    pub fn size(&self) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the length of the physical range mapped.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: size in bytes
+    //==============================================================================================
+
+        self.size
+    }
+
+
+    //==============================================================================================
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+    //----------------------------------------------------------------------------------------------
+    // Read a register with a single volatile access.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   offset -> byte offset of the register from the start of the range
+    //
+    // RETURNS: value read
+    //==============================================================================================
+
+        unsafe { ptr::read_volatile(self.register::<T>(offset)) }
+    }
+
+
+    //==============================================================================================
+    pub fn write<T: Copy>(&self, offset: usize, value: T) {
+    //----------------------------------------------------------------------------------------------
+    // Write a register with a single volatile access.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   offset -> byte offset of the register from the start of the range
+    //          value  -> value to write
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        unsafe { ptr::write_volatile(self.register::<T>(offset), value) }
+    }
+
+
+    //==============================================================================================
+    fn register<T>(&self, offset: usize) -> *mut T {
+    //----------------------------------------------------------------------------------------------
+    // Obtain a pointer to a register, checking that it lies within the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   offset -> byte offset of the register from the start of the range
+    //
+    // RETURNS: pointer to the register
+    //==============================================================================================
+
+        assert!(offset + ::core::mem::size_of::<T>() <= self.size, "register outside mapping");
+        (self.address + offset).as_mut_ptr::<T>()
+    }
+}
+
+
+//==================================================================================================
+impl Drop for IoMapping {
+//==================================================================================================
+
+
+    //==============================================================================================
+    fn drop(&mut self) {
+    //----------------------------------------------------------------------------------------------
+    // Unmap the range and return its virtual pages to the window. The frames belong to the
+    // device, so they are never handed to the frame allocator.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        let mut controller = MEMORY_CONTROLLER.lock();
+        let controller = controller.as_mut().expect("memory not initialized");
+
+        controller.mmio_allocator.unmap(self.pages, &mut controller.active_table,
+                                        &mut controller.frame_allocator);
+    }
+}
+
+
+//==================================================================================================
+impl MmioAllocator {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub const fn new() -> MmioAllocator {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for an MmioAllocator with the whole window free.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: an empty MmioAllocator
+    //==============================================================================================
+
+        MmioAllocator { used: [None; MAX_MAPPINGS] }
+    }
+
+
+    //==============================================================================================
+    pub fn map<A: FrameAllocator>(&mut self, physical: PhysicalAddress, size: usize,
+                                  memory_type: MemoryType, active_table: &mut ActivePageTable,
+                                  allocator: &mut A) -> Option<IoMapping> {
+    //----------------------------------------------------------------------------------------------
+    // Map a physical range into the window with the given memory type. The range need not be
+    // page aligned; the mapping covers every page it touches.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   physical     -> first physical address to map
+    //          size         -> length of the range in bytes
+    //          memory_type  -> caching behaviour of the mapping
+    //          active_table -> page table to map the range in
+    //          allocator    -> allocator to allocate tables from
+    //
+    // RETURNS: Some(...) -> handle to the mapping
+    //          None      -> the range is empty or the window has no room for it
+    //==============================================================================================
+
+        if (size == 0) {
+            return None;
+        }
+
+        let frames = FrameRange::containing(physical, physical + size);
+
+        let slot = match self.used.iter().position(|used| used.is_none()) {
+            Some(slot) => slot,
+            None       => return None,
+        };
+        let pages = match self.find_gap(frames.len()) {
+            Some(pages) => pages,
+            None        => return None,
+        };
+        self.used[slot] = Some(pages);
+
+        active_table.map_range_to_frames(pages, frames,
+                                         WRITABLE | NO_EXEC | memory_type.entry_flags(),
+                                         allocator);
+
+        Some(IoMapping {
+            pages: pages,
+            address: pages.start().starting_address() + physical.as_usize() % PAGE_SIZE,
+            physical: physical,
+            size: size,
+        })
+    }
+
+
+    //==============================================================================================
+    fn unmap<A: FrameAllocator>(&mut self, pages: PageRange, active_table: &mut ActivePageTable,
+                                allocator: &mut A) {
+    //----------------------------------------------------------------------------------------------
+    // Unmap a range handed out by map and free it for reuse.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   pages        -> pages of the window to unmap
+    //          active_table -> page table the range is mapped in
+    //          allocator    -> allocator receiving any emptied tables
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        let slot = self.used.iter().position(|used| *used == Some(pages))
+            .expect("MMIO range not mapped");
+
+        for page in pages {
+            active_table.unmap_shared(page, allocator);
+        }
+
+        self.used[slot] = None;
+    }
+
+
+    //==============================================================================================
+    fn find_gap(&self, count: usize) -> Option<PageRange> {
+    //----------------------------------------------------------------------------------------------
+    // Find the lowest run of free pages in the window long enough for a mapping.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   count -> number of pages needed
+    //
+    // RETURNS: Some(...) -> free range of count pages
+    //          None      -> no gap is large enough
+    //==============================================================================================
+
+        let window_end = Page::containing_address(VirtualAddress::new(MMIO_START + MMIO_SIZE));
+        let mut start = Page::containing_address(VirtualAddress::new(MMIO_START));
+
+        loop {
+            if (window_end.starting_address() - start.starting_address() < count * PAGE_SIZE) {
+                return None;
+            }
+
+            let candidate = PageRange::containing(start.starting_address(),
+                                                  start.starting_address() + count * PAGE_SIZE);
+
+            // Restart just past any range in the way; ranges only ever push the start upwards
+            match self.used.iter().filter_map(|used| *used)
+                      .find(|used| used.intersection(&candidate).is_some()) {
+                Some(used) => start = used.end(),
+                None       => return Some(candidate),
+            }
+        }
+    }
+}
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn init() {
+//--------------------------------------------------------------------------------------------------
+// Program the PAT so that WriteCombining mappings can be made. Must run before any page uses the
+// PAT bit. Does nothing on processors without a PAT.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    if (!cpu::supports_pat()) {
+        return;
+    }
+
+    unsafe {
+        if (rdmsr(IA32_PAT) != PAT_VALUE) {
+            wrmsr(IA32_PAT, PAT_VALUE);
+
+            // Nothing may stay cached or translated under the old memory types
+            asm!("wbinvd" :::: "volatile");
+            tlb::flush_all();
+        }
+    }
+}
+
+
+//==================================================================================================
+pub fn ioremap(physical: PhysicalAddress, size: usize, memory_type: MemoryType)
+               -> Option<IoMapping> {
+//--------------------------------------------------------------------------------------------------
+// Map a physical range, such as a device's register window, into the MMIO window through
+// MEMORY_CONTROLLER.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   physical    -> first physical address to map
+//          size        -> length of the range in bytes
+//          memory_type -> caching behaviour of the mapping
+//
+// RETURNS: Some(...) -> handle to the mapping, which unmaps the range when dropped
+//          None      -> the range is empty or the window has no room for it
+//==================================================================================================
+
+    let mut controller = MEMORY_CONTROLLER.lock();
+    let controller = controller.as_mut().expect("memory not initialized");
+
+    controller.mmio_allocator.map(physical, size, memory_type, &mut controller.active_table,
+                                  &mut controller.frame_allocator)
+}
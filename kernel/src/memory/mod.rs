@@ -43,13 +43,25 @@
 
 
 pub use self::alpha_frame_allocator::AlphaFrameAllocator;
-use self::paging::PhysicalAddress;
+pub use self::buddy_allocator::BuddyAllocator;
+pub use self::mmio::{MmioAllocator, IoMapping, MemoryType, ioremap};
+pub use self::slab_allocator::SlabAllocator;
+pub use self::stack_allocator::{StackAllocator, Stack, allocate_stack};
+use self::paging::{PhysicalAddress,VirtualAddress,ActivePageTable};
+use multiboot2::BootInformation;
+use spin::Mutex;
 
 //==================================================================================================
 
 
 pub mod alpha_frame_allocator;
+pub mod buddy_allocator;
+pub mod frame_table;
+pub mod heap;
+pub mod mmio;
 pub mod paging;
+pub mod slab_allocator;
+pub mod stack_allocator;
 
 //##################################################################################################
 //*********************************** STATIC & CONST DATA ******************************************
@@ -57,6 +69,13 @@ pub mod paging;
 
 
 pub const PAGE_SIZE: usize = 4096;
+pub const MAX_FRAME_COUNT: usize = 0x1_0000_0000 / PAGE_SIZE;  // Frames in the first 4 GiB
+
+
+//==================================================================================================
+
+
+pub static MEMORY_CONTROLLER: Mutex<Option<MemoryController>> = Mutex::new(None);
 
 
 //##################################################################################################
@@ -98,6 +117,22 @@ pub trait FrameAllocator {
 //##################################################################################################
 
 
+//==================================================================================================
+pub struct MemoryController {
+//--------------------------------------------------------------------------------------------------
+// Owner of the kernel's page table and frame allocator once memory has been initialized. Code that
+// holds the lock on MEMORY_CONTROLLER must not touch unbacked demand-paged memory, such as fresh
+// heap pages, as the page fault handler needs the same lock to back them.
+//==================================================================================================
+
+    pub active_table: ActivePageTable,
+    pub frame_allocator: BuddyAllocator,
+    pub slab_allocator: SlabAllocator,
+    pub stack_allocator: StackAllocator,
+    pub mmio_allocator: MmioAllocator,
+}
+
+
 #[derive(Eq, PartialEq, Ord, PartialOrd, Debug)]
 //==================================================================================================
 pub struct Frame {
@@ -127,7 +162,7 @@ impl Frame {
     //
     //==============================================================================================
 
-        Frame { frame_num: address / PAGE_SIZE }
+        Frame { frame_num: address.as_usize() / PAGE_SIZE }
     }
 
 
@@ -141,7 +176,38 @@ impl Frame {
     //
     //==============================================================================================
 
-        self.frame_num * PAGE_SIZE
+        PhysicalAddress::new(self.frame_num * PAGE_SIZE)
+    }
+
+
+    //==============================================================================================
+    pub fn virtual_address(&self) -> VirtualAddress {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the address at which this frame may be accessed through the linear map of physical
+    // memory. Only valid once the kernel has been remapped.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: virtual address of the start of the frame
+    //==============================================================================================
+
+        paging::phys_to_virt(self.address())
+    }
+
+
+    //==============================================================================================
+    pub fn overlaps(&self, start: PhysicalAddress, end: PhysicalAddress) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether this frame shares any memory with the range [start, end).
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   start -> starting address of the range
+    //          end   -> ending address of the range
+    //
+    // RETURNS: true  -> frame overlaps the range
+    //          false -> frame and range are disjoint
+    //==============================================================================================
+
+        self.address() < end && self.address() + PAGE_SIZE > start
     }
 
     //===============================================================================================
@@ -157,3 +223,55 @@ impl Frame {
         Frame { frame_num: self.frame_num }
     }
 }
+
+
+//##################################################################################################
+//**************************************** PUBLIC FUNCTIONS ****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn init(boot_info: &BootInformation) {
+//--------------------------------------------------------------------------------------------------
+// Set up physical and virtual memory management: program the PAT, enable PCIDs, seed the frame
+// allocator from the memory map, remap the kernel, publish everything in MEMORY_CONTROLLER, and
+// reserve the demand-paged heap.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   boot_info -> multiboot information structure handed to the kernel
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    mmio::init();
+    paging::pcid::init();
+
+    let memory_map_tag = boot_info.memory_map_tag().expect("Need memory map tag!");
+
+    let elf_sections_tag = boot_info.elf_sections_tag().expect("Need ELF sections tag");
+
+    // Sections are linked in the higher half, but the allocator deals in physical addresses
+    let kernel_start = elf_sections_tag.sections().filter(|s| s.is_allocated())
+        .map(|s| paging::kernel_to_phys(VirtualAddress::new(s.start_address()))).min().unwrap();
+
+    let kernel_end = elf_sections_tag.sections().filter(|s| s.is_allocated())
+        .map(|s| paging::kernel_to_phys(VirtualAddress::new(s.end_address()))).max().unwrap();
+
+    let multiboot_start = paging::kernel_to_phys(VirtualAddress::new(boot_info.start_address()));
+    let multiboot_end = paging::kernel_to_phys(VirtualAddress::new(boot_info.end_address()));
+
+    let mut frame_allocator = BuddyAllocator::new(kernel_start, kernel_end,
+                                                  multiboot_start, multiboot_end,
+                                                  memory_map_tag.memory_areas());
+
+    let active_table = paging::remap_kernel(&mut frame_allocator, boot_info);
+
+    *MEMORY_CONTROLLER.lock() = Some(MemoryController {
+        active_table: active_table,
+        frame_allocator: frame_allocator,
+        slab_allocator: SlabAllocator::new(),
+        stack_allocator: StackAllocator::new(),
+        mmio_allocator: MmioAllocator::new(),
+    });
+
+    heap::init();
+}
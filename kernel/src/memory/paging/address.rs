@@ -0,0 +1,553 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/memory/paging: address.rs                                                               #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+const VIRTUAL_ADDRESS_BITS: usize = 48;        // Bits translated by 4-level paging
+const PHYSICAL_ADDRESS_BITS: usize = 52;       // Architectural limit on physical addresses
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+//==================================================================================================
+pub struct PhysicalAddress(usize);
+//--------------------------------------------------------------------------------------------------
+// Address in physical memory. Never dereferenced directly; see paging::phys_to_virt.
+//==================================================================================================
+
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+//==================================================================================================
+pub struct VirtualAddress(usize);
+//--------------------------------------------------------------------------------------------------
+// Canonical address in the virtual address space: bits 48-63 all equal bit 47.
+//==================================================================================================
+
+
+#[derive(Clone, Copy, Debug)]
+//==================================================================================================
+pub struct NonCanonicalAddress(pub usize);
+//--------------------------------------------------------------------------------------------------
+// Error returned when a value is not a canonical virtual address.
+//==================================================================================================
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl PhysicalAddress {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn new(address: usize) -> PhysicalAddress {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for PhysicalAddress. Panics if the address cannot exist.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   address -> raw physical address, below 2^52
+    //
+    // RETURNS: the wrapped address
+    //==============================================================================================
+
+        assert!(address >> PHYSICAL_ADDRESS_BITS == 0,
+                "physical address {:#x} exceeds 52 bits", address);
+        PhysicalAddress(address)
+    }
+
+
+    //==============================================================================================
+    pub fn as_usize(&self) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the raw address.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the address as a usize
+    //==============================================================================================
+
+        self.0
+    }
+
+
+    //==============================================================================================
+    pub fn as_u64(&self) -> u64 {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the raw address in the width used by page table entries and registers.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the address as a u64
+    //==============================================================================================
+
+        self.0 as u64
+    }
+
+
+    //==============================================================================================
+    pub fn align_up(&self, align: usize) -> PhysicalAddress {
+    //----------------------------------------------------------------------------------------------
+    // Round this address up to a multiple of align.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   align -> alignment, a power of two
+    //
+    // RETURNS: the lowest aligned address at or above this one
+    //==============================================================================================
+
+        PhysicalAddress::new(align_up(self.0, align))
+    }
+
+
+    //==============================================================================================
+    pub fn align_down(&self, align: usize) -> PhysicalAddress {
+    //----------------------------------------------------------------------------------------------
+    // Round this address down to a multiple of align.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   align -> alignment, a power of two
+    //
+    // RETURNS: the highest aligned address at or below this one
+    //==============================================================================================
+
+        PhysicalAddress(align_down(self.0, align))
+    }
+
+
+    //==============================================================================================
+    pub fn is_aligned(&self, align: usize) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether this address is a multiple of align.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   align -> alignment, a power of two
+    //
+    // RETURNS: true  -> the address is aligned
+    //          false -> the address is not aligned
+    //==============================================================================================
+
+        align_down(self.0, align) == self.0
+    }
+}
+
+
+//==================================================================================================
+impl VirtualAddress {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn new(address: usize) -> VirtualAddress {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for VirtualAddress. Panics if the address is not canonical.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   address -> raw virtual address
+    //
+    // RETURNS: the wrapped address
+    //==============================================================================================
+
+        match VirtualAddress::try_new(address) {
+            Ok(address) => address,
+            Err(NonCanonicalAddress(address)) => {
+                panic!("virtual address {:#x} is not canonical", address)
+            },
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn try_new(address: usize) -> Result<VirtualAddress, NonCanonicalAddress> {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for VirtualAddress that reports non-canonical addresses instead of
+    // panicking.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   address -> raw virtual address
+    //
+    // RETURNS: Ok(...)  -> the wrapped address
+    //          Err(...) -> the address is not canonical
+    //==============================================================================================
+
+        if (sign_extend(address) == address) {
+            Ok(VirtualAddress(address))
+        }
+        else {
+            Err(NonCanonicalAddress(address))
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn new_truncate(address: usize) -> VirtualAddress {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for VirtualAddress that makes any value canonical by copying bit 47 into
+    // the upper bits, as needed when an address is assembled from table indices.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   address -> raw virtual address, of which only the low 48 bits are used
+    //
+    // RETURNS: the wrapped, canonical address
+    //==============================================================================================
+
+        VirtualAddress(sign_extend(address))
+    }
+
+
+    //==============================================================================================
+    pub fn from_ptr<T>(pointer: *const T) -> VirtualAddress {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for the VirtualAddress of a pointer.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   pointer -> pointer to take the address of
+    //
+    // RETURNS: the address the pointer points to
+    //==============================================================================================
+
+        VirtualAddress::new(pointer as usize)
+    }
+
+
+    //==============================================================================================
+    pub fn as_usize(&self) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the raw address.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the address as a usize
+    //==============================================================================================
+
+        self.0
+    }
+
+
+    //==============================================================================================
+    pub fn as_u64(&self) -> u64 {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the raw address in the width used by descriptor tables and registers.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the address as a u64
+    //==============================================================================================
+
+        self.0 as u64
+    }
+
+
+    //==============================================================================================
+    pub fn as_ptr<T>(&self) -> *const T {
+    //----------------------------------------------------------------------------------------------
+    // Obtain a pointer to this address.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: an immutable raw pointer
+    //==============================================================================================
+
+        self.0 as *const T
+    }
+
+
+    //==============================================================================================
+    pub fn as_mut_ptr<T>(&self) -> *mut T {
+    //----------------------------------------------------------------------------------------------
+    // Obtain a mutable pointer to this address.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: a mutable raw pointer
+    //==============================================================================================
+
+        self.0 as *mut T
+    }
+
+
+    //==============================================================================================
+    pub fn align_up(&self, align: usize) -> VirtualAddress {
+    //----------------------------------------------------------------------------------------------
+    // Round this address up to a multiple of align.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   align -> alignment, a power of two
+    //
+    // RETURNS: the lowest aligned address at or above this one
+    //==============================================================================================
+
+        VirtualAddress::new(align_up(self.0, align))
+    }
+
+
+    //==============================================================================================
+    pub fn align_down(&self, align: usize) -> VirtualAddress {
+    //----------------------------------------------------------------------------------------------
+    // Round this address down to a multiple of align.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   align -> alignment, a power of two
+    //
+    // RETURNS: the highest aligned address at or below this one
+    //==============================================================================================
+
+        VirtualAddress(align_down(self.0, align))
+    }
+
+
+    //==============================================================================================
+    pub fn is_aligned(&self, align: usize) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether this address is a multiple of align.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   align -> alignment, a power of two
+    //
+    // RETURNS: true  -> the address is aligned
+    //          false -> the address is not aligned
+    //==============================================================================================
+
+        align_down(self.0, align) == self.0
+    }
+}
+
+
+//==================================================================================================
+impl Add<usize> for PhysicalAddress {
+//==================================================================================================
+
+    type Output = PhysicalAddress;
+
+    fn add(self, offset: usize) -> PhysicalAddress {
+        PhysicalAddress::new(self.0.checked_add(offset).expect("physical address overflow"))
+    }
+}
+
+
+//==================================================================================================
+impl AddAssign<usize> for PhysicalAddress {
+//==================================================================================================
+
+    fn add_assign(&mut self, offset: usize) {
+        *self = *self + offset;
+    }
+}
+
+
+//==================================================================================================
+impl Sub<usize> for PhysicalAddress {
+//==================================================================================================
+
+    type Output = PhysicalAddress;
+
+    fn sub(self, offset: usize) -> PhysicalAddress {
+        PhysicalAddress(self.0.checked_sub(offset).expect("physical address underflow"))
+    }
+}
+
+
+//==================================================================================================
+impl SubAssign<usize> for PhysicalAddress {
+//==================================================================================================
+
+    fn sub_assign(&mut self, offset: usize) {
+        *self = *self - offset;
+    }
+}
+
+
+//==================================================================================================
+impl Sub<PhysicalAddress> for PhysicalAddress {
+//==================================================================================================
+
+    type Output = usize;
+
+    fn sub(self, other: PhysicalAddress) -> usize {
+        self.0.checked_sub(other.0).expect("physical address underflow")
+    }
+}
+
+
+//==================================================================================================
+impl Add<usize> for VirtualAddress {
+//==================================================================================================
+
+    type Output = VirtualAddress;
+
+    fn add(self, offset: usize) -> VirtualAddress {
+        VirtualAddress::new(self.0.checked_add(offset).expect("virtual address overflow"))
+    }
+}
+
+
+//==================================================================================================
+impl AddAssign<usize> for VirtualAddress {
+//==================================================================================================
+
+    fn add_assign(&mut self, offset: usize) {
+        *self = *self + offset;
+    }
+}
+
+
+//==================================================================================================
+impl Sub<usize> for VirtualAddress {
+//==================================================================================================
+
+    type Output = VirtualAddress;
+
+    fn sub(self, offset: usize) -> VirtualAddress {
+        VirtualAddress::new(self.0.checked_sub(offset).expect("virtual address underflow"))
+    }
+}
+
+
+//==================================================================================================
+impl SubAssign<usize> for VirtualAddress {
+//==================================================================================================
+
+    fn sub_assign(&mut self, offset: usize) {
+        *self = *self - offset;
+    }
+}
+
+
+//==================================================================================================
+impl Sub<VirtualAddress> for VirtualAddress {
+//==================================================================================================
+
+    type Output = usize;
+
+    fn sub(self, other: VirtualAddress) -> usize {
+        self.0.checked_sub(other.0).expect("virtual address underflow")
+    }
+}
+
+
+//==================================================================================================
+impl fmt::Debug for PhysicalAddress {
+//==================================================================================================
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PhysicalAddress({:#x})", self.0)
+    }
+}
+
+
+//==================================================================================================
+impl fmt::LowerHex for PhysicalAddress {
+//==================================================================================================
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+
+//==================================================================================================
+impl fmt::Debug for VirtualAddress {
+//==================================================================================================
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "VirtualAddress({:#x})", self.0)
+    }
+}
+
+
+//==================================================================================================
+impl fmt::LowerHex for VirtualAddress {
+//==================================================================================================
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn sign_extend(address: usize) -> usize {
+//--------------------------------------------------------------------------------------------------
+// Copy bit 47 of an address into bits 48-63.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address -> raw address
+//
+// RETURNS: the canonical form of the address's low 48 bits
+//==================================================================================================
+
+    let shift = 64 - VIRTUAL_ADDRESS_BITS;
+    (((address << shift) as isize) >> shift) as usize
+}
+
+
+//==================================================================================================
+fn align_up(address: usize, align: usize) -> usize {
+//--------------------------------------------------------------------------------------------------
+// Round an address up to a multiple of align.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address -> address to round
+//          align   -> alignment, a power of two
+//
+// RETURNS: the rounded address
+//==================================================================================================
+
+    assert!(align.is_power_of_two(), "alignment must be a power of two");
+    address.checked_add(align - 1).expect("address overflow while aligning") & !(align - 1)
+}
+
+
+//==================================================================================================
+fn align_down(address: usize, align: usize) -> usize {
+//--------------------------------------------------------------------------------------------------
+// Round an address down to a multiple of align.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address -> address to round
+//          align   -> alignment, a power of two
+//
+// RETURNS: the rounded address
+//==================================================================================================
+
+    assert!(align.is_power_of_two(), "alignment must be a power of two");
+    address & !(align - 1)
+}
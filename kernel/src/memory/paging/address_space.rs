@@ -0,0 +1,640 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/memory/paging: address_space.rs                                                         #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::{Frame, FrameAllocator, PAGE_SIZE};
+use memory::paging::{PhysicalAddress, FrameRange, ENTRY_COUNT, is_linear_mapped};
+use memory::paging::cow;
+use memory::frame_table::{self, FrameUsage};
+use memory::paging::entry::{EntryFlags, PRESENT, WRITABLE, NO_EXEC, ACCESSIBLE, HUGE_PAGE};
+use memory::paging::table::{Table, TableLevel, MetaLevel, PageMap, PointerTable, PageDirectory,
+                            PageTable};
+use memory::paging::pcid;
+use ::x86::shared::control_regs;
+use core::ptr;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+const USER_ENTRY_COUNT: usize = ENTRY_COUNT / 2;        // PML4 entries 0-255 map the lower half
+const RECURSIVE_INDEX: usize = ENTRY_COUNT - 1;         // PML4 entry mapping the table itself
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub struct AddressSpace {
+//--------------------------------------------------------------------------------------------------
+// Virtual address space owning its own page map. The lower half belongs to the space alone, while
+// the kernel half points at the same pointer tables in every space, so kernel mappings made in
+// one space are seen by all of them. Every kernel pointer table is allocated during remap_kernel,
+// which keeps the shared PML4 entries from ever changing afterwards.
+//
+// A space must be torn down with destroy; dropping it leaks its frames.
+//==================================================================================================
+
+    page_map_frame: Frame,              // Frame holding the space's PML4
+    pcid: u16,                          // Tag of the space's TLB entries, or NO_PCID
+}
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl AddressSpace {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn new<A: FrameAllocator>(allocator: &mut A) -> Option<AddressSpace> {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for AddressSpace. Creates a space with an empty lower half and the
+    // kernel half shared with the running space.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   allocator -> allocator to obtain the page map frame from
+    //
+    // RETURNS: Some(...) -> the new address space
+    //          None      -> no frame was left for the page map
+    //==============================================================================================
+
+        let frame = match allocator.allocate_frame() {
+            Some(frame) => frame,
+            None        => return None,
+        };
+        frame_table::set_usage(&frame, FrameUsage::PageTable);
+
+        {
+            let page_map = unsafe { table_at::<PageMap>(&frame) };
+            let active_map = unsafe { table_at::<PageMap>(&active_page_map_frame()) };
+
+            page_map.clear();
+
+            for index in USER_ENTRY_COUNT .. RECURSIVE_INDEX {
+                if let Some(table) = active_map[index].target_frame() {
+                    page_map[index].set(table, active_map[index].flags());
+                }
+            }
+
+            page_map[RECURSIVE_INDEX].set(frame.clone(), PRESENT | WRITABLE | NO_EXEC);
+        }
+
+        Some(AddressSpace { page_map_frame: frame, pcid: pcid::allocate() })
+    }
+
+
+    //==============================================================================================
+    pub unsafe fn from_active() -> AddressSpace {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for AddressSpace, taking ownership of the page map currently loaded in
+    // CR3. Unsafe, as nothing else may own that page map or the space would be destroyed twice.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the running address space
+    //==============================================================================================
+
+        AddressSpace { page_map_frame: active_page_map_frame(), pcid: pcid::current() }
+    }
+
+
+    //==============================================================================================
+    pub fn clone_space<A: FrameAllocator>(&self, allocator: &mut A) -> Option<AddressSpace> {
+    //----------------------------------------------------------------------------------------------
+    // Create a space whose lower half is a private copy of this one's. Every table is duplicated,
+    // but 4 KiB pages share their frames copy-on-write, so that only frames written to after the
+    // clone are ever copied. Huge mappings are copied outright, as 4 KiB pages. The kernel half is
+    // shared as usual.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   allocator -> allocator to obtain the copied tables and frames from
+    //
+    // RETURNS: Some(...) -> the copy
+    //          None      -> memory ran out, and whatever was copied has been freed again
+    //==============================================================================================
+
+        let space = match AddressSpace::new(allocator) {
+            Some(space) => space,
+            None        => return None,
+        };
+
+        let copied = {
+            let source = unsafe { table_at::<PageMap>(&self.page_map_frame) };
+            let target = unsafe { table_at::<PageMap>(&space.page_map_frame) };
+
+            (0 .. USER_ENTRY_COUNT).all(|index| copy_page_map_entry(source, target, index,
+                                                                     allocator))
+        };
+
+        // Pages of this space may have just lost write access
+        if (self.is_active()) {
+            pcid::flush_current();
+        }
+        else {
+            pcid::flush_context(self.pcid);
+        }
+
+        if (copied) {
+            Some(space)
+        }
+        else {
+            space.destroy(allocator);
+            None
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn destroy<A: FrameAllocator>(self, allocator: &mut A) {
+    //----------------------------------------------------------------------------------------------
+    // Tear down the space, returning every lower-half table, every frame mapped in the lower
+    // half, and the page map itself to the allocator. Frames that are pinned or still mapped
+    // elsewhere, such as those shared copy-on-write with another space, are not freed. The shared
+    // kernel half is left alone.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   allocator -> allocator receiving the frames
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        assert!(!self.is_active(), "cannot destroy the running address space");
+
+        {
+            let page_map = unsafe { table_at::<PageMap>(&self.page_map_frame) };
+
+            for index in 0 .. USER_ENTRY_COUNT {
+                if let Some(frame) = page_map[index].target_frame() {
+                    free_pointer_table(&frame, allocator);
+                    allocator.deallocate_frame(frame);
+                    page_map[index].mark_unused();
+                }
+            }
+        }
+
+        pcid::free(self.pcid);
+        allocator.deallocate_frame(self.page_map_frame);
+    }
+
+
+    //==============================================================================================
+    pub fn activate(&self) {
+    //----------------------------------------------------------------------------------------------
+    // Load the space into CR3. Safe to call from a scheduler at any point in kernel code: the
+    // kernel half, stacks included, is identical in every space, so execution carries on
+    // unchanged. CR3 is left alone if the space is already running, and with PCIDs the space's
+    // TLB entries survive being switched away from and back to.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        if (!self.is_active()) {
+            unsafe { pcid::load(self.page_map_frame.address(), self.pcid); }
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn is_active(&self) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether the space is the one loaded in CR3.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: true  -> the space is running
+    //          false -> another space is running
+    //==============================================================================================
+
+        active_page_map_frame() == self.page_map_frame
+    }
+
+
+    //==============================================================================================
+    pub fn page_map_frame(&self) -> &Frame {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the frame holding the space's PML4.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the page map frame
+    //==============================================================================================
+
+        &self.page_map_frame
+    }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn active_page_map_frame() -> Frame {
+//--------------------------------------------------------------------------------------------------
+// Obtain the frame of the page map loaded in CR3.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: the running page map's frame
+//==================================================================================================
+
+    Frame::frame_containing_address(PhysicalAddress::new(unsafe { control_regs::cr3() } as usize))
+}
+
+
+//==================================================================================================
+unsafe fn table_at<'a, L: TableLevel>(frame: &Frame) -> &'a mut Table<L> {
+//--------------------------------------------------------------------------------------------------
+// Access a table through the linear map of physical memory, whether or not the space it belongs
+// to is running. Unsafe, as nothing stops the caller from aliasing the table.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frame -> frame holding the table
+//
+// RETURNS: the table
+//==================================================================================================
+
+    &mut *frame.virtual_address().as_mut_ptr::<Table<L>>()
+}
+
+
+//==================================================================================================
+fn new_table<'a, L: MetaLevel, A: FrameAllocator>(entry_flags: EntryFlags, parent: &mut Table<L>,
+                                                  index: usize, allocator: &mut A)
+                                                  -> Option<&'a mut Table<L::NextLevel>> {
+//--------------------------------------------------------------------------------------------------
+// Allocate an empty table and hook it into an entry of its parent straight away, so that a later
+// failure leaves it reachable for destroy.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   entry_flags -> flags of the source entry the table is copied for
+//          parent      -> table receiving the new table
+//          index       -> entry of the parent to point at the new table
+//          allocator   -> allocator to obtain the table's frame from
+//
+// RETURNS: Some(...) -> the new, empty table
+//          None      -> no frame was left
+//==================================================================================================
+
+    let frame = match allocator.allocate_frame() {
+        Some(frame) => frame,
+        None        => return None,
+    };
+    frame_table::set_usage(&frame, FrameUsage::PageTable);
+
+    let table = unsafe { table_at::<L::NextLevel>(&frame) };
+    table.clear();
+    parent[index].set(frame, (entry_flags & (WRITABLE | ACCESSIBLE)) | PRESENT);
+    Some(table)
+}
+
+
+//==================================================================================================
+fn copy_frame<A: FrameAllocator>(source: &Frame, allocator: &mut A) -> Option<Frame> {
+//--------------------------------------------------------------------------------------------------
+// Allocate a frame and fill it with a copy of another.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   source    -> frame to copy
+//          allocator -> allocator to obtain the copy from
+//
+// RETURNS: Some(...) -> the copy
+//          None      -> no frame was left
+//==================================================================================================
+
+    allocator.allocate_frame().map(|frame| {
+        unsafe {
+            ptr::copy_nonoverlapping(source.virtual_address().as_ptr::<u8>(),
+                                     frame.virtual_address().as_mut_ptr::<u8>(), PAGE_SIZE);
+        }
+        frame
+    })
+}
+
+
+//==================================================================================================
+fn copy_page_map_entry<A: FrameAllocator>(source: &Table<PageMap>, target: &mut Table<PageMap>,
+                                          index: usize, allocator: &mut A) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Copy one lower-half PML4 entry, and everything below it, into another page map.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   source    -> page map to copy from
+//          target    -> page map to copy into
+//          index     -> entry to copy
+//          allocator -> allocator to obtain tables and frames from
+//
+// RETURNS: true  -> the entry was copied
+//          false -> memory ran out part way through
+//==================================================================================================
+
+    let flags = source[index].flags();
+    let frame = match source[index].target_frame() {
+        Some(frame) => frame,
+        None        => return true,
+    };
+
+    let source = unsafe { table_at::<PointerTable>(&frame) };
+    let target = match new_table(flags, target, index, allocator) {
+        Some(table) => table,
+        None        => return false,
+    };
+
+    for index in 0 .. ENTRY_COUNT {
+        let flags = source[index].flags();
+        let frame = match source[index].target_frame() {
+            Some(frame) => frame,
+            None        => continue,
+        };
+
+        let copied = if (flags.contains(HUGE_PAGE)) {
+            copy_huge_gigabyte(&frame, source[index].split_flags(), target, index, allocator)
+        }
+        else {
+            copy_page_directory(&frame, flags, target, index, allocator)
+        };
+
+        if (!copied) {
+            return false;
+        }
+    }
+
+    true
+}
+
+
+//==================================================================================================
+fn copy_page_directory<A: FrameAllocator>(frame: &Frame, flags: EntryFlags,
+                                          parent: &mut Table<PointerTable>, index: usize,
+                                          allocator: &mut A) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Copy a page directory, and everything below it, into a pointer table.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frame     -> frame holding the page directory to copy
+//          flags     -> flags of the pointer table entry referencing it
+//          parent    -> pointer table receiving the copy
+//          index     -> entry of the parent to point at the copy
+//          allocator -> allocator to obtain tables and frames from
+//
+// RETURNS: true  -> the directory was copied
+//          false -> memory ran out part way through
+//==================================================================================================
+
+    let source = unsafe { table_at::<PageDirectory>(frame) };
+    let target = match new_table(flags, parent, index, allocator) {
+        Some(table) => table,
+        None        => return false,
+    };
+
+    for index in 0 .. ENTRY_COUNT {
+        let flags = source[index].flags();
+        let frame = match source[index].target_frame() {
+            Some(frame) => frame,
+            None        => continue,
+        };
+
+        let copied = if (flags.contains(HUGE_PAGE)) {
+            copy_huge_run(&frame, source[index].split_flags(), target, index, allocator)
+        }
+        else {
+            copy_page_table(&frame, flags, target, index, allocator)
+        };
+
+        if (!copied) {
+            return false;
+        }
+    }
+
+    true
+}
+
+
+//==================================================================================================
+fn copy_page_table<A: FrameAllocator>(frame: &Frame, flags: EntryFlags,
+                                      parent: &mut Table<PageDirectory>, index: usize,
+                                      allocator: &mut A) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Copy a page table into a page directory. The frames it maps are shared copy-on-write rather
+// than copied.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frame     -> frame holding the page table to copy
+//          flags     -> flags of the page directory entry referencing it
+//          parent    -> page directory receiving the copy
+//          index     -> entry of the parent to point at the copy
+//          allocator -> allocator to obtain the table from
+//
+// RETURNS: true  -> the table was copied
+//          false -> no frame was left for the table
+//==================================================================================================
+
+    let source = unsafe { table_at::<PageTable>(frame) };
+    let target = match new_table(flags, parent, index, allocator) {
+        Some(table) => table,
+        None        => return false,
+    };
+
+    for index in 0 .. ENTRY_COUNT {
+        if (!source[index].is_unused()) {
+            let (frame, flags) = cow::share_entry(&mut source[index]);
+            frame_table::map(&frame);
+            target[index].set(frame, flags);
+        }
+    }
+
+    true
+}
+
+
+//==================================================================================================
+fn copy_huge_gigabyte<A: FrameAllocator>(first_frame: &Frame, flags: EntryFlags,
+                                         parent: &mut Table<PointerTable>, index: usize,
+                                         allocator: &mut A) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Copy a 1 GiB run of frames into a page directory of 2 MiB runs, each held in 4 KiB pages.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   first_frame -> first frame of the run
+//          flags       -> flags for the 4 KiB entries, see PageEntry::split_flags
+//          parent      -> pointer table receiving the page directory
+//          index       -> entry of the parent to point at the page directory
+//          allocator   -> allocator to obtain the tables and frames from
+//
+// RETURNS: true  -> the run was copied
+//          false -> memory ran out part way through
+//==================================================================================================
+
+    let target = match new_table(flags, parent, index, allocator) {
+        Some(table) => table,
+        None        => return false,
+    };
+
+    for index in 0 .. ENTRY_COUNT {
+        let run = Frame { frame_num: first_frame.frame_num + index * ENTRY_COUNT };
+        if (!copy_huge_run(&run, flags, target, index, allocator)) {
+            return false;
+        }
+    }
+
+    true
+}
+
+
+//==================================================================================================
+fn copy_huge_run<A: FrameAllocator>(first_frame: &Frame, flags: EntryFlags,
+                                    parent: &mut Table<PageDirectory>, index: usize,
+                                    allocator: &mut A) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Copy a 2 MiB run of frames into a page table of 4 KiB pages, as the allocator cannot be relied
+// on for contiguous frames. Frames outside of RAM, such as device memory, are not in the linear
+// map to be copied from, and are shared rather than duplicated.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   first_frame -> first frame of the run
+//          flags       -> flags for the 4 KiB entries, see PageEntry::split_flags
+//          parent      -> page directory receiving the page table
+//          index       -> entry of the parent to point at the page table
+//          allocator   -> allocator to obtain the table and frames from
+//
+// RETURNS: true  -> the run was copied
+//          false -> memory ran out part way through
+//==================================================================================================
+
+    let target = match new_table(flags, parent, index, allocator) {
+        Some(table) => table,
+        None        => return false,
+    };
+
+    for index in 0 .. ENTRY_COUNT {
+        let frame = Frame { frame_num: first_frame.frame_num + index };
+
+        if (!is_linear_mapped(&frame)) {
+            frame_table::map(&frame);
+            target[index].set(frame, flags);
+            continue;
+        }
+
+        match copy_frame(&frame, allocator) {
+            Some(copy) => target[index].set(copy, flags),
+            None       => return false,
+        }
+    }
+
+    true
+}
+
+
+//==================================================================================================
+fn free_pointer_table<A: FrameAllocator>(frame: &Frame, allocator: &mut A) {
+//--------------------------------------------------------------------------------------------------
+// Return everything below a lower-half pointer table to the allocator. The pointer table's own
+// frame is left to the caller. Mapped frames are only freed if the allocator handed them out and
+// nothing else still uses them, so device memory and frames beyond the frame table stay put.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frame     -> frame holding the pointer table
+//          allocator -> allocator receiving the frames
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let pointer_table = unsafe { table_at::<PointerTable>(frame) };
+
+    for ptr_index in 0 .. ENTRY_COUNT {
+        let frame = match pointer_table[ptr_index].target_frame() {
+            Some(frame) => frame,
+            None        => continue,
+        };
+
+        if (pointer_table[ptr_index].flags().contains(HUGE_PAGE)) {
+            free_frames(&frame, ENTRY_COUNT * ENTRY_COUNT, allocator);
+            continue;
+        }
+
+        let directory = unsafe { table_at::<PageDirectory>(&frame) };
+
+        for dir_index in 0 .. ENTRY_COUNT {
+            let frame = match directory[dir_index].target_frame() {
+                Some(frame) => frame,
+                None        => continue,
+            };
+
+            if (directory[dir_index].flags().contains(HUGE_PAGE)) {
+                free_frames(&frame, ENTRY_COUNT, allocator);
+                continue;
+            }
+
+            let page_table = unsafe { table_at::<PageTable>(&frame) };
+
+            for tbl_index in 0 .. ENTRY_COUNT {
+                if let Some(frame) = page_table[tbl_index].page_frame() {
+                    if (frame_table::unmap(&frame)) {
+                        allocator.deallocate_frame(frame);
+                    }
+                }
+            }
+
+            allocator.deallocate_frame(frame);
+        }
+
+        allocator.deallocate_frame(frame);
+    }
+}
+
+
+//==================================================================================================
+fn free_frames<A: FrameAllocator>(first_frame: &Frame, count: usize, allocator: &mut A) {
+//--------------------------------------------------------------------------------------------------
+// Return a run of frames mapped by a huge entry to the allocator, one frame at a time. Huge
+// mappings are not counted in the frame table, so only frames the allocator handed out and that
+// nothing else maps or pins are returned; device memory and frames still in use are left alone.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   first_frame -> first frame of the run
+//          count       -> number of frames in the run
+//          allocator   -> allocator receiving the frames
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let end = Frame { frame_num: first_frame.frame_num + count };
+
+    for frame in FrameRange::new(Frame { frame_num: first_frame.frame_num }, end) {
+        if (frame_table::is_reclaimable(&frame)) {
+            allocator.deallocate_frame(frame);
+        }
+    }
+}
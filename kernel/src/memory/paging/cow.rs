@@ -0,0 +1,147 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/memory/paging: cow.rs                                                                   #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::{MEMORY_CONTROLLER, FrameAllocator, Frame, PAGE_SIZE};
+use memory::frame_table;
+use memory::paging::{Page, VirtualAddress};
+use memory::paging::pcid;
+use memory::paging::demand::{PageFaultAccess, PageFaultError};
+use memory::paging::entry::{PageEntry, EntryFlags, WRITABLE, ACCESSIBLE, COPY_ON_WRITE};
+use core::ptr;
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn share_entry(entry: &mut PageEntry) -> (Frame, EntryFlags) {
+//--------------------------------------------------------------------------------------------------
+// Prepare a mapped 4 KiB entry to be duplicated into another mapping. A writable entry loses
+// write access and gains COPY_ON_WRITE, so that whichever mapping writes first gets its own copy.
+// The duplicate mapping must be counted in the frame table by whoever makes it, which
+// PTMapper::map_page_to_frame does.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   entry -> present 4 KiB entry to share
+//
+// RETURNS: (frame the entry maps, flags the duplicate mapping must use)
+//==================================================================================================
+
+    let frame = entry.page_frame().expect("Page not mapped!");
+    let mut flags = entry.flags();
+
+    if (flags.contains(WRITABLE)) {
+        flags.remove(WRITABLE);
+        flags.insert(COPY_ON_WRITE);
+        entry.set_flags(flags);
+    }
+
+    // A mapping made without PTMapper, such as part of a split huge page, was never counted
+    if (frame_table::refcount(&frame) == 0) {
+        frame_table::map(&frame);
+    }
+
+    (frame, flags)
+}
+
+
+//==================================================================================================
+pub fn handle_page_fault(address: VirtualAddress, access: PageFaultAccess)
+                         -> Result<(), PageFaultError> {
+//--------------------------------------------------------------------------------------------------
+// Resolve a write to a copy-on-write page. A frame still shared with other mappings is copied
+// into a fresh frame for the faulting mapping; the last mapping of a frame simply takes it over.
+// Either way, write access is restored. Like demand paging, this never waits on
+// MEMORY_CONTROLLER; the frame table is only locked while holding it, so it is free here.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address -> faulting address, as read from CR2
+//          access  -> access that caused the fault
+//
+// RETURNS: Ok(())  -> the page is writable and the access may be retried
+//          Err(..) -> reason the fault could not be resolved
+//==================================================================================================
+
+    if (!access.protection_violation || !access.write) {
+        return Err(PageFaultError::ProtectionViolation);
+    }
+
+    let mut controller = match MEMORY_CONTROLLER.try_lock() {
+        Some(controller) => controller,
+        None             => return Err(PageFaultError::Busy),
+    };
+    let controller = match controller.as_mut() {
+        Some(controller) => controller,
+        None             => return Err(PageFaultError::Busy),
+    };
+
+    let page = Page::containing_address(address);
+    let entry = match controller.active_table.entry_mut(page) {
+        Some(entry) => entry,
+        None        => return Err(PageFaultError::ProtectionViolation),
+    };
+
+    let flags = entry.flags();
+    if (!flags.contains(COPY_ON_WRITE) || (access.user && !flags.contains(ACCESSIBLE))) {
+        return Err(PageFaultError::ProtectionViolation);
+    }
+
+    let frame = entry.page_frame().unwrap();
+    let writable = (flags - COPY_ON_WRITE) | WRITABLE;
+
+    if (frame_table::refcount(&frame) > 1) {
+        let copy = match controller.frame_allocator.allocate_frame() {
+            Some(copy) => copy,
+            None       => return Err(PageFaultError::OutOfMemory),
+        };
+
+        unsafe {
+            ptr::copy_nonoverlapping(frame.virtual_address().as_ptr::<u8>(),
+                                     copy.virtual_address().as_mut_ptr::<u8>(), PAGE_SIZE);
+        }
+
+        frame_table::unmap(&frame);
+        frame_table::map(&copy);
+        entry.set(copy, writable);
+    }
+    else {
+        entry.set_flags(writable);
+    }
+
+    pcid::flush_page(page.starting_address());
+    Ok(())
+}
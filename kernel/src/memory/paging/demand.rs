@@ -0,0 +1,334 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/memory/paging: demand.rs                                                                #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::{MEMORY_CONTROLLER, FrameAllocator, PAGE_SIZE};
+use memory::paging::{Page, VirtualAddress};
+use memory::paging::entry::{EntryFlags, WRITABLE, ACCESSIBLE, NO_EXEC};
+use core::fmt;
+use core::ptr;
+use spin::Mutex;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+const MAX_DEMAND_REGIONS: usize = 32;
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Clone, Copy)]
+//==================================================================================================
+pub struct DemandRegion {
+//--------------------------------------------------------------------------------------------------
+// Range of kernel virtual memory that is reserved but only backed by frames once it is touched.
+//==================================================================================================
+
+    pub start: VirtualAddress,          // First address of the region, page aligned
+    pub end: VirtualAddress,            // One past the last address of the region, page aligned
+    pub flags: EntryFlags,              // Flags every page of the region is mapped with
+    pub name: &'static str,             // Name printed in fault diagnostics
+}
+
+
+#[derive(Clone, Copy)]
+//==================================================================================================
+pub struct PageFaultAccess {
+//--------------------------------------------------------------------------------------------------
+// Decoded description of the access that caused a page fault.
+//==================================================================================================
+
+    pub protection_violation: bool,     // Page was present, so the access broke its protection
+    pub write: bool,                    // Access was a write
+    pub user: bool,                     // Access came from ring 3
+    pub instruction_fetch: bool,        // Access was an instruction fetch
+}
+
+
+#[derive(Clone, Copy)]
+//==================================================================================================
+pub enum PageFaultError {
+//--------------------------------------------------------------------------------------------------
+// Reason a page fault could not be resolved by demand paging.
+//==================================================================================================
+
+    ProtectionViolation,                // Faulting page was already present
+    NoRegion,                           // Address lies outside every registered region
+    PermissionMismatch(DemandRegion),   // Region does not permit the kind of access made
+    OutOfMemory,                        // No frame was left to back the page
+    Busy,                               // Fault was raised while memory management was locked
+}
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+static DEMAND_REGIONS: Mutex<[Option<DemandRegion>; MAX_DEMAND_REGIONS]> =
+    Mutex::new([None; MAX_DEMAND_REGIONS]);
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl DemandRegion {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn contains(&self, address: VirtualAddress) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether an address lies within this region.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   address -> virtual address to check
+    //
+    // RETURNS: true  -> address is part of the region
+    //          false -> address lies outside the region
+    //==============================================================================================
+
+        address >= self.start && address < self.end
+    }
+
+
+    //==============================================================================================
+    fn permits(&self, access: &PageFaultAccess) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether the region's flags allow an access.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   access -> access that caused the fault
+    //
+    // RETURNS: true  -> the access may be satisfied by mapping a page
+    //          false -> the region forbids the access
+    //==============================================================================================
+
+        !(access.write && !self.flags.contains(WRITABLE))
+            && !(access.instruction_fetch && self.flags.contains(NO_EXEC))
+            && !(access.user && !self.flags.contains(ACCESSIBLE))
+    }
+}
+
+
+//==================================================================================================
+impl fmt::Display for PageFaultError {
+//==================================================================================================
+
+
+    //==============================================================================================
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    //----------------------------------------------------------------------------------------------
+    // Describe why the fault could not be resolved.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   f -> formatter to write the description to
+    //
+    // RETURNS: result of writing to the formatter
+    //==============================================================================================
+
+        match *self {
+            PageFaultError::ProtectionViolation => {
+                write!(f, "access violated the protection of a present page")
+            },
+            PageFaultError::NoRegion => {
+                write!(f, "address lies outside every demand-paged region")
+            },
+            PageFaultError::PermissionMismatch(region) => {
+                write!(f, "access not permitted by region '{}' ({:#x}-{:#x}, flags {:?})",
+                       region.name, region.start, region.end, region.flags)
+            },
+            PageFaultError::OutOfMemory => {
+                write!(f, "no frame left to back the page")
+            },
+            PageFaultError::Busy => {
+                write!(f, "fault raised while memory management was locked")
+            },
+        }
+    }
+}
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn register_region(start: VirtualAddress, end: VirtualAddress, flags: EntryFlags,
+                       name: &'static str) {
+//--------------------------------------------------------------------------------------------------
+// Reserve a range of virtual memory whose pages are mapped the first time they are touched. None
+// of the region's pages may already be mapped.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   start -> first address of the region, page aligned
+//          end   -> one past the last address of the region, page aligned
+//          flags -> flags to map the region's pages with
+//          name  -> name printed in fault diagnostics
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    assert!(start.is_aligned(PAGE_SIZE) && end.is_aligned(PAGE_SIZE),
+            "demand region must be page aligned");
+    assert!(start < end, "demand region must not be empty");
+
+    let mut regions = DEMAND_REGIONS.lock();
+
+    for region in regions.iter().filter_map(|region| *region) {
+        assert!(end <= region.start || start >= region.end,
+                "demand region '{}' overlaps region '{}'", name, region.name);
+    }
+
+    let slot = regions.iter_mut().find(|region| region.is_none())
+        .expect("too many demand regions");
+
+    *slot = Some(DemandRegion { start: start, end: end, flags: flags, name: name });
+}
+
+
+//==================================================================================================
+pub fn unregister_region(start: VirtualAddress) -> Option<DemandRegion> {
+//--------------------------------------------------------------------------------------------------
+// Remove a region from the registry. Pages already backed stay mapped; unmapping them is up to
+// the caller.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   start -> first address of the region
+//
+// RETURNS: Some(...) -> the removed region
+//          None      -> no region starts at the address
+//==================================================================================================
+
+    let mut regions = DEMAND_REGIONS.lock();
+
+    for slot in regions.iter_mut() {
+        if let Some(region) = *slot {
+            if (region.start == start) {
+                *slot = None;
+                return Some(region);
+            }
+        }
+    }
+
+    None
+}
+
+
+//==================================================================================================
+pub fn find_region(address: VirtualAddress) -> Option<DemandRegion> {
+//--------------------------------------------------------------------------------------------------
+// Look up the region containing an address. Does not wait for the registry lock, so that it may
+// be used while reporting faults.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address -> virtual address to look up
+//
+// RETURNS: Some(...) -> the region containing the address
+//          None      -> no region contains the address, or the registry is locked
+//==================================================================================================
+
+    DEMAND_REGIONS.try_lock()
+        .and_then(|regions| regions.iter().filter_map(|region| *region)
+                                   .find(|region| region.contains(address)))
+}
+
+
+//==================================================================================================
+pub fn handle_page_fault(address: VirtualAddress, access: PageFaultAccess)
+                         -> Result<(), PageFaultError> {
+//--------------------------------------------------------------------------------------------------
+// Resolve a page fault on a demand-paged region by backing the faulting page with a fresh, zeroed
+// frame. Runs in the page fault handler, so it never waits on a lock: a fault raised while memory
+// management is locked is reported instead of deadlocking.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address -> faulting address, as read from CR2
+//          access  -> access that caused the fault
+//
+// RETURNS: Ok(())  -> the page was mapped and the access may be retried
+//          Err(..) -> reason the fault could not be resolved
+//==================================================================================================
+
+    if (access.protection_violation) {
+        return Err(PageFaultError::ProtectionViolation);
+    }
+
+    let region = match DEMAND_REGIONS.try_lock() {
+        Some(regions) => regions.iter().filter_map(|region| *region)
+                                .find(|region| region.contains(address)),
+        None          => return Err(PageFaultError::Busy),
+    };
+
+    let region = match region {
+        Some(region) => region,
+        None         => return Err(PageFaultError::NoRegion),
+    };
+
+    if (!region.permits(&access)) {
+        return Err(PageFaultError::PermissionMismatch(region));
+    }
+
+    let mut controller = match MEMORY_CONTROLLER.try_lock() {
+        Some(controller) => controller,
+        None             => return Err(PageFaultError::Busy),
+    };
+    let controller = match controller.as_mut() {
+        Some(controller) => controller,
+        None             => return Err(PageFaultError::Busy),
+    };
+
+    let frame = match controller.frame_allocator.allocate_frame() {
+        Some(frame) => frame,
+        None        => return Err(PageFaultError::OutOfMemory),
+    };
+
+    // Map writable first so the page can be cleared, then settle on the region's own flags
+    let page = Page::containing_address(address);
+    controller.active_table.map_page_to_frame(page, frame, region.flags | WRITABLE,
+                                              &mut controller.frame_allocator);
+    unsafe { ptr::write_bytes(page.starting_address().as_mut_ptr::<u8>(), 0, PAGE_SIZE); }
+
+    if (!region.flags.contains(WRITABLE)) {
+        controller.active_table.update_flags(page, region.flags,
+                                             &mut controller.frame_allocator);
+    }
+
+    Ok(())
+}
@@ -43,6 +43,7 @@
 
 
 use memory::Frame;
+use memory::paging::PhysicalAddress;
 use multiboot2::{ElfSection,ELF_SECTION_ALLOCATED,ELF_SECTION_WRITABLE,ELF_SECTION_EXECUTABLE};
 
 
@@ -54,6 +55,8 @@ use multiboot2::{ElfSection,ELF_SECTION_ALLOCATED,ELF_SECTION_WRITABLE,ELF_SECTI
 
 
 const PAGE_ALIGNED_52_BIT_MASK: usize = 0x000F_FFFF_FFFF_F000;
+const HUGE_ALIGNED_52_BIT_MASK: usize = 0x000F_FFFF_FFFF_E000;  // Address bits of a huge entry
+const HUGE_PAT_BIT: u64 = 1 << 12;      // PAT bit of huge entries, whose bit 7 is HUGE_PAGE
 
 
 //##################################################################################################
@@ -82,7 +85,10 @@ bitflags! { pub flags EntryFlags: u64 {
     const ACCESSED     = 1 << 5,        // Target has been used
     const DIRTY        = 1 << 6,        // Target has been written to
     const HUGE_PAGE    = 1 << 7,        // Target size
+    const PAT          = 1 << 7,        // Memory type selector; shares the bit with HUGE_PAGE, but
+                                        // only means PAT in page table (4 KiB) entries
     const GLOBAL       = 1 << 8,        // Keep target in cache on addr space switch
+    const COPY_ON_WRITE = 1 << 9,       // Software: shared frame, copy before the first write
     const NO_EXEC      = 1 << 63,       // Forbid executing code in target
   }
 }
@@ -105,11 +111,11 @@ impl PageEntry {
     //----------------------------------------------------------------------------------------------
     // TAKES:   nothing
     //
-    // RETURNS: true  -> PRESENT bit set, entry present
-    //          false -> Present bit not set, no entry    
+    // RETURNS: true  -> PRESENT bit not set, no entry
+    //          false -> PRESENT bit set, entry present
     //==============================================================================================
 
-        self.flags().contains(PRESENT)
+        !self.flags().contains(PRESENT)
     }
 
 
@@ -146,17 +152,46 @@ impl PageEntry {
     //==============================================================================================
     pub fn target_frame(&self) -> Option<Frame> {
     //----------------------------------------------------------------------------------------------
-    // Obtain frame referenced by entry.
+    // Obtain frame referenced by a page map, pointer table or page directory entry. Bit 12 of a
+    // huge entry is its PAT bit rather than part of the address, so it is left out.
     //----------------------------------------------------------------------------------------------
     // TAKES:   nothing
     //
     // RETURNS: Some(...) -> Frame object corresponding to address in entry
     //          None      -> Entry is invalid, no corresponding frame    
+    //==============================================================================================
+
+        let mask = if (self.flags().contains(HUGE_PAGE)) {
+            HUGE_ALIGNED_52_BIT_MASK
+        }
+        else {
+            PAGE_ALIGNED_52_BIT_MASK
+        };
+
+        if (self.flags().contains(PRESENT)) {
+            Some(Frame::frame_containing_address(PhysicalAddress::new(self.0 as usize & mask)))
+        }
+        else {
+            None
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn page_frame(&self) -> Option<Frame> {
+    //----------------------------------------------------------------------------------------------
+    // Obtain frame referenced by a page table entry. Bit 7 is PAT rather than HUGE_PAGE in these,
+    // so bit 12 always belongs to the address.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: Some(...) -> Frame object corresponding to address in entry
+    //          None      -> Entry is invalid, no corresponding frame
     //==============================================================================================
 
         if (self.flags().contains(PRESENT)) {
             Some(Frame::frame_containing_address(
-                self.0 as usize & PAGE_ALIGNED_52_BIT_MASK))
+                PhysicalAddress::new(self.0 as usize & PAGE_ALIGNED_52_BIT_MASK)))
         }
         else {
             None
@@ -164,6 +199,61 @@ impl PageEntry {
     }
 
 
+    //==============================================================================================
+    pub fn split_flags(&self) -> EntryFlags {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the flags for page table entries mapping part of this huge entry's region with the
+    // same permissions and memory type. HUGE_PAGE is dropped and PAT moves from bit 12 to bit 7.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: flags for the 4 KiB entries
+    //==============================================================================================
+
+        let mut flags = self.flags() - HUGE_PAGE;
+        if (self.has_huge_pat()) {
+            flags.insert(PAT);
+        }
+        flags
+    }
+
+
+    //==============================================================================================
+    pub fn has_huge_pat(&self) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether the PAT bit of a huge entry is set.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: true  -> bit 12 set
+    //          false -> bit 12 clear
+    //==============================================================================================
+
+        self.flags().contains(HUGE_PAGE) && self.0 & HUGE_PAT_BIT != 0
+    }
+
+
+    //==============================================================================================
+    pub fn set_huge_pat(&mut self, pat: bool) {
+    //----------------------------------------------------------------------------------------------
+    // Set or clear the PAT bit of a huge entry.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   pat -> whether the bit should be set
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        assert!(self.flags().contains(HUGE_PAGE), "PAT is bit 12 only in huge entries");
+
+        if (pat) {
+            self.0 |= HUGE_PAT_BIT;
+        }
+        else {
+            self.0 &= !HUGE_PAT_BIT;
+        }
+    }
+
+
     //==============================================================================================
     pub fn set(&mut self, frame: Frame, flags: EntryFlags) {
     //----------------------------------------------------------------------------------------------
@@ -175,7 +265,7 @@ impl PageEntry {
     // RETURNS: nothing
     //==============================================================================================
 
-        self.0 = ((frame.address() as u64) | flags.bits());
+        self.0 = (frame.address().as_u64() | flags.bits());
     }
 
     
@@ -190,7 +280,7 @@ impl PageEntry {
     //==============================================================================================
 
 
-        self.0 = (self.flags().bits() | frame.address() as u64);  
+        self.0 = (self.flags().bits() | frame.address().as_u64());  
     }
 
     //==============================================================================================
@@ -203,9 +293,10 @@ impl PageEntry {
     // RETURNS: nothing
     //==============================================================================================
 
+        // The raw address bits are kept, as bit 12 may be the PAT bit of a huge entry
         self.0 = match self.target_frame() {
-            Some(frame) => { (frame.address() as u64) | flags.bits() },
-            None        => { flags.bits() }, 
+            Some(_) => { (self.0 & PAGE_ALIGNED_52_BIT_MASK as u64) | flags.bits() },
+            None    => { flags.bits() }, 
         };
     }
 }
@@ -42,10 +42,17 @@
 //##################################################################################################
 
 
-mod entry;
+mod address;
+pub mod address_space;
+pub mod cow;
+pub mod demand;
+pub mod entry;
 mod table;
 mod temp_page;
 mod pt_mapper;
+mod range;
+mod walker;
+pub mod pcid;
 
 
 //##################################################################################################
@@ -54,15 +61,23 @@ mod pt_mapper;
 
 
 use memory::{FrameAllocator,Frame,PAGE_SIZE};
-use self::entry::{EntryFlags,HUGE_PAGE,PRESENT,WRITABLE};
+use memory::frame_table::{self,FrameUsage};
+use self::entry::{EntryFlags,HUGE_PAGE,PRESENT,WRITABLE,NO_EXEC};
 use memory::paging::table::PAGE_MAP;
-use self::table::{Table,PageMap};
+use self::table::{Table,PageMap,start_window_log,finish_window_log};
 use core::ptr::Unique;
 use core::ops::{Deref,DerefMut};
 use memory::paging::temp_page::TempPage;
 use self::pt_mapper::PTMapper;
-use ::x86::shared::{control_regs,tlb};
+pub use self::pt_mapper::HugePageSize;
+pub use self::address::{PhysicalAddress,VirtualAddress,NonCanonicalAddress};
+pub use self::range::{PageRange,FrameRange,PageIter,FrameIter};
+pub use self::address_space::AddressSpace;
+pub use self::walker::{Mapping,MappingIter};
+use ::x86::shared::control_regs;
 use multiboot2::BootInformation;
+use vga_interface::VGA_BUFFER_START;
+use spin::Mutex;
     
 
 
@@ -71,8 +86,12 @@ use multiboot2::BootInformation;
 //##################################################################################################
 
 
+pub const KERNEL_OFFSET: usize = 0o177777_400_000_000_000_0000;     // Kernel image, linker.ld
+pub const PHYS_MAP_OFFSET: usize = 0o177777_600_000_000_000_0000;   // Linear map of physical RAM
+
 const ENTRY_COUNT: usize = 512;
-const MAGIC_PAGE_NUMBER: usize = 0xDEADBEEF;
+const TEMP_PAGE_ADDRESS: usize = 0o177777_403_000_000_000_0000;
+const MAX_LINEAR_AREAS: usize = 32;             // Memory areas the linear map can cover
 
 
 //##################################################################################################
@@ -80,20 +99,6 @@ const MAGIC_PAGE_NUMBER: usize = 0xDEADBEEF;
 //##################################################################################################
 
 
-//==================================================================================================
-pub type PhysicalAddress = usize;
-//--------------------------------------------------------------------------------------------------
-// Alias for usize to specify that a given address represents a physical address.
-//==================================================================================================
-
-
-//==================================================================================================
-type VirtualAddress = usize;
-//--------------------------------------------------------------------------------------------------
-// Alias for usize to specify that a given address represents a virtual address.
-//==================================================================================================
-
-
 //==================================================================================================
 pub struct ActivePageTable {
 //--------------------------------------------------------------------------------------------------
@@ -115,7 +120,7 @@ pub struct InactivePageTable {
 
 
 //==================================================================================================
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Page {
 //--------------------------------------------------------------------------------------------------
 // Object representing a memory page.    
@@ -125,6 +130,17 @@ pub struct Page {
 }
 
 
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+// Frame number ranges, as (first, end) pairs, covered by the linear map, followed by how many of
+// them are in use. Only RAM is mapped; the holes between areas hold device memory.
+static LINEAR_AREAS: Mutex<([(usize, usize); MAX_LINEAR_AREAS], usize)> =
+    Mutex::new(([(0, 0); MAX_LINEAR_AREAS], 0));
+
+
 //##################################################################################################
 //************************************* STRUCT IMPLEMENTATIONS *************************************
 //##################################################################################################
@@ -153,18 +169,22 @@ impl ActivePageTable {
     //==============================================================================================
 
 
-        let orig_ctrl3 = Frame::frame_containing_address(unsafe { control_regs::cr3() } as usize);
+        let orig_ctrl3 = Frame::frame_containing_address(
+            PhysicalAddress::new(unsafe { control_regs::cr3() } as usize));
 
         {
             let active_table = temp_page.map_to_frame_as_table(orig_ctrl3.clone(), self);
             
-            self.page_map_mut()[ENTRY_COUNT-1].set(table.page_map_frame.clone(), PRESENT | WRITABLE);
-            unsafe { tlb::flush_all() };
+            // Only the window pages the closure reaches can hold stale translations, and only
+            // under the running PCID
+            self.page_map_mut()[ENTRY_COUNT-1].set(table.page_map_frame.clone(),
+                                                   PRESENT | WRITABLE | NO_EXEC);
+            start_window_log();
 
             lambda(self);
 
-            active_table[ENTRY_COUNT - 1].set(orig_ctrl3, PRESENT | WRITABLE);
-            unsafe { tlb::flush_all() };
+            active_table[ENTRY_COUNT - 1].set(orig_ctrl3, PRESENT | WRITABLE | NO_EXEC);
+            finish_window_log();
         }
         
         temp_page.unmap(self);
@@ -174,10 +194,11 @@ impl ActivePageTable {
     pub fn switch(&mut self, inactive_table: InactivePageTable) -> InactivePageTable {
         let orig_table = InactivePageTable
         {
-            page_map_frame: Frame::frame_containing_address(unsafe { control_regs::cr3() } as usize),
+            page_map_frame: Frame::frame_containing_address(
+                PhysicalAddress::new(unsafe { control_regs::cr3() } as usize)),
         };
 
-        unsafe { control_regs::cr3_write(inactive_table.page_map_frame.address()); }
+        unsafe { pcid::load(inactive_table.page_map_frame.address(), pcid::NO_PCID); }
 
         orig_table
     }
@@ -228,10 +249,12 @@ impl InactivePageTable {
     // RETURNS: an instance of InactivePageTable mapped to `frame` in `Active
     //==============================================================================================
 
+        frame_table::set_usage(&frame, FrameUsage::PageTable);
+
         {
             let table = temp_page.map_to_frame_as_table(frame.clone(), active_table);
             table.clear();
-            table[511].set(frame.clone(), PRESENT | WRITABLE);
+            table[511].set(frame.clone(), PRESENT | WRITABLE | NO_EXEC);
         }
 
         temp_page.unmap(active_table);
@@ -248,16 +271,16 @@ impl Page {
 
 
     //==============================================================================================
-    pub fn starting_address(&self) -> PhysicalAddress {
+    pub fn starting_address(&self) -> VirtualAddress {
     //----------------------------------------------------------------------------------------------
     // Obtain the starting address of the page.
     //----------------------------------------------------------------------------------------------
     // TAKES:   nothing
     //
-    // RETURNS: Physical starting address of the page
+    // RETURNS: Virtual starting address of the page
     //==============================================================================================
 
-        self.page_num * PAGE_SIZE
+        VirtualAddress::new(self.page_num * PAGE_SIZE)
     }
     
 
@@ -271,7 +294,7 @@ impl Page {
     // RETURNS: page containing given virtual address
     //==============================================================================================
 
-        Page { page_num: addr / PAGE_SIZE }
+        Page { page_num: addr.as_usize() / PAGE_SIZE }
     }
 
     //==============================================================================================
@@ -336,17 +359,23 @@ impl Page {
 
 
 //==================================================================================================
-pub fn remap_kernel<F: FrameAllocator>(allocator: &mut F, boot_info: &BootInformation) {
+pub fn remap_kernel<F: FrameAllocator>(allocator: &mut F, boot_info: &BootInformation)
+                                       -> ActivePageTable {
 //--------------------------------------------------------------------------------------------------
-//
+// Build a fresh page table mapping the kernel sections, the VGA buffer, and the multiboot
+// information structure in the higher half, along with a linear map of all physical memory at
+// PHYS_MAP_OFFSET. Switch to it, and turn the old page map into a guard page. Nothing is left
+// mapped in the lower half.
 //--------------------------------------------------------------------------------------------------
+// TAKES:   allocator -> allocator to obtain page table frames from
+//          boot_info -> multiboot information structure describing the kernel sections
 //
-//
-//
+// RETURNS: the newly activated page table
 //==================================================================================================
 
     
-    let mut temp_page = TempPage::new(Page { page_num: MAGIC_PAGE_NUMBER }, allocator);
+    let mut temp_page = TempPage::new(
+        Page::containing_address(VirtualAddress::new(TEMP_PAGE_ADDRESS)), allocator);
 
     let mut active_table = unsafe { ActivePageTable::new() };
 
@@ -356,37 +385,231 @@ pub fn remap_kernel<F: FrameAllocator>(allocator: &mut F, boot_info: &BootInform
 
     active_table.with(&mut inactive_table, &mut temp_page, |pt_mapper| {
 
-        // Identity map the kernel
+        // Map the kernel at the addresses it was linked at
         for section in boot_info.elf_sections_tag().expect("multiboot tag required!").sections() {
             if (!section.is_allocated()) { continue; }
+
+            // Boot code only runs before the jump to the higher half
+            if (section.start_address() < KERNEL_OFFSET) { continue; }
+
+            let start = kernel_to_phys(VirtualAddress::new(section.start_address()));
+            let end = kernel_to_phys(VirtualAddress::new(section.end_address()));
+
             println!("mapping sect w/ addr={:#x} & size={:#x}", section.addr, section.size);
 
             let flags = EntryFlags::from_elf_section(section);
             assert!(section.addr as usize % PAGE_SIZE == 0,
                     "sections need to be page aligned");
-            for frame_num in Frame::frame_containing_address(section.start_address()).frame_num ..
-                Frame::frame_containing_address(section.end_address()).frame_num {
-                    pt_mapper.identity_map(Frame { frame_num: frame_num }, flags, allocator);
-                }
+            map_kernel_frames(pt_mapper, FrameRange::containing(start, end), flags, allocator);
         }
 
-        // Identity map the VGA buffer
-        pt_mapper.identity_map(Frame::frame_containing_address(::vga_interface::VGA_BUFFER_START as usize),
-                               WRITABLE, allocator);
+        // Map the VGA buffer
+        let vga_frame = Frame::frame_containing_address(PhysicalAddress::new(VGA_BUFFER_START));
+        map_kernel_frames(pt_mapper, FrameRange::new_inclusive(vga_frame.clone(), vga_frame),
+                          WRITABLE | NO_EXEC, allocator);
 
-        // Identity map the boot information structure
-        for frame_num in Frame::frame_containing_address(boot_info.start_address()).frame_num..
-            Frame::frame_containing_address(boot_info.end_address()).frame_num {
-                pt_mapper.identity_map(Frame { frame_num: frame_num }, PRESENT, allocator);
-            }
+        // Map the boot information structure
+        let boot_info_start = kernel_to_phys(VirtualAddress::new(boot_info.start_address()));
+        let boot_info_end = kernel_to_phys(VirtualAddress::new(boot_info.end_address()));
+        map_kernel_frames(pt_mapper, FrameRange::containing(boot_info_start, boot_info_end),
+                          PRESENT, allocator);
+
+        map_physical_memory(pt_mapper, boot_info, allocator);
+
+        preallocate_kernel_tables(pt_mapper, allocator);
     });
     
     let orig_table = active_table.switch(inactive_table);
     println!("SWITCHED");
 
-    active_table.unmap(Page::containing_address(orig_table.page_map_frame.address()), allocator);
+    // The old page map lies in the kernel image, so its frame is not handed to the allocator
+    active_table.unmap_shared(
+        Page::containing_address(kernel_address(orig_table.page_map_frame.address())), allocator);
 
     println!("guard page active!");
+
+    active_table
+}
+
+
+//==================================================================================================
+pub fn phys_to_virt(address: PhysicalAddress) -> VirtualAddress {
+//--------------------------------------------------------------------------------------------------
+// Obtain the address at which a physical address may be accessed through the linear map of
+// physical memory. Only valid once the kernel has been remapped.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address -> physical address to access
+//
+// RETURNS: virtual address mapped to the physical address
+//==================================================================================================
+
+    VirtualAddress::new(PHYS_MAP_OFFSET + address.as_usize())
+}
+
+
+//==================================================================================================
+pub fn is_linear_mapped(frame: &Frame) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether a frame can be reached through the linear map, which only covers RAM.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   frame -> frame to check
+//
+// RETURNS: true if it can, false for device memory and other holes in the memory map
+//==================================================================================================
+
+    let areas = LINEAR_AREAS.lock();
+
+    areas.0[.. areas.1].iter()
+        .any(|&(first, end)| frame.frame_num >= first && frame.frame_num < end)
+}
+
+
+//==================================================================================================
+pub fn kernel_to_phys(address: VirtualAddress) -> PhysicalAddress {
+//--------------------------------------------------------------------------------------------------
+// Obtain the physical address of an address in the kernel image, or in the multiboot information
+// structure, as seen through the kernel's higher half mapping. Boot code is linked at its physical
+// address, so addresses below KERNEL_OFFSET are returned unchanged.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address -> virtual address within the kernel image
+//
+// RETURNS: physical address backing the virtual address
+//==================================================================================================
+
+    let address = address.as_usize();
+    PhysicalAddress::new(if (address >= KERNEL_OFFSET) { address - KERNEL_OFFSET } else { address })
+}
+
+
+//==================================================================================================
+pub fn kernel_address(address: PhysicalAddress) -> VirtualAddress {
+//--------------------------------------------------------------------------------------------------
+// Obtain the address at which a physical address in the first GiB appears in the kernel's higher
+// half mapping. The inverse of kernel_to_phys for the kernel image.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address -> physical address within the kernel image
+//
+// RETURNS: virtual address the kernel accesses it at
+//==================================================================================================
+
+    VirtualAddress::new(KERNEL_OFFSET + address.as_usize())
 }
 
 
+//##################################################################################################
+//**************************************** PRIVATE FUNCTIONS ***************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn map_kernel_frames<A: FrameAllocator>(pt_mapper: &mut PTMapper, frames: FrameRange,
+                                        flags: EntryFlags, allocator: &mut A) {
+//--------------------------------------------------------------------------------------------------
+// Map a range of frames at their physical addresses plus KERNEL_OFFSET, as the kernel image is
+// linked.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   pt_mapper -> mapper of the table being built
+//          frames    -> frames to map
+//          flags     -> flags to set in each entry
+//          allocator -> allocator to allocate new tables if necessary
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let pages = PageRange::new(Page::containing_address(kernel_address(frames.start().address())),
+                               Page::containing_address(kernel_address(frames.end().address())));
+    pt_mapper.map_range_to_frames(pages, frames, flags, allocator);
+}
+
+
+//==================================================================================================
+fn preallocate_kernel_tables<A: FrameAllocator>(pt_mapper: &mut PTMapper, allocator: &mut A) {
+//--------------------------------------------------------------------------------------------------
+// Give every kernel half PML4 entry a pointer table. Address spaces copy these entries when they
+// are created, so they must never change afterwards; any later kernel mapping then only touches
+// tables shared by every space.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   pt_mapper -> mapper of the table being built
+//          allocator -> allocator to allocate the pointer tables from
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    for index in ENTRY_COUNT / 2 .. ENTRY_COUNT - 1 {
+        pt_mapper.page_map_mut().next_table_create(index, allocator);
+    }
+}
+
+
+//==================================================================================================
+fn map_physical_memory<A: FrameAllocator>(pt_mapper: &mut PTMapper, boot_info: &BootInformation,
+                                          allocator: &mut A) {
+//--------------------------------------------------------------------------------------------------
+// Map every usable memory area linearly at PHYS_MAP_OFFSET, using 2 MiB pages where a whole
+// aligned run lies inside the area and 4 KiB pages at its edges. The holes between areas are left
+// out, as device memory there is mapped uncached by ioremap, and a write-back alias of it would
+// give the same frames conflicting memory types.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   pt_mapper -> mapper of the table being built
+//          boot_info -> multiboot information structure holding the memory map
+//          allocator -> allocator to allocate new tables from
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let huge_page_frames = HugePageSize::TwoMiB.page_count();
+    let mut areas = LINEAR_AREAS.lock();
+
+    for area in boot_info.memory_map_tag().expect("Need memory map tag!").memory_areas() {
+        // Only frames lying entirely inside the area are RAM the allocators may hand out
+        let first_frame = (area.base_addr as usize + PAGE_SIZE - 1) / PAGE_SIZE;
+        let end_frame = (area.base_addr + area.length) as usize / PAGE_SIZE;
+
+        if (first_frame >= end_frame) {
+            continue;
+        }
+
+        assert!(areas.1 < MAX_LINEAR_AREAS, "too many memory areas for the linear map");
+        let count = areas.1;
+        areas.0[count] = (first_frame, end_frame);
+        areas.1 += 1;
+
+        let mut frame_num = first_frame;
+        while (frame_num < end_frame) {
+            let frame = Frame { frame_num: frame_num };
+            let page = Page::containing_address(phys_to_virt(frame.address()));
+
+            if (frame_num % huge_page_frames == 0 && frame_num + huge_page_frames <= end_frame) {
+                pt_mapper.map_huge_2m(page, frame, WRITABLE | NO_EXEC, allocator);
+                frame_num += huge_page_frames;
+            }
+            else {
+                map_linear_page(pt_mapper, page, frame, allocator);
+                frame_num += 1;
+            }
+        }
+    }
+}
+
+
+//==================================================================================================
+fn map_linear_page<A: FrameAllocator>(pt_mapper: &mut PTMapper, page: Page, frame: Frame,
+                                      allocator: &mut A) {
+//--------------------------------------------------------------------------------------------------
+// Map one 4 KiB page of the linear map. The entry is set directly rather than through
+// map_page_to_frame, as the linear map is an alias of memory, not a use of it, and must not count
+// as one.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   pt_mapper -> mapper of the table being built
+//          page      -> page of the linear map
+//          frame     -> frame the page aliases
+//          allocator -> allocator to allocate new tables from
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    pt_mapper.page_map_mut().next_table_create(page.page_map_index(), allocator)
+        .next_table_create(page.pointer_table_index(), allocator)
+        .next_table_create(page.page_dir_index(), allocator)
+        [page.page_table_index()].set(frame, PRESENT | WRITABLE | NO_EXEC);
+}
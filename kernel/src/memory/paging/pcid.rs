@@ -0,0 +1,387 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/memory/paging: pcid.rs                                                                  #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::paging::{PhysicalAddress, VirtualAddress, KERNEL_OFFSET};
+use ::x86::shared::{control_regs, tlb};
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use cpu;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const NO_PCID: u16 = 0;                     // Untagged context, flushed on every load
+const PCID_COUNT: usize = 4096;
+const RECURSIVE_START: usize = 0o177777_777_000_000_000_0000;  // Page map entry 511, per space
+const PCID_MASK: u64 = 0xFFF;                   // CR3 bits holding the PCID
+const WORD_BITS: usize = 64;
+
+const CR4_PCIDE: u64 = 1 << 17;                 // CR3 tags TLB entries with a PCID
+const CR3_NO_FLUSH: usize = 1 << 63;            // Keep the new PCID's entries on a CR3 write
+
+const INVPCID_SINGLE_CONTEXT: u64 = 1;          // Every non-global entry of one PCID
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+struct PcidTable {
+//--------------------------------------------------------------------------------------------------
+// Bookkeeping for the PCIDs handed to address spaces.
+//==================================================================================================
+
+    allocated: [u64; PCID_COUNT / WORD_BITS],   // Bit set for every PCID in use
+    stale: [u64; PCID_COUNT / WORD_BITS],       // Bit set for PCIDs to flush on their next load
+    next: usize,                                // Where the search for a free PCID starts
+}
+
+
+#[repr(C)]
+//==================================================================================================
+struct InvpcidDescriptor {
+//--------------------------------------------------------------------------------------------------
+// Memory operand of the invpcid instruction.
+//==================================================================================================
+
+    pcid: u64,
+    address: u64,
+}
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static INVPCID: AtomicBool = AtomicBool::new(false);
+
+static PCIDS: Mutex<PcidTable> = Mutex::new(PcidTable {
+    allocated: [0; PCID_COUNT / WORD_BITS],
+    stale: [0; PCID_COUNT / WORD_BITS],
+    next: 1,
+});
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn init() {
+//--------------------------------------------------------------------------------------------------
+// Turn on PCIDs if the processor has them. Must run while CR3 holds PCID 0, as it does from
+// boot. Without PCIDs, every function here falls back to what a plain CR3 write or invlpg does.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    if (!cpu::supports_pcid()) {
+        return;
+    }
+
+    assert!(unsafe { control_regs::cr3() } as u64 & PCID_MASK == 0, "CR3 already tagged");
+
+    unsafe { cpu::cr4_write(cpu::cr4() | CR4_PCIDE); }
+
+    INVPCID.store(cpu::supports_invpcid(), Ordering::SeqCst);
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+
+//==================================================================================================
+pub fn enabled() -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check whether TLB entries are tagged with PCIDs.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: true  -> PCIDs are in use
+//          false -> every CR3 write flushes the TLB
+//==================================================================================================
+
+    ENABLED.load(Ordering::SeqCst)
+}
+
+
+//==================================================================================================
+pub fn allocate() -> u16 {
+//--------------------------------------------------------------------------------------------------
+// Hand out a PCID for a new address space. Once every PCID is taken, further spaces share
+// NO_PCID, which costs them a full flush on every switch but is otherwise harmless.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: the PCID, or NO_PCID if PCIDs are off or exhausted
+//==================================================================================================
+
+    if (!enabled()) {
+        return NO_PCID;
+    }
+
+    let mut pcids = PCIDS.lock();
+
+    for offset in 0 .. PCID_COUNT - 1 {
+        // PCID 0 is never handed out, so the search wraps from the top back to 1
+        let pcid = (pcids.next - 1 + offset) % (PCID_COUNT - 1) + 1;
+
+        if (!test_bit(&pcids.allocated, pcid)) {
+            set_bit(&mut pcids.allocated, pcid);
+            pcids.next = pcid % (PCID_COUNT - 1) + 1;
+            return pcid as u16;
+        }
+    }
+
+    NO_PCID
+}
+
+
+//==================================================================================================
+pub fn free(pcid: u16) {
+//--------------------------------------------------------------------------------------------------
+// Return a PCID once its address space is gone. Its TLB entries are dropped now if invpcid is
+// available, and otherwise on the first load by whichever space gets it next.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   pcid -> PCID to release
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    if (pcid == NO_PCID) {
+        return;
+    }
+
+    flush_context(pcid);
+    clear_bit(&mut PCIDS.lock().allocated, pcid as usize);
+}
+
+
+//==================================================================================================
+pub unsafe fn load(page_map: PhysicalAddress, pcid: u16) {
+//--------------------------------------------------------------------------------------------------
+// Write CR3, keeping the TLB entries already tagged with the PCID unless they have been marked
+// stale since the PCID was last running. Unsafe, as the page map must map the running kernel.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   page_map -> physical address of the PML4 to load
+//          pcid     -> PCID of the address space the PML4 belongs to
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    if (!enabled() || pcid == NO_PCID) {
+        control_regs::cr3_write(page_map.as_usize());
+        return;
+    }
+
+    let keep = {
+        let mut pcids = PCIDS.lock();
+        let stale = test_bit(&pcids.stale, pcid as usize);
+        clear_bit(&mut pcids.stale, pcid as usize);
+        !stale
+    };
+
+    let no_flush = if (keep) { CR3_NO_FLUSH } else { 0 };
+    control_regs::cr3_write(page_map.as_usize() | pcid as usize | no_flush);
+}
+
+
+//==================================================================================================
+pub fn current() -> u16 {
+//--------------------------------------------------------------------------------------------------
+// Obtain the PCID of the running address space.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: the PCID in CR3, NO_PCID if PCIDs are off
+//==================================================================================================
+
+    (unsafe { control_regs::cr3() } as u64 & PCID_MASK) as u16
+}
+
+
+//==================================================================================================
+pub fn flush_page(address: VirtualAddress) {
+//--------------------------------------------------------------------------------------------------
+// Drop the translation of one page after its entry was changed in the running space. The kernel
+// half, bar the recursive mapping, is shared by every space, so a kernel page may also be cached
+// under other PCIDs; those are marked stale and flushed when next loaded.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address -> any address in the page
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    unsafe { tlb::flush(address.as_usize()); }
+
+    let shared = address.as_usize() >= KERNEL_OFFSET && address.as_usize() < RECURSIVE_START;
+
+    if (enabled() && shared) {
+        let current = current() as usize;
+        let mut guard = PCIDS.lock();
+        let pcids = &mut *guard;
+
+        for word in 0 .. PCID_COUNT / WORD_BITS {
+            pcids.stale[word] |= pcids.allocated[word];
+        }
+        clear_bit(&mut pcids.stale, current);
+    }
+}
+
+
+//==================================================================================================
+pub fn flush_current() {
+//--------------------------------------------------------------------------------------------------
+// Drop every non-global translation of the running space, and nothing belonging to other PCIDs.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let pcid = current();
+
+    if (INVPCID.load(Ordering::SeqCst)) {
+        unsafe { invpcid(INVPCID_SINGLE_CONTEXT, pcid, 0); }
+    }
+    else {
+        // With PCIDs on, a CR3 write without CR3_NO_FLUSH flushes only the PCID it loads
+        unsafe { tlb::flush_all(); }
+    }
+}
+
+
+//==================================================================================================
+pub fn flush_context(pcid: u16) {
+//--------------------------------------------------------------------------------------------------
+// Drop every translation of a space whose tables were changed while it was not running. With
+// invpcid this happens at once; otherwise the PCID is flushed the next time it is loaded.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   pcid -> PCID of the changed space
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    if (pcid == NO_PCID) {
+        return;
+    }
+
+    if (INVPCID.load(Ordering::SeqCst)) {
+        unsafe { invpcid(INVPCID_SINGLE_CONTEXT, pcid, 0); }
+    }
+    else {
+        set_bit(&mut PCIDS.lock().stale, pcid as usize);
+    }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+unsafe fn invpcid(kind: u64, pcid: u16, address: u64) {
+//--------------------------------------------------------------------------------------------------
+// Execute the invpcid instruction. Unsafe, as it faults where unsupported.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   kind    -> invalidation type
+//          pcid    -> PCID the invalidation applies to
+//          address -> address the invalidation applies to, for single-address invalidations
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let descriptor = InvpcidDescriptor { pcid: pcid as u64, address: address };
+
+    asm!("invpcid $0, [$1]"
+         :: "r" (kind), "r" (&descriptor as *const InvpcidDescriptor)
+         : "memory" : "intel", "volatile");
+}
+
+
+//==================================================================================================
+fn test_bit(bitmap: &[u64; PCID_COUNT / WORD_BITS], bit: usize) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Check a bit of a PCID bitmap.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   bitmap -> bitmap to read
+//          bit    -> PCID whose bit to read
+//
+// RETURNS: true if the bit is set
+//==================================================================================================
+
+    bitmap[bit / WORD_BITS] & (1 << (bit % WORD_BITS)) != 0
+}
+
+
+//==================================================================================================
+fn set_bit(bitmap: &mut [u64; PCID_COUNT / WORD_BITS], bit: usize) {
+//--------------------------------------------------------------------------------------------------
+// Set a bit of a PCID bitmap.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   bitmap -> bitmap to change
+//          bit    -> PCID whose bit to set
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    bitmap[bit / WORD_BITS] |= 1 << (bit % WORD_BITS);
+}
+
+
+//==================================================================================================
+fn clear_bit(bitmap: &mut [u64; PCID_COUNT / WORD_BITS], bit: usize) {
+//--------------------------------------------------------------------------------------------------
+// Clear a bit of a PCID bitmap.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   bitmap -> bitmap to change
+//          bit    -> PCID whose bit to clear
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    bitmap[bit / WORD_BITS] &= !(1 << (bit % WORD_BITS));
+}
@@ -1,10 +1,29 @@
 
 use core::ptr::Unique;
-use memory::paging::table::{PageMap,Table,PAGE_MAP};
-use memory::paging::entry::{EntryFlags,PRESENT,HUGE_PAGE};
-use memory::paging::{Page,VirtualAddress,PhysicalAddress,InactivePageTable,ENTRY_COUNT};
+use memory::paging::table::{PageMap,Table,MetaLevel,PAGE_MAP};
+use memory::paging::entry::{PageEntry,EntryFlags,PRESENT,WRITABLE,ACCESSIBLE,HUGE_PAGE};
+use memory::paging::cow;
+use memory::paging::pcid;
+use memory::paging::walker::MappingIter;
+use memory::frame_table::{self, FrameUsage};
+use memory::paging::{Page,PageRange,FrameRange,VirtualAddress,PhysicalAddress,InactivePageTable,
+                     ENTRY_COUNT};
 use memory::{Frame,FrameAllocator,PAGE_SIZE};
-use ::x86::shared::tlb;
+use cpu;
+
+const USER_ENTRY_COUNT: usize = ENTRY_COUNT / 2;        // PML4 entries 0-255 map the lower half
+
+//==================================================================================================
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HugePageSize {
+//--------------------------------------------------------------------------------------------------
+// Sizes of the mappings made by a single page directory or pointer table entry.
+//==================================================================================================
+
+    TwoMiB,                             // Page directory entry with HUGE_PAGE set
+    OneGiB,                             // Pointer table entry with HUGE_PAGE set
+}
+
 
 //==================================================================================================
 pub struct PTMapper {
@@ -31,7 +50,7 @@ impl PTMapper {
     //==============================================================================================
     
         PTMapper {
-            page_map: Unique::new(PAGE_MAP),
+            page_map: Unique::new_unchecked(PAGE_MAP),
         }
     }
 
@@ -46,7 +65,7 @@ impl PTMapper {
     // RETURNS: An immutable reference to the page map
     //==============================================================================================
 
-        unsafe { self.page_map.get() }
+        unsafe { self.page_map.as_ref() }
     }
 
 
@@ -60,7 +79,7 @@ impl PTMapper {
     // RETURNS: A mutable reference to the page map
     //==============================================================================================
 
-        unsafe { self.page_map.get_mut() }
+        unsafe { self.page_map.as_mut() }
     }
 
     
@@ -75,9 +94,9 @@ impl PTMapper {
     //          None      -> virtual address was invalid   
     //==================================================================================================
 
-        let page_offset = v_addr & 0x0FFF;
+        let page_offset = v_addr.as_usize() % PAGE_SIZE;
         self.translate_page_to_frame(Page::containing_address(v_addr))
-            .map(|frame| frame.frame_num * PAGE_SIZE + page_offset)
+            .map(|frame| frame.address() + page_offset)
     }
 
 
@@ -99,24 +118,64 @@ impl PTMapper {
     }
 
 
+    //==================================================================================================
+    pub fn try_map_page<A: FrameAllocator>(&mut self, page: Page, flags: EntryFlags,
+                                           allocator: &mut A) -> bool {
+    //--------------------------------------------------------------------------------------------------
+    // Map the given page to the next free frame, without panicking when frames run out. Tables
+    // created before running out are left in place, empty.
+    //--------------------------------------------------------------------------------------------------
+    // TAKES:   page      -> page to be mapped
+    //          flags     -> flags to set the entry with
+    //          allocator -> allocator to allocate the frame and any new tables from
+    //
+    // RETURNS: true  -> the page is mapped
+    //          false -> no frame was left for the page or one of its tables
+    //==================================================================================================
+
+        let frame = match allocator.allocate_frame() {
+            Some(frame) => frame,
+            None        => return false,
+        };
+
+        let has_tables = self.page_map_mut()
+            .try_next_table_create(page.page_map_index(), allocator)
+            .and_then(|table| table.try_next_table_create(page.pointer_table_index(), allocator))
+            .and_then(|table| table.try_next_table_create(page.page_dir_index(), allocator))
+            .is_some();
+        if (!has_tables) {
+            allocator.deallocate_frame(frame);
+            return false;
+        }
+
+        self.map_page_to_frame(page, frame, flags, allocator);
+        true
+    }
+
+
     //==================================================================================================
     pub fn map_page_to_frame<A: FrameAllocator>(&mut self, page: Page, frame: Frame,
                                                 flags: EntryFlags, allocator: &mut A) {
     //--------------------------------------------------------------------------------------------------
-    // Map the given page to the given frame.
+    // Map the given page to the given frame, counting the mapping in the frame's descriptor. Panics
+    // if the page is already mapped, as the old frame's mapping would never be uncounted.
     //--------------------------------------------------------------------------------------------------
-    // TAKES:   page      ->
-    //          frame     -> 
-    //          flags     ->
-    //          allocator ->
+    // TAKES:   page      -> page to be mapped
+    //          frame     -> frame to map the page to
+    //          flags     -> flags to set the entry with
+    //          allocator -> allocator to allocate new tables if necessary
     //
     // RETURNS: nothing
     //==================================================================================================        
 
-        self.page_map_mut().next_table_create(page.page_map_index(), allocator)
+        let entry = &mut self.page_map_mut().next_table_create(page.page_map_index(), allocator)
             .next_table_create(page.pointer_table_index(), allocator)
             .next_table_create(page.page_dir_index(), allocator)
-            [page.page_table_index()].set(frame, flags | PRESENT);
+            [page.page_table_index()];
+        assert!(entry.is_unused(), "page {:#x} already mapped", page.starting_address());
+
+        frame_table::map(&frame);
+        entry.set(frame, flags | PRESENT);
     }
 
 
@@ -132,34 +191,474 @@ impl PTMapper {
     // RETURNS: nothing
     //==================================================================================================
         
-        self.map_page_to_frame(Page::containing_address(frame.address()), frame, flags, allocator);
+        let page = Page::containing_address(VirtualAddress::new(frame.address().as_usize()));
+        self.map_page_to_frame(page, frame, flags, allocator);
+    }
+
+
+    //==============================================================================================
+    pub fn map_range<A: FrameAllocator>(&mut self, pages: PageRange, flags: EntryFlags,
+                                        allocator: &mut A) {
+    //----------------------------------------------------------------------------------------------
+    // Map every page of a range to a freshly allocated frame. The frames need not be contiguous.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   pages     -> pages to map
+    //          flags     -> flags to set in each entry
+    //          allocator -> allocator to allocate frames and tables from
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        for page in pages {
+            self.map_page(page, flags, allocator);
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn map_range_to_frames<A: FrameAllocator>(&mut self, pages: PageRange, frames: FrameRange,
+                                                  flags: EntryFlags, allocator: &mut A) {
+    //----------------------------------------------------------------------------------------------
+    // Map a range of pages onto a range of frames of the same length, page for frame in order.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   pages     -> pages to map
+    //          frames    -> frames to map them to
+    //          flags     -> flags to set in each entry
+    //          allocator -> allocator to allocate new tables if necessary
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        assert!(pages.len() == frames.len(), "page and frame ranges differ in length");
+
+        for (page, frame) in pages.into_iter().zip(frames) {
+            self.map_page_to_frame(page, frame, flags, allocator);
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn identity_map_range<A: FrameAllocator>(&mut self, frames: FrameRange, flags: EntryFlags,
+                                                 allocator: &mut A) {
+    //----------------------------------------------------------------------------------------------
+    // Identity map every frame of a range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   frames    -> frames to identity map
+    //          flags     -> flags to set in each entry
+    //          allocator -> allocator to allocate new tables if necessary
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        for frame in frames {
+            self.identity_map(frame, flags, allocator);
+        }
     }
 
 
     //==============================================================================================
     pub fn unmap<A: FrameAllocator>(&mut self, page: Page, allocator: &mut A) {
-    //--------------------------------------------------------------------------------------------------
-    // Unmap a given page.
-    //--------------------------------------------------------------------------------------------------
+    //----------------------------------------------------------------------------------------------
+    // Unmap a given page and return its frame to the allocator, unless other mappings still share
+    // it, it is pinned, or it is not allocator memory the frame table saw being mapped. A huge
+    // mapping containing the page is split first, so that the rest of it stays mapped.
+    //----------------------------------------------------------------------------------------------
     // TAKES:   page      -> page to unmap
-    //          allocator -> allocator to allocate new tables if necessary
+    //          allocator -> allocator receiving the frame and any emptied tables
     //
     // RETURNS: nothing
-    //==================================================================================================
+    //==============================================================================================
+
+        let (frame, unused) = self.remove_mapping(page, allocator);
+
+        if (unused) {
+            allocator.deallocate_frame(frame);
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn unmap_shared<A: FrameAllocator>(&mut self, page: Page, allocator: &mut A) -> Frame {
+    //----------------------------------------------------------------------------------------------
+    // Unmap a given page but hand its frame back to the caller rather than to the allocator, for
+    // frames that are still mapped elsewhere or not owned by the allocator. The mapping is still
+    // dropped from the frame's reference count, and tables left empty are still freed.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   page      -> page to unmap
+    //          allocator -> allocator receiving any emptied tables
+    //
+    // RETURNS: the frame the page was mapped to
+    //==============================================================================================
+
+        self.remove_mapping(page, allocator).0
+    }
+
+
+    //==============================================================================================
+    fn remove_mapping<A: FrameAllocator>(&mut self, page: Page, allocator: &mut A)
+                                         -> (Frame, bool) {
+    //----------------------------------------------------------------------------------------------
+    // Clear a page's entry, splitting a huge mapping around it first, drop the mapping from the
+    // frame's reference count and free any tables left empty.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   page      -> page to unmap
+    //          allocator -> allocator receiving any emptied tables
+    //
+    // RETURNS: (frame the page was mapped to, whether the frame should go back to the allocator)
+    //==============================================================================================
 
         assert!(self.translate(page.starting_address()).is_some());
-        
-        let mut page_table = self.page_map_mut().next_table_mut(page.page_map_index())
+
+        self.split_huge(page, allocator);
+
+        let frame = {
+            let page_table = self.page_map_mut().next_table_mut(page.page_map_index())
+                .and_then(|ptr_tbl| ptr_tbl.next_table_mut(page.pointer_table_index()))
+                .and_then(|pg_dir| pg_dir.next_table_mut(page.page_dir_index()))
+                .expect("Page not mapped!");
+
+            let frame = page_table[page.page_table_index()].page_frame()
+                .expect("Page not mapped!");
+            page_table[page.page_table_index()].mark_unused();
+            frame
+        };
+
+        let unused = frame_table::unmap(&frame);
+
+        pcid::flush_page(page.starting_address());
+        self.reclaim_tables(page, allocator);
+        (frame, unused)
+    }
+
+
+    //==============================================================================================
+    pub fn unmap_range<A: FrameAllocator>(&mut self, pages: PageRange, allocator: &mut A) {
+    //----------------------------------------------------------------------------------------------
+    // Unmap every page of a range and return their frames to the allocator.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   pages     -> mapped pages to unmap
+    //          allocator -> allocator receiving the frames and any emptied tables
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        for page in pages {
+            self.unmap(page, allocator);
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn share_copy_on_write<A: FrameAllocator>(&mut self, source: Page, target: Page,
+                                                  allocator: &mut A) {
+    //----------------------------------------------------------------------------------------------
+    // Map a page to the same frame as another, copy-on-write: if the source is writable, both
+    // pages lose write access until one of them is written to, at which point the page fault
+    // handler hands the writer its own copy.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   source    -> mapped page to share
+    //          target    -> unmapped page to map to the source's frame
+    //          allocator -> allocator to allocate new tables from
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        self.split_huge(source, allocator);
+
+        let (frame, flags) = cow::share_entry(self.entry_mut(source).expect("Page not mapped!"));
+        pcid::flush_page(source.starting_address());
+
+        self.map_page_to_frame(target, frame, flags, allocator);
+    }
+
+
+    //==============================================================================================
+    pub fn update_flags<A: FrameAllocator>(&mut self, page: Page, flags: EntryFlags,
+                                           allocator: &mut A) {
+    //----------------------------------------------------------------------------------------------
+    // Replace the flags of a mapped page, keeping the frame it is mapped to. A huge mapping
+    // containing the page is split first, so that only this page changes.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   page      -> mapped page to update
+    //          flags     -> new flags for the page's entry
+    //          allocator -> allocator to allocate tables from when splitting
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        self.split_huge(page, allocator);
+
+        let page_table = self.page_map_mut().next_table_mut(page.page_map_index())
+            .and_then(|ptr_tbl| ptr_tbl.next_table_mut(page.pointer_table_index()))
+            .and_then(|pg_dir| pg_dir.next_table_mut(page.page_dir_index()))
+            .expect("Page not mapped!");
+
+        assert!(page_table[page.page_table_index()].page_frame().is_some(), "Page not mapped!");
+        page_table[page.page_table_index()].set_flags(flags | PRESENT);
+        pcid::flush_page(page.starting_address());
+    }
+
+
+    //==============================================================================================
+    pub fn map_huge_2m<A: FrameAllocator>(&mut self, page: Page, frame: Frame, flags: EntryFlags,
+                                          allocator: &mut A) {
+    //----------------------------------------------------------------------------------------------
+    // Map a 2 MiB region with a single page directory entry.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   page      -> first page of the region, 2 MiB aligned
+    //          frame     -> first frame of the region, 2 MiB aligned
+    //          flags     -> flags to set in the entry
+    //          allocator -> allocator to allocate new tables if necessary
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        let page_count = HugePageSize::TwoMiB.page_count();
+        assert!(page.page_num % page_count == 0 && frame.frame_num % page_count == 0,
+                "2 MiB mapping must be 2 MiB aligned");
+
+        let pg_dir = self.page_map_mut().next_table_create(page.page_map_index(), allocator)
+            .next_table_create(page.pointer_table_index(), allocator);
+
+        assert!(!pg_dir[page.page_dir_index()].flags().contains(PRESENT),
+                "2 MiB region already mapped");
+        pg_dir[page.page_dir_index()].set(frame, flags | PRESENT | HUGE_PAGE);
+    }
+
+
+    //==============================================================================================
+    pub fn map_huge_1g<A: FrameAllocator>(&mut self, page: Page, frame: Frame, flags: EntryFlags,
+                                          allocator: &mut A) {
+    //----------------------------------------------------------------------------------------------
+    // Map a 1 GiB region with a single pointer table entry. The processor must support 1 GiB
+    // pages, see cpu::supports_1g_pages.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   page      -> first page of the region, 1 GiB aligned
+    //          frame     -> first frame of the region, 1 GiB aligned
+    //          flags     -> flags to set in the entry
+    //          allocator -> allocator to allocate new tables if necessary
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        assert!(cpu::supports_1g_pages(), "1 GiB pages not supported by this processor");
+
+        let page_count = HugePageSize::OneGiB.page_count();
+        assert!(page.page_num % page_count == 0 && frame.frame_num % page_count == 0,
+                "1 GiB mapping must be 1 GiB aligned");
+
+        let ptr_tbl = self.page_map_mut().next_table_create(page.page_map_index(), allocator);
+
+        assert!(!ptr_tbl[page.pointer_table_index()].flags().contains(PRESENT),
+                "1 GiB region already mapped");
+        ptr_tbl[page.pointer_table_index()].set(frame, flags | PRESENT | HUGE_PAGE);
+    }
+
+
+    //==============================================================================================
+    pub fn unmap_huge<A: FrameAllocator>(&mut self, page: Page, allocator: &mut A)
+                                         -> (Frame, HugePageSize) {
+    //----------------------------------------------------------------------------------------------
+    // Unmap a whole 2 MiB or 1 GiB mapping. Huge frames are usually device memory or contiguous
+    // blocks, so they are handed back to the caller; tables left empty are freed.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   page      -> first page of the huge mapping
+    //          allocator -> allocator receiving any emptied tables
+    //
+    // RETURNS: the first frame of the mapping and the mapping's size
+    //==============================================================================================
+
+        let size = self.huge_page_size(page).expect("Page not part of a huge mapping!");
+        assert!(page.page_num % size.page_count() == 0, "Page does not start its huge mapping!");
+
+        let frame = {
+            let ptr_tbl = self.page_map_mut().next_table_mut(page.page_map_index()).unwrap();
+            let entry = match size {
+                HugePageSize::OneGiB => &mut ptr_tbl[page.pointer_table_index()],
+                HugePageSize::TwoMiB => {
+                    &mut ptr_tbl.next_table_mut(page.pointer_table_index()).unwrap()
+                        [page.page_dir_index()]
+                },
+            };
+
+            let frame = entry.target_frame().unwrap();
+            entry.mark_unused();
+            frame
+        };
+
+        // Invalidating any address within a huge page drops its whole translation
+        pcid::flush_page(page.starting_address());
+        self.reclaim_tables(page, allocator);
+        (frame, size)
+    }
+
+
+    //==============================================================================================
+    pub fn split_huge<A: FrameAllocator>(&mut self, page: Page, allocator: &mut A) {
+    //----------------------------------------------------------------------------------------------
+    // Break the huge mapping containing a page into smaller mappings with the same frames and
+    // flags, until the page is mapped by a 4 KiB entry. Does nothing if the page is not part of a
+    // huge mapping.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   page      -> page that must end up in a 4 KiB mapping
+    //          allocator -> allocator to allocate the new tables from
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        while let Some(size) = self.huge_page_size(page) {
+            let ptr_tbl = self.page_map_mut().next_table_mut(page.page_map_index()).unwrap();
+
+            match size {
+                HugePageSize::OneGiB => {
+                    split_entry(ptr_tbl, page.pointer_table_index(),
+                                HugePageSize::TwoMiB.page_count(), allocator)
+                },
+                HugePageSize::TwoMiB => {
+                    let pg_dir = ptr_tbl.next_table_mut(page.pointer_table_index()).unwrap();
+                    split_entry(pg_dir, page.page_dir_index(), 1, allocator)
+                },
+            }
+
+            // invlpg drops the huge translation whatever address in it is given
+            pcid::flush_page(page.starting_address());
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn huge_page_size(&self, page: Page) -> Option<HugePageSize> {
+    //----------------------------------------------------------------------------------------------
+    // Determine whether a page is mapped as part of a huge page.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   page -> page to examine
+    //
+    // RETURNS: Some(...) -> size of the huge mapping containing the page
+    //          None      -> page is unmapped or mapped by a 4 KiB entry
+    //==============================================================================================
+
+        let ptr_tbl = match self.page_map().next_table(page.page_map_index()) {
+            Some(ptr_tbl) => ptr_tbl,
+            None          => return None,
+        };
+
+        if (ptr_tbl[page.pointer_table_index()].flags().contains(PRESENT | HUGE_PAGE)) {
+            return Some(HugePageSize::OneGiB);
+        }
+
+        ptr_tbl.next_table(page.pointer_table_index())
+            .and_then(|pg_dir| {
+                if (pg_dir[page.page_dir_index()].flags().contains(PRESENT | HUGE_PAGE)) {
+                    Some(HugePageSize::TwoMiB)
+                }
+                else {
+                    None
+                }
+            })
+    }
+
+    
+    //==============================================================================================
+    pub fn entry_mut(&mut self, page: Page) -> Option<&mut PageEntry> {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the 4 KiB entry mapping a page, for callers that manage entry bits themselves.
+    // Callers changing the entry must flush the page from the TLB.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   page -> page to look up
+    //
+    // RETURNS: Some(...) -> the present entry mapping the page
+    //          None      -> page is unmapped or part of a huge mapping
+    //==============================================================================================
+
+        self.page_map_mut().next_table_mut(page.page_map_index())
             .and_then(|ptr_tbl| ptr_tbl.next_table_mut(page.pointer_table_index()))
             .and_then(|pg_dir| pg_dir.next_table_mut(page.page_dir_index()))
-            .expect("Huge pages not currently supported!");
+            .map(|pg_tbl| &mut pg_tbl[page.page_table_index()])
+            .and_then(|entry| if (entry.is_unused()) { None } else { Some(entry) })
+    }
+
 
-        let frame = page_table[page.page_table_index()].target_frame().expect("Page not mapped!");
-        page_table[page.page_table_index()].mark_unused();
-        unsafe {tlb::flush(page.starting_address());}
+    //==============================================================================================
+    pub fn mappings(&self) -> MappingIter {
+    //----------------------------------------------------------------------------------------------
+    // Walk every present mapping, coalescing runs that are contiguous and share their flags.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: iterator over the runs, in address order
+    //==============================================================================================
+
+        MappingIter::new(self.page_map())
+    }
+
+
+    //==============================================================================================
+    pub fn print_mappings(&self) {
+    //----------------------------------------------------------------------------------------------
+    // Print every run of mappings to the console, one per line.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        println!("Virtual memory map:");
+        for mapping in self.mappings() {
+            println!("   {}", mapping);
+        }
     }
 
     
+    //==============================================================================================
+    fn reclaim_tables<A: FrameAllocator>(&mut self, page: Page, allocator: &mut A) {
+    //----------------------------------------------------------------------------------------------
+    // Free the page table, page directory and pointer table on the path to a page if they no longer
+    // hold any entries. Pointer tables of the kernel half are kept, as they are preallocated and
+    // their page map entries shared by every address space.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   page      -> page that was just unmapped
+    //          allocator -> allocator to return the tables' frames to
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        {
+            let pg_dir = match self.page_map_mut().next_table_mut(page.page_map_index())
+                .and_then(|ptr_tbl| ptr_tbl.next_table_mut(page.pointer_table_index())) {
+                Some(pg_dir) => pg_dir,
+                None         => return,
+            };
+
+            if (!pg_dir.next_table(page.page_dir_index()).map_or(false, |table| table.is_empty())) {
+                return;
+            }
+
+            free_table(pg_dir, page.page_dir_index(), allocator);
+        }
+
+        {
+            let ptr_tbl = self.page_map_mut().next_table_mut(page.page_map_index()).unwrap();
+
+            let empty = ptr_tbl.next_table(page.pointer_table_index())
+                .map_or(false, |pg_dir| pg_dir.is_empty());
+            if (!empty) {
+                return;
+            }
+
+            free_table(ptr_tbl, page.pointer_table_index(), allocator);
+        }
+
+        if (page.page_map_index() >= USER_ENTRY_COUNT) {
+            return;
+        }
+
+        let page_map = self.page_map_mut();
+        if (page_map.next_table(page.page_map_index()).map_or(false, |table| table.is_empty())) {
+            free_table(page_map, page.page_map_index(), allocator);
+        }
+    }
+
+
     //==================================================================================================
     fn translate_page_to_frame(&self, page: Page) -> Option<Frame> {
     //--------------------------------------------------------------------------------------------------
@@ -220,7 +719,102 @@ impl PTMapper {
         // Attempt to resolve virtual
         pointer_table.and_then(|ptr_tbl| ptr_tbl.next_table(page.pointer_table_index()))
             .and_then(|pg_dir| pg_dir.next_table(page.page_dir_index()))
-            .and_then(|pg_tbl| pg_tbl[page.page_table_index()].target_frame())
+            .and_then(|pg_tbl| pg_tbl[page.page_table_index()].page_frame())
             .or_else(check_huge_page)
     } 
 }
+
+
+//==================================================================================================
+impl HugePageSize {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn page_count(&self) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the number of 4 KiB pages covered by a mapping of this size.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: number of pages in the mapping
+    //==============================================================================================
+
+        match *self {
+            HugePageSize::TwoMiB => ENTRY_COUNT,
+            HugePageSize::OneGiB => ENTRY_COUNT * ENTRY_COUNT,
+        }
+    }
+}
+
+
+//==================================================================================================
+fn split_entry<L: MetaLevel, A: FrameAllocator>(table: &mut Table<L>, index: usize,
+                                                child_page_count: usize, allocator: &mut A) {
+//--------------------------------------------------------------------------------------------------
+// Replace a huge entry with a new table whose entries map the same frames with the same flags.
+// Permissions are enforced by the new entries, so the entry pointing to the table grants as much
+// as possible. New 4 KiB entries are counted in the frame table; huge ones, like every huge
+// mapping, are not.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   table            -> table holding the huge entry
+//          index            -> index of the huge entry
+//          child_page_count -> pages mapped by each entry of the new table
+//          allocator        -> allocator to allocate the new table from
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let flags = table[index].flags();
+    let pat = table[index].has_huge_pat();
+    let frame = table[index].target_frame().expect("Huge entry not present!");
+
+    // HUGE_PAGE occupies the PAT bit in 4 KiB entries, so PAT moves down from bit 12 for them
+    let child_flags = if (child_page_count == 1) { table[index].split_flags() } else { flags };
+
+    let table_frame = allocator.allocate_frame().expect("unable to allocate frame");
+    frame_table::set_usage(&table_frame, FrameUsage::PageTable);
+    table[index].set(table_frame, PRESENT | WRITABLE | (flags & ACCESSIBLE));
+
+    let child = table.next_table_mut(index).unwrap();
+
+    // The table's recursive address may still hold a stale translation into the huge page
+    pcid::flush_page(VirtualAddress::new(child as *const _ as usize));
+
+    for child_index in 0 .. ENTRY_COUNT {
+        let child_frame = Frame { frame_num: frame.frame_num + child_index * child_page_count };
+
+        // 4 KiB mappings are counted, so that unmapping one later sees it was tracked
+        if (child_page_count == 1) {
+            frame_table::map(&child_frame);
+        }
+
+        child[child_index].set(child_frame, child_flags);
+
+        if (child_page_count > 1) {
+            child[child_index].set_huge_pat(pat);
+        }
+    }
+}
+
+
+//==================================================================================================
+fn free_table<L: MetaLevel, A: FrameAllocator>(table: &mut Table<L>, index: usize,
+                                               allocator: &mut A) {
+//--------------------------------------------------------------------------------------------------
+// Remove the entry pointing to a child table and return the child's frame to the allocator.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   table     -> table holding the entry
+//          index     -> index of the entry pointing to the child table
+//          allocator -> allocator to return the child's frame to
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let child = table.next_table(index).expect("No table to free!") as *const _ as usize;
+    let frame = table[index].target_frame().unwrap();
+
+    table[index].mark_unused();
+    pcid::flush_page(VirtualAddress::new(child));
+    allocator.deallocate_frame(frame);
+}
@@ -0,0 +1,625 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/memory/paging: range.rs                                                                 #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::{Frame, PAGE_SIZE};
+use memory::paging::{Page, VirtualAddress, PhysicalAddress};
+use core::cmp;
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+//==================================================================================================
+pub struct PageRange {
+//--------------------------------------------------------------------------------------------------
+// Contiguous run of virtual pages. The end is exclusive, so an empty range has start == end.
+//==================================================================================================
+
+    start: usize,                       // Number of the first page in the range
+    end: usize,                         // Number of the page one past the end of the range
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+//==================================================================================================
+pub struct FrameRange {
+//--------------------------------------------------------------------------------------------------
+// Contiguous run of physical frames. The end is exclusive, so an empty range has start == end.
+//==================================================================================================
+
+    start: usize,                       // Number of the first frame in the range
+    end: usize,                         // Number of the frame one past the end of the range
+}
+
+
+//==================================================================================================
+pub struct PageIter {
+//--------------------------------------------------------------------------------------------------
+// Iterator over the pages of a PageRange, advancing a fixed number of pages at a time.
+//==================================================================================================
+
+    next: usize,                        // Number of the next page to yield
+    end: usize,                         // Number of the page one past the end of the range
+    step: usize,                        // Pages to advance between yields
+}
+
+
+//==================================================================================================
+pub struct FrameIter {
+//--------------------------------------------------------------------------------------------------
+// Iterator over the frames of a FrameRange, advancing a fixed number of frames at a time.
+//==================================================================================================
+
+    next: usize,                        // Number of the next frame to yield
+    end: usize,                         // Number of the frame one past the end of the range
+    step: usize,                        // Frames to advance between yields
+}
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl PageRange {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn new(start: Page, end: Page) -> PageRange {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for PageRange, covering [start, end).
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   start -> first page of the range
+    //          end   -> page one past the end of the range, no lower than start
+    //
+    // RETURNS: the range of pages
+    //==============================================================================================
+
+        assert!(start.page_num <= end.page_num, "page range ends before it starts");
+        PageRange { start: start.page_num, end: end.page_num }
+    }
+
+
+    //==============================================================================================
+    pub fn new_inclusive(start: Page, last: Page) -> PageRange {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for PageRange, covering [start, last].
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   start -> first page of the range
+    //          last  -> last page of the range, no lower than start
+    //
+    // RETURNS: the range of pages
+    //==============================================================================================
+
+        assert!(start.page_num <= last.page_num, "page range ends before it starts");
+        PageRange { start: start.page_num, end: last.page_num + 1 }
+    }
+
+
+    //==============================================================================================
+    pub fn containing(start: VirtualAddress, end: VirtualAddress) -> PageRange {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for PageRange, covering every page that shares memory with the addresses
+    // [start, end). Neither address needs to be page aligned; a partial last page is included.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   start -> first address to cover
+    //          end   -> address one past the last to cover
+    //
+    // RETURNS: the smallest range of pages holding the addresses
+    //==============================================================================================
+
+        assert!(start <= end, "address range ends before it starts");
+        PageRange {
+            start: start.as_usize() / PAGE_SIZE,
+            end: end.align_up(PAGE_SIZE).as_usize() / PAGE_SIZE,
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn start(&self) -> Page {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the first page of the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the first page, or the end page if the range is empty
+    //==============================================================================================
+
+        Page { page_num: self.start }
+    }
+
+
+    //==============================================================================================
+    pub fn end(&self) -> Page {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the page one past the end of the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the exclusive end page
+    //==============================================================================================
+
+        Page { page_num: self.end }
+    }
+
+
+    //==============================================================================================
+    pub fn len(&self) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the number of pages in the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: page count
+    //==============================================================================================
+
+        self.end - self.start
+    }
+
+
+    //==============================================================================================
+    pub fn is_empty(&self) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether the range holds no pages.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: true  -> range is empty
+    //          false -> range holds at least one page
+    //==============================================================================================
+
+        self.start == self.end
+    }
+
+
+    //==============================================================================================
+    pub fn contains(&self, page: Page) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether a page lies within the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   page -> page to check
+    //
+    // RETURNS: true  -> page is part of the range
+    //          false -> page lies outside the range
+    //==============================================================================================
+
+        page.page_num >= self.start && page.page_num < self.end
+    }
+
+
+    //==============================================================================================
+    pub fn contains_range(&self, other: &PageRange) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether every page of another range lies within this one. An empty range is contained
+    // in every range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   other -> range to check
+    //
+    // RETURNS: true  -> other is a subrange of this range
+    //          false -> some page of other lies outside this range
+    //==============================================================================================
+
+        other.is_empty() || (other.start >= self.start && other.end <= self.end)
+    }
+
+
+    //==============================================================================================
+    pub fn intersection(&self, other: &PageRange) -> Option<PageRange> {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the pages shared by this range and another.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   other -> range to intersect with
+    //
+    // RETURNS: Some(...) -> the non-empty range of shared pages
+    //          None      -> the ranges are disjoint
+    //==============================================================================================
+
+        let start = cmp::max(self.start, other.start);
+        let end = cmp::min(self.end, other.end);
+
+        if (start < end) { Some(PageRange { start: start, end: end }) } else { None }
+    }
+
+
+    //==============================================================================================
+    pub fn split_at(&self, page: Page) -> (PageRange, PageRange) {
+    //----------------------------------------------------------------------------------------------
+    // Divide the range in two at a page. A page before the range leaves the first half empty, and
+    // one past its end leaves the second half empty.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   page -> first page of the second half
+    //
+    // RETURNS: (pages before page, pages from page onwards)
+    //==============================================================================================
+
+        let middle = cmp::min(cmp::max(page.page_num, self.start), self.end);
+
+        (PageRange { start: self.start, end: middle }, PageRange { start: middle, end: self.end })
+    }
+
+
+    //==============================================================================================
+    pub fn iter(&self) -> PageIter {
+    //----------------------------------------------------------------------------------------------
+    // Obtain an iterator over every page of the range, in ascending order.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: iterator yielding each page once
+    //==============================================================================================
+
+        self.step_by(1)
+    }
+
+
+    //==============================================================================================
+    pub fn step_by(&self, pages: usize) -> PageIter {
+    //----------------------------------------------------------------------------------------------
+    // Obtain an iterator over the range that advances several pages at a time, such as one huge
+    // page's worth. The first page yielded is the start of the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   pages -> number of pages to advance between yields, at least one
+    //
+    // RETURNS: iterator yielding every pages-th page of the range
+    //==============================================================================================
+
+        assert!(pages > 0, "page range step must not be zero");
+        PageIter { next: self.start, end: self.end, step: pages }
+    }
+}
+
+
+//==================================================================================================
+impl IntoIterator for PageRange {
+//==================================================================================================
+
+    type Item = Page;
+    type IntoIter = PageIter;
+
+
+    //==============================================================================================
+    fn into_iter(self) -> PageIter {
+    //----------------------------------------------------------------------------------------------
+    // Obtain an iterator over every page of the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: iterator yielding each page once
+    //==============================================================================================
+
+        self.iter()
+    }
+}
+
+
+//==================================================================================================
+impl Iterator for PageIter {
+//==================================================================================================
+
+    type Item = Page;
+
+
+    //==============================================================================================
+    fn next(&mut self) -> Option<Page> {
+    //----------------------------------------------------------------------------------------------
+    // Yield the next page of the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: Some(...) -> the next page
+    //          None      -> the range is exhausted
+    //==============================================================================================
+
+        if (self.next >= self.end) {
+            return None;
+        }
+
+        let page = Page { page_num: self.next };
+        self.next = cmp::min(self.next.saturating_add(self.step), self.end);
+        Some(page)
+    }
+}
+
+
+//==================================================================================================
+impl FrameRange {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn new(start: Frame, end: Frame) -> FrameRange {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for FrameRange, covering [start, end).
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   start -> first frame of the range
+    //          end   -> frame one past the end of the range, no lower than start
+    //
+    // RETURNS: the range of frames
+    //==============================================================================================
+
+        assert!(start.frame_num <= end.frame_num, "frame range ends before it starts");
+        FrameRange { start: start.frame_num, end: end.frame_num }
+    }
+
+
+    //==============================================================================================
+    pub fn new_inclusive(start: Frame, last: Frame) -> FrameRange {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for FrameRange, covering [start, last].
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   start -> first frame of the range
+    //          last  -> last frame of the range, no lower than start
+    //
+    // RETURNS: the range of frames
+    //==============================================================================================
+
+        assert!(start.frame_num <= last.frame_num, "frame range ends before it starts");
+        FrameRange { start: start.frame_num, end: last.frame_num + 1 }
+    }
+
+
+    //==============================================================================================
+    pub fn containing(start: PhysicalAddress, end: PhysicalAddress) -> FrameRange {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for FrameRange, covering every frame that shares memory with the
+    // addresses [start, end). Neither address needs to be page aligned; a partial last frame is
+    // included.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   start -> first address to cover
+    //          end   -> address one past the last to cover
+    //
+    // RETURNS: the smallest range of frames holding the addresses
+    //==============================================================================================
+
+        assert!(start <= end, "address range ends before it starts");
+        FrameRange {
+            start: start.as_usize() / PAGE_SIZE,
+            end: end.align_up(PAGE_SIZE).as_usize() / PAGE_SIZE,
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn start(&self) -> Frame {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the first frame of the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the first frame, or the end frame if the range is empty
+    //==============================================================================================
+
+        Frame { frame_num: self.start }
+    }
+
+
+    //==============================================================================================
+    pub fn end(&self) -> Frame {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the frame one past the end of the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the exclusive end frame
+    //==============================================================================================
+
+        Frame { frame_num: self.end }
+    }
+
+
+    //==============================================================================================
+    pub fn len(&self) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the number of frames in the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: frame count
+    //==============================================================================================
+
+        self.end - self.start
+    }
+
+
+    //==============================================================================================
+    pub fn is_empty(&self) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether the range holds no frames.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: true  -> range is empty
+    //          false -> range holds at least one frame
+    //==============================================================================================
+
+        self.start == self.end
+    }
+
+
+    //==============================================================================================
+    pub fn contains(&self, frame: &Frame) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether a frame lies within the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   frame -> frame to check
+    //
+    // RETURNS: true  -> frame is part of the range
+    //          false -> frame lies outside the range
+    //==============================================================================================
+
+        frame.frame_num >= self.start && frame.frame_num < self.end
+    }
+
+
+    //==============================================================================================
+    pub fn contains_range(&self, other: &FrameRange) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether every frame of another range lies within this one. An empty range is
+    // contained in every range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   other -> range to check
+    //
+    // RETURNS: true  -> other is a subrange of this range
+    //          false -> some frame of other lies outside this range
+    //==============================================================================================
+
+        other.is_empty() || (other.start >= self.start && other.end <= self.end)
+    }
+
+
+    //==============================================================================================
+    pub fn intersection(&self, other: &FrameRange) -> Option<FrameRange> {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the frames shared by this range and another.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   other -> range to intersect with
+    //
+    // RETURNS: Some(...) -> the non-empty range of shared frames
+    //          None      -> the ranges are disjoint
+    //==============================================================================================
+
+        let start = cmp::max(self.start, other.start);
+        let end = cmp::min(self.end, other.end);
+
+        if (start < end) { Some(FrameRange { start: start, end: end }) } else { None }
+    }
+
+
+    //==============================================================================================
+    pub fn split_at(&self, frame: &Frame) -> (FrameRange, FrameRange) {
+    //----------------------------------------------------------------------------------------------
+    // Divide the range in two at a frame. A frame before the range leaves the first half empty,
+    // and one past its end leaves the second half empty.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   frame -> first frame of the second half
+    //
+    // RETURNS: (frames before frame, frames from frame onwards)
+    //==============================================================================================
+
+        let middle = cmp::min(cmp::max(frame.frame_num, self.start), self.end);
+
+        (FrameRange { start: self.start, end: middle }, FrameRange { start: middle, end: self.end })
+    }
+
+
+    //==============================================================================================
+    pub fn iter(&self) -> FrameIter {
+    //----------------------------------------------------------------------------------------------
+    // Obtain an iterator over every frame of the range, in ascending order.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: iterator yielding each frame once
+    //==============================================================================================
+
+        self.step_by(1)
+    }
+
+
+    //==============================================================================================
+    pub fn step_by(&self, frames: usize) -> FrameIter {
+    //----------------------------------------------------------------------------------------------
+    // Obtain an iterator over the range that advances several frames at a time, such as one huge
+    // page's worth. The first frame yielded is the start of the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   frames -> number of frames to advance between yields, at least one
+    //
+    // RETURNS: iterator yielding every frames-th frame of the range
+    //==============================================================================================
+
+        assert!(frames > 0, "frame range step must not be zero");
+        FrameIter { next: self.start, end: self.end, step: frames }
+    }
+}
+
+
+//==================================================================================================
+impl IntoIterator for FrameRange {
+//==================================================================================================
+
+    type Item = Frame;
+    type IntoIter = FrameIter;
+
+
+    //==============================================================================================
+    fn into_iter(self) -> FrameIter {
+    //----------------------------------------------------------------------------------------------
+    // Obtain an iterator over every frame of the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: iterator yielding each frame once
+    //==============================================================================================
+
+        self.iter()
+    }
+}
+
+
+//==================================================================================================
+impl Iterator for FrameIter {
+//==================================================================================================
+
+    type Item = Frame;
+
+
+    //==============================================================================================
+    fn next(&mut self) -> Option<Frame> {
+    //----------------------------------------------------------------------------------------------
+    // Yield the next frame of the range.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: Some(...) -> the next frame
+    //          None      -> the range is exhausted
+    //==============================================================================================
+
+        if (self.next >= self.end) {
+            return None;
+        }
+
+        let frame = Frame { frame_num: self.next };
+        self.next = cmp::min(self.next.saturating_add(self.step), self.end);
+        Some(frame)
+    }
+}
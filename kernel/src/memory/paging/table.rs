@@ -44,9 +44,12 @@
 
 use memory::paging::entry::*;
 use memory::FrameAllocator;
-use memory::paging::ENTRY_COUNT;
+use memory::frame_table::{self, FrameUsage};
+use memory::paging::{pcid, ENTRY_COUNT, VirtualAddress};
 use core::ops::{Index,IndexMut};
 use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
 
 
 //##################################################################################################
@@ -56,6 +59,8 @@ use core::marker::PhantomData;
 
 pub const PAGE_MAP:   *mut Table<PageMap> = 0xFFFF_FFFF_FFFF_F000 as *mut _;
 
+const MAX_WINDOW_PAGES: usize = 64;     // Recursive-window pages logged before a full flush
+
 
 //##################################################################################################
 //********************************************* TRAITS *********************************************
@@ -110,6 +115,31 @@ pub struct Table<Level: TableLevel> {
 }
 
 
+//==================================================================================================
+struct WindowLog {
+//--------------------------------------------------------------------------------------------------
+// Recursive-window addresses of the tables reached while the recursive entry is borrowed.
+//==================================================================================================
+
+    pages: [usize; MAX_WINDOW_PAGES],   // Window addresses, each invalidated on first use
+    count: usize,                       // Number of pages logged
+    overflowed: bool,                   // More tables were reached than pages can hold
+}
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+static WINDOW_LOGGING: AtomicBool = AtomicBool::new(false);
+static WINDOW_LOG: Mutex<WindowLog> = Mutex::new(WindowLog {
+    pages: [0; MAX_WINDOW_PAGES],
+    count: 0,
+    overflowed: false,
+});
+
+
 //##################################################################################################
 //*************************************** ENUM IMPLEMENTATIONS *************************************
 //##################################################################################################
@@ -158,6 +188,21 @@ impl<Level: TableLevel> Table<Level>  {
             entry.mark_unused();
         }
     }   
+
+
+    //==============================================================================================
+    pub fn is_empty(&self) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether every entry in the table is unused.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: true  -> no entry is present
+    //          false -> at least one entry is present
+    //==============================================================================================
+
+        self.entries.iter().all(|entry| entry.is_unused())
+    }
 }
 
 
@@ -178,7 +223,11 @@ impl <Level: MetaLevel> Table<Level> {
     //==============================================================================================
 
         if (self[index].flags().contains(PRESENT) && !self[index].flags().contains(HUGE_PAGE)) {
-            Some((self as *const _ as usize) << 9 | index << 12)
+            let address = (self as *const _ as usize) << 9 | index << 12;
+            if (WINDOW_LOGGING.load(Ordering::SeqCst)) {
+                log_window_page(address);
+            }
+            Some(address)
         }
         else {
             None
@@ -226,12 +275,35 @@ impl <Level: MetaLevel> Table<Level> {
     //
     //==============================================================================================
 
+        self.try_next_table_create(i, allocator).expect("unable to allocate_frame")
+    }
+
+
+    //==============================================================================================
+    pub fn try_next_table_create<A: FrameAllocator>(&mut self, i: usize, allocator: &mut A)
+                                                    -> Option<&mut Table<Level::NextLevel>> {
+    //----------------------------------------------------------------------------------------------
+    // Obtain a mutable reference to the table pointed to by the ith entry, creating the table if
+    // the entry is unused.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   i         -> index of the entry to follow
+    //          allocator -> allocator to allocate the table from
+    //
+    // RETURNS: Some(...) -> mutable reference to the table pointed to in the ith entry
+    //          None      -> the table was missing and no frame was left to create it
+    //==============================================================================================
+
+        assert!(!self.entries[i].flags().contains(HUGE_PAGE), "mapping inside a huge page");
         if (self.next_table(i).is_none()) {
-            self.entries[i].set(allocator.allocate_frame().expect("unable to allocate_frame"), (PRESENT | WRITABLE));
+            let frame = match allocator.allocate_frame() {
+                Some(frame) => frame,
+                None        => return None,
+            };
+            frame_table::set_usage(&frame, FrameUsage::PageTable);
+            self.entries[i].set(frame, (PRESENT | WRITABLE));
             self.next_table_mut(i).unwrap().clear();
-                                
         }
-        self.next_table_mut(i).unwrap()
+        self.next_table_mut(i)
     }
 }
 
@@ -280,3 +352,89 @@ impl<Level: TableLevel> IndexMut<usize> for Table<Level> {
         &mut self.entries[index]
     }
 }
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn start_window_log() {
+//--------------------------------------------------------------------------------------------------
+// Begin logging the tables reached through the recursive entry, once it points at another table.
+// Each window page is invalidated as it is first reached, dropping what it translated to before.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    {
+        let mut log = WINDOW_LOG.lock();
+        log.count = 0;
+        log.overflowed = false;
+    }
+
+    pcid::flush_page(VirtualAddress::new(PAGE_MAP as usize));
+    WINDOW_LOGGING.store(true, Ordering::SeqCst);
+}
+
+
+//==================================================================================================
+pub fn finish_window_log() {
+//--------------------------------------------------------------------------------------------------
+// Stop logging, once the recursive entry is restored, and invalidate every window page reached
+// since start_window_log. Falls back to flushing the running space if too many were reached.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    WINDOW_LOGGING.store(false, Ordering::SeqCst);
+
+    let log = WINDOW_LOG.lock();
+    if (log.overflowed) {
+        pcid::flush_current();
+        return;
+    }
+
+    pcid::flush_page(VirtualAddress::new(PAGE_MAP as usize));
+    for page in log.pages[.. log.count].iter() {
+        pcid::flush_page(VirtualAddress::new(*page));
+    }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn log_window_page(address: usize) {
+//--------------------------------------------------------------------------------------------------
+// Record a window page about to be used, invalidating it the first time it is seen.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address -> recursive-window address of a table
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let mut log = WINDOW_LOG.lock();
+    if (log.pages[.. log.count].contains(&address)) {
+        return;
+    }
+
+    pcid::flush_page(VirtualAddress::new(address));
+
+    if (log.count < MAX_WINDOW_PAGES) {
+        let count = log.count;
+        log.pages[count] = address;
+        log.count += 1;
+    }
+    else {
+        log.overflowed = true;
+    }
+}
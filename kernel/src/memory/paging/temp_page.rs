@@ -43,7 +43,7 @@
 
 
 use super::{Page,ActivePageTable,VirtualAddress};
-use super::entry::WRITABLE;
+use super::entry::{WRITABLE,NO_EXEC};
 use memory::{Frame,FrameAllocator};
 use super::table::{Table,PageTable};
 
@@ -119,7 +119,7 @@ impl TempPage {
     // RETURNS: Virtual address of the temporary page.
     //==============================================================================================
 
-        active_table.map_page_to_frame(self.page, frame, WRITABLE, &mut self.allocator);
+        active_table.map_page_to_frame(self.page, frame, WRITABLE | NO_EXEC, &mut self.allocator);
         self.page.starting_address()
     }
 
@@ -136,7 +136,7 @@ impl TempPage {
     // RETURNS: a mutable reference to a PageTable
     //==============================================================================================
 
-        unsafe { &mut *(self.map_to_frame(frame, active_table) as *mut Table<PageTable>) }
+        unsafe { &mut *self.map_to_frame(frame, active_table).as_mut_ptr::<Table<PageTable>>() }
     }  
 
     
@@ -150,7 +150,8 @@ impl TempPage {
     // RETURNS: nothing
     //==============================================================================================
 
-        active_table.unmap(self.page, &mut self.allocator);
+        // The frame belongs to whoever asked for it to be mapped, so it is not freed
+        active_table.unmap_shared(self.page, &mut self.allocator);
     }
 }
 
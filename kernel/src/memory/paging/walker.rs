@@ -0,0 +1,355 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/memory/paging: walker.rs                                                                #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::paging::{VirtualAddress, PhysicalAddress, ENTRY_COUNT};
+use memory::paging::table::{Table, PageMap};
+use memory::paging::entry::{PageEntry, EntryFlags, WRITABLE, ACCESSIBLE, WRITETHROUGH, NO_CACHE,
+                            GLOBAL, COPY_ON_WRITE, NO_EXEC};
+use memory::PAGE_SIZE;
+use core::fmt;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+const RECURSIVE_INDEX: usize = ENTRY_COUNT - 1;         // Maps the tables themselves, not memory
+const LEVEL_COUNT: usize = 4;
+
+const PAGE_2M: usize = PAGE_SIZE * ENTRY_COUNT;
+const PAGE_1G: usize = PAGE_2M * ENTRY_COUNT;
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Clone, Copy)]
+//==================================================================================================
+pub struct Mapping {
+//--------------------------------------------------------------------------------------------------
+// Run of virtual memory mapped contiguously onto physical memory with the same permissions.
+//==================================================================================================
+
+    pub start: VirtualAddress,          // First address of the run
+    pub size: usize,                    // Length of the run in bytes
+    pub frame: PhysicalAddress,         // Physical address start is mapped to
+    pub flags: EntryFlags,              // Effective flags, combined across every table level
+}
+
+
+//==================================================================================================
+pub struct MappingIter<'a> {
+//--------------------------------------------------------------------------------------------------
+// Iterator walking every present entry of a page table in address order, yielding runs of
+// mappings that are contiguous both virtually and physically and share their flags.
+//==================================================================================================
+
+    page_map: &'a Table<PageMap>,       // Page map being walked, through the recursive mapping
+    indices: [usize; LEVEL_COUNT],      // Next entry to visit at each level, page map first
+    pending: Option<Mapping>,           // Run still being extended
+}
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl Mapping {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn end(&self) -> VirtualAddress {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the address one past the end of the run.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: exclusive end address
+    //==============================================================================================
+
+        self.start + self.size
+    }
+
+
+    //==============================================================================================
+    fn extends(&self, next: &Mapping) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Check whether another run carries on directly from this one.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   next -> run found after this one
+    //
+    // RETURNS: true  -> the runs may be merged
+    //          false -> the runs differ in placement or flags
+    //==============================================================================================
+
+        self.end() == next.start && self.frame + self.size == next.frame
+            && self.flags == next.flags
+    }
+}
+
+
+//==================================================================================================
+impl fmt::Display for Mapping {
+//==================================================================================================
+
+
+    //==============================================================================================
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    //----------------------------------------------------------------------------------------------
+    // Describe the run as `start-end -> frame RWX EXTRAS`.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   f -> formatter to write the description to
+    //
+    // RETURNS: result of writing to the formatter
+    //==============================================================================================
+
+        write!(f, "{:#x}-{:#x} -> {:#x} R{}{}", self.start, self.end(), self.frame,
+               if (self.flags.contains(WRITABLE)) { "W" } else { "-" },
+               if (self.flags.contains(NO_EXEC)) { "-" } else { "X" })?;
+
+        let extras = [(ACCESSIBLE, "USER"), (GLOBAL, "GLOBAL"), (WRITETHROUGH, "WT"),
+                      (NO_CACHE, "UC"), (COPY_ON_WRITE, "COW")];
+
+        for &(flag, name) in extras.iter() {
+            if (self.flags.contains(flag)) {
+                write!(f, " {}", name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+//==================================================================================================
+impl<'a> MappingIter<'a> {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn new(page_map: &'a Table<PageMap>) -> MappingIter<'a> {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for MappingIter. The page map must be reachable through the recursive
+    // mapping, which holds for the active table and for one borrowed by ActivePageTable::with.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   page_map -> page map to walk
+    //
+    // RETURNS: iterator starting at the lowest address
+    //==============================================================================================
+
+        MappingIter { page_map: page_map, indices: [0; LEVEL_COUNT], pending: None }
+    }
+
+
+    //==============================================================================================
+    fn next_leaf(&mut self) -> Option<Mapping> {
+    //----------------------------------------------------------------------------------------------
+    // Find the next present entry that maps memory rather than another table, at any level.
+    // The recursive entry is skipped, as it maps the tables themselves.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: Some(...) -> the mapping made by the entry
+    //          None      -> every entry has been visited
+    //==============================================================================================
+
+        let page_map = self.page_map;
+
+        loop {
+            let indices = self.indices;
+            let (map_index, ptr_index) = (indices[0], indices[1]);
+            let (dir_index, tbl_index) = (indices[2], indices[3]);
+
+            if (map_index >= RECURSIVE_INDEX) {
+                return None;
+            }
+
+            let ptr_tbl = match page_map.next_table(map_index) {
+                Some(ptr_tbl) => ptr_tbl,
+                None          => { self.advance(0); continue; },
+            };
+            let map_entry = &page_map[map_index];
+
+            let ptr_entry = &ptr_tbl[ptr_index];
+            if (ptr_entry.is_unused()) { self.advance(1); continue; }
+
+            let pg_dir = match ptr_tbl.next_table(ptr_index) {
+                Some(pg_dir) => pg_dir,
+                None         => {
+                    self.advance(1);
+                    return Some(leaf(indices, ptr_entry, &[map_entry], PAGE_1G));
+                },
+            };
+
+            let dir_entry = &pg_dir[dir_index];
+            if (dir_entry.is_unused()) { self.advance(2); continue; }
+
+            let pg_tbl = match pg_dir.next_table(dir_index) {
+                Some(pg_tbl) => pg_tbl,
+                None         => {
+                    self.advance(2);
+                    return Some(leaf(indices, dir_entry, &[map_entry, ptr_entry], PAGE_2M));
+                },
+            };
+
+            let tbl_entry = &pg_tbl[tbl_index];
+            self.advance(3);
+            if (tbl_entry.is_unused()) { continue; }
+
+            return Some(leaf(indices, tbl_entry, &[map_entry, ptr_entry, dir_entry], PAGE_SIZE));
+        }
+    }
+
+
+    //==============================================================================================
+    fn advance(&mut self, level: usize) {
+    //----------------------------------------------------------------------------------------------
+    // Step past the current entry at a level, restarting every deeper level at its first entry
+    // and carrying into shallower levels when a table is exhausted.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   level -> level to step, 0 being the page map
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        for index in level + 1 .. LEVEL_COUNT {
+            self.indices[index] = 0;
+        }
+
+        let mut level = level;
+        self.indices[level] += 1;
+
+        while (level > 0 && self.indices[level] == ENTRY_COUNT) {
+            self.indices[level] = 0;
+            level -= 1;
+            self.indices[level] += 1;
+        }
+    }
+}
+
+
+//==================================================================================================
+impl<'a> Iterator for MappingIter<'a> {
+//==================================================================================================
+
+    type Item = Mapping;
+
+
+    //==============================================================================================
+    fn next(&mut self) -> Option<Mapping> {
+    //----------------------------------------------------------------------------------------------
+    // Yield the next run, merging entries for as long as they carry on from one another.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: Some(...) -> the next run of mappings
+    //          None      -> the table has been walked completely
+    //==============================================================================================
+
+        loop {
+            let leaf = match self.next_leaf() {
+                Some(leaf) => leaf,
+                None       => return self.pending.take(),
+            };
+
+            match self.pending.take() {
+                Some(mut pending) => {
+                    if (pending.extends(&leaf)) {
+                        pending.size += leaf.size;
+                        self.pending = Some(pending);
+                    }
+                    else {
+                        self.pending = Some(leaf);
+                        return Some(pending);
+                    }
+                },
+                None => self.pending = Some(leaf),
+            }
+        }
+    }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn leaf(indices: [usize; LEVEL_COUNT], entry: &PageEntry, parents: &[&PageEntry], size: usize)
+        -> Mapping {
+//--------------------------------------------------------------------------------------------------
+// Describe the mapping made by an entry that maps memory rather than another table.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   indices -> table indices leading to the entry, page map first
+//          entry   -> present entry mapping memory
+//          parents -> entries on the path to it, page map entry first
+//          size    -> bytes mapped by the entry
+//
+// RETURNS: the mapping, with write and user access limited, and execution forbidden, as the
+//          parents dictate
+//==================================================================================================
+
+    let mut address = 0;
+    for level in 0 .. parents.len() + 1 {
+        address |= indices[level] << (39 - 9 * level);
+    }
+
+    // ACCESSED and DIRTY are left out, as they differ between otherwise identical pages
+    let mut flags = entry.flags() & (WRITABLE | ACCESSIBLE | WRITETHROUGH | NO_CACHE | GLOBAL |
+                                     COPY_ON_WRITE | NO_EXEC);
+    for parent in parents.iter() {
+        flags.remove(!parent.flags() & (WRITABLE | ACCESSIBLE));
+        flags.insert(parent.flags() & NO_EXEC);
+    }
+
+    // Bit 7 is PAT rather than HUGE_PAGE in 4 KiB entries, so they need their own decoding
+    let frame = if (size == PAGE_SIZE) { entry.page_frame() } else { entry.target_frame() };
+
+    Mapping {
+        start: VirtualAddress::new_truncate(address),
+        size: size,
+        frame: frame.unwrap().address(),
+        flags: flags,
+    }
+}
@@ -0,0 +1,653 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/memory: slab_allocator.rs                                                               #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::{FrameAllocator, PAGE_SIZE};
+use memory::paging::{Page, ActivePageTable, VirtualAddress};
+use memory::paging::entry::{WRITABLE, NO_EXEC};
+use memory::frame_table::{self, FrameUsage};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const SLAB_START: usize = 0o177777_402_000_000_000_0000;  // Page map entry 258, higher half
+pub const CACHE_SIZES: [usize; CACHE_COUNT] = [16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
+pub const CACHE_COUNT: usize = 9;
+const SLOT_SIZE: usize = 16 * PAGE_SIZE;        // Virtual space reserved for every slab
+const SLOT_COUNT: usize = 4096;                 // Number of slabs that may exist at once
+const MIN_OBJECTS_PER_SLAB: usize = 8;          // Large objects get multi-page slabs to reach this
+const MAX_OBJECTS_PER_SLAB: usize = 256;        // Single page slab of the smallest objects
+const BITS_PER_WORD: usize = 64;
+const ALLOCATED_WORDS: usize = MAX_OBJECTS_PER_SLAB / BITS_PER_WORD;
+const NO_SLOT: usize = !0;
+const NO_CACHE: usize = !0;
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+// Slab descriptors are kept apart from the slabs themselves so that objects pack perfectly and keep
+// their natural alignment. Lives in static memory so that it never needs frames of its own.
+static mut SLAB_DESCRIPTORS: [SlabDescriptor; SLOT_COUNT] = [EMPTY_DESCRIPTOR; SLOT_COUNT];
+static DESCRIPTORS_CLAIMED: AtomicBool = AtomicBool::new(false);
+
+const EMPTY_DESCRIPTOR: SlabDescriptor = SlabDescriptor {
+    cache: NO_CACHE,
+    free_object: 0,
+    in_use: 0,
+    allocated: [0; ALLOCATED_WORDS],
+    prev: NO_SLOT,
+    next: NO_SLOT,
+};
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Clone, Copy)]
+//==================================================================================================
+struct SlabDescriptor {
+//--------------------------------------------------------------------------------------------------
+// Bookkeeping for the slab occupying one slot of the slab window.
+//==================================================================================================
+
+    cache: usize,                       // Index of the owning cache, NO_CACHE if slot is unused
+    free_object: usize,                 // Address of the first free object, 0 if slab is full
+    in_use: usize,                      // Number of objects currently handed out
+    allocated: [u64; ALLOCATED_WORDS],  // One bit per object, set while it is handed out
+    prev: usize,                        // Previous slot in the same list, NO_SLOT if first
+    next: usize,                        // Next slot in the same list, NO_SLOT if last
+}
+
+
+#[derive(Clone, Copy)]
+//==================================================================================================
+struct SlabList {
+//--------------------------------------------------------------------------------------------------
+// Doubly linked list of slots, threaded through the slab descriptors.
+//==================================================================================================
+
+    head: usize,
+    length: usize,
+}
+
+
+#[derive(Clone, Copy)]
+//==================================================================================================
+struct SlabCache {
+//--------------------------------------------------------------------------------------------------
+// Cache of slabs holding objects of a single size.
+//==================================================================================================
+
+    partial: SlabList,                  // Slabs with both used and free objects
+    full: SlabList,                     // Slabs with no free objects
+    empty: SlabList,                    // Slabs with no used objects, kept for reuse
+    allocations: usize,                 // Total objects ever allocated from the cache
+    frees: usize,                       // Total objects ever returned to the cache
+}
+
+
+#[derive(Debug, Clone, Copy)]
+//==================================================================================================
+pub struct CacheStatistics {
+//--------------------------------------------------------------------------------------------------
+// Snapshot of a cache's state, as reported by SlabAllocator::statistics.
+//==================================================================================================
+
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    pub partial_slabs: usize,
+    pub full_slabs: usize,
+    pub empty_slabs: usize,
+    pub objects_in_use: usize,
+    pub allocations: usize,
+    pub frees: usize,
+}
+
+
+//==================================================================================================
+pub struct SlabAllocator {
+//--------------------------------------------------------------------------------------------------
+// Object allocator keeping one cache per power of two size from 16 bytes to 4 KiB. Every slab lives
+// in its own fixed slot of a virtual window, so an object's slab is found from its address alone.
+//==================================================================================================
+
+    caches: [SlabCache; CACHE_COUNT],
+    descriptors: &'static mut [SlabDescriptor; SLOT_COUNT],
+    free_slots: SlabList,               // Slots that held a slab which has since been destroyed
+    next_unused_slot: usize,            // First slot that has never held a slab
+}
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl SlabList {
+//==================================================================================================
+
+
+    //==============================================================================================
+    const fn new() -> SlabList {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for an empty SlabList.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: an empty list
+    //==============================================================================================
+
+        SlabList { head: NO_SLOT, length: 0 }
+    }
+
+
+    //==============================================================================================
+    fn push(&mut self, descriptors: &mut [SlabDescriptor; SLOT_COUNT], slot: usize) {
+    //----------------------------------------------------------------------------------------------
+    // Insert a slot at the front of the list.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   descriptors -> descriptor table the list is threaded through
+    //          slot        -> slot to insert, must not be in any list
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        descriptors[slot].prev = NO_SLOT;
+        descriptors[slot].next = self.head;
+
+        if (self.head != NO_SLOT) {
+            descriptors[self.head].prev = slot;
+        }
+
+        self.head = slot;
+        self.length += 1;
+    }
+
+
+    //==============================================================================================
+    fn remove(&mut self, descriptors: &mut [SlabDescriptor; SLOT_COUNT], slot: usize) {
+    //----------------------------------------------------------------------------------------------
+    // Unlink a slot from the list.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   descriptors -> descriptor table the list is threaded through
+    //          slot        -> slot to remove, must be in this list
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        let prev = descriptors[slot].prev;
+        let next = descriptors[slot].next;
+
+        if (prev == NO_SLOT) {
+            self.head = next;
+        }
+        else {
+            descriptors[prev].next = next;
+        }
+
+        if (next != NO_SLOT) {
+            descriptors[next].prev = prev;
+        }
+
+        descriptors[slot].prev = NO_SLOT;
+        descriptors[slot].next = NO_SLOT;
+        self.length -= 1;
+    }
+}
+
+
+//==================================================================================================
+impl SlabAllocator {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn new() -> SlabAllocator {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for SlabAllocator. May only be called once, as all instances share the
+    // same descriptor table and virtual window.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: a slab allocator with every cache empty
+    //==============================================================================================
+
+        assert!(!DESCRIPTORS_CLAIMED.swap(true, Ordering::SeqCst),
+                "SlabAllocator may only be constructed once");
+
+        let empty_cache = SlabCache {
+            partial: SlabList::new(),
+            full: SlabList::new(),
+            empty: SlabList::new(),
+            allocations: 0,
+            frees: 0,
+        };
+
+        SlabAllocator {
+            caches: [empty_cache; CACHE_COUNT],
+            descriptors: unsafe { &mut SLAB_DESCRIPTORS },
+            free_slots: SlabList::new(),
+            next_unused_slot: 0,
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn allocate<A: FrameAllocator>(&mut self, size: usize, active_table: &mut ActivePageTable,
+                                       allocator: &mut A) -> Option<*mut u8> {
+    //----------------------------------------------------------------------------------------------
+    // Allocate an object from the smallest cache able to hold size bytes. Objects are aligned to
+    // the size of their cache.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   size         -> number of bytes needed
+    //          active_table -> page table to map new slabs in
+    //          allocator    -> allocator to obtain frames for new slabs from
+    //
+    // RETURNS: Some(...) -> pointer to the object
+    //          None      -> size exceeds the largest cache, or no slab could be created
+    //==============================================================================================
+
+        let cache = match cache_for_size(size) {
+            Some(cache) => cache,
+            None        => return None,
+        };
+
+        // Prefer partially used slabs, then cached empty ones, and only then build a new slab
+        let slot = if (self.caches[cache].partial.head != NO_SLOT) {
+            self.caches[cache].partial.head
+        }
+        else if (self.caches[cache].empty.head != NO_SLOT) {
+            let slot = self.caches[cache].empty.head;
+            self.caches[cache].empty.remove(self.descriptors, slot);
+            self.caches[cache].partial.push(self.descriptors, slot);
+            slot
+        }
+        else {
+            match self.create_slab(cache, active_table, allocator) {
+                Some(slot) => {
+                    self.caches[cache].partial.push(self.descriptors, slot);
+                    slot
+                },
+                None => return None,
+            }
+        };
+
+        let object = self.descriptors[slot].free_object;
+        let object_num = (object - slot_address(slot)) / CACHE_SIZES[cache];
+        self.descriptors[slot].free_object = unsafe { *(object as *const usize) };
+        self.descriptors[slot].allocated[object_num / BITS_PER_WORD] |= bit_mask(object_num);
+        self.descriptors[slot].in_use += 1;
+
+        if (self.descriptors[slot].in_use == objects_per_slab(cache)) {
+            self.caches[cache].partial.remove(self.descriptors, slot);
+            self.caches[cache].full.push(self.descriptors, slot);
+        }
+
+        self.caches[cache].allocations += 1;
+        Some(object as *mut u8)
+    }
+
+
+    //==============================================================================================
+    pub fn deallocate(&mut self, object: *mut u8) {
+    //----------------------------------------------------------------------------------------------
+    // Return an object to its cache. Slabs left empty are kept for reuse until shrink is called.
+    // Panics if the object is not currently allocated.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   object -> pointer previously returned by allocate
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        let address = object as usize;
+        assert!(address >= SLAB_START && address < SLAB_START + SLOT_COUNT * SLOT_SIZE,
+                "object {:#x} does not belong to the slab allocator", address);
+
+        let slot = (address - SLAB_START) / SLOT_SIZE;
+        let cache = self.descriptors[slot].cache;
+        assert!(cache != NO_CACHE, "object {:#x} lies in an unused slab slot", address);
+
+        let offset = address - slot_address(slot);
+        let object_num = offset / CACHE_SIZES[cache];
+        assert!(offset % CACHE_SIZES[cache] == 0 && object_num < objects_per_slab(cache),
+                "object {:#x} is not the start of a {} byte object", address, CACHE_SIZES[cache]);
+
+        let word = object_num / BITS_PER_WORD;
+        assert!(self.descriptors[slot].allocated[word] & bit_mask(object_num) != 0,
+                "double free of object {:#x}", address);
+        self.descriptors[slot].allocated[word] &= !bit_mask(object_num);
+
+        let was_full = self.descriptors[slot].in_use == objects_per_slab(cache);
+
+        unsafe { *(address as *mut usize) = self.descriptors[slot].free_object; }
+        self.descriptors[slot].free_object = address;
+        self.descriptors[slot].in_use -= 1;
+
+        if (was_full) {
+            self.caches[cache].full.remove(self.descriptors, slot);
+        }
+        else {
+            self.caches[cache].partial.remove(self.descriptors, slot);
+        }
+
+        if (self.descriptors[slot].in_use == 0) {
+            self.caches[cache].empty.push(self.descriptors, slot);
+        }
+        else {
+            self.caches[cache].partial.push(self.descriptors, slot);
+        }
+
+        self.caches[cache].frees += 1;
+    }
+
+
+    //==============================================================================================
+    pub fn shrink<A: FrameAllocator>(&mut self, active_table: &mut ActivePageTable,
+                                     allocator: &mut A) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Destroy every empty slab, handing its frames back to the frame allocator.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   active_table -> page table the slabs are mapped in
+    //          allocator    -> allocator to return the slabs' frames to
+    //
+    // RETURNS: number of frames returned
+    //==============================================================================================
+
+        let mut frames_freed = 0;
+
+        for cache in 0 .. CACHE_COUNT {
+            while (self.caches[cache].empty.head != NO_SLOT) {
+                let slot = self.caches[cache].empty.head;
+                self.caches[cache].empty.remove(self.descriptors, slot);
+                frames_freed += self.destroy_slab(slot, active_table, allocator);
+            }
+        }
+
+        frames_freed
+    }
+
+
+    //==============================================================================================
+    pub fn statistics(&self, cache: usize) -> CacheStatistics {
+    //----------------------------------------------------------------------------------------------
+    // Obtain a snapshot of a cache's slabs and objects.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   cache -> index of the cache, see CACHE_SIZES
+    //
+    // RETURNS: statistics for the cache
+    //==============================================================================================
+
+        let slab_cache = &self.caches[cache];
+        let per_slab = objects_per_slab(cache);
+
+        // Objects in partial slabs have to be counted slab by slab
+        let mut partial_in_use = 0;
+        let mut slot = slab_cache.partial.head;
+        while (slot != NO_SLOT) {
+            partial_in_use += self.descriptors[slot].in_use;
+            slot = self.descriptors[slot].next;
+        }
+
+        CacheStatistics {
+            object_size: CACHE_SIZES[cache],
+            objects_per_slab: per_slab,
+            partial_slabs: slab_cache.partial.length,
+            full_slabs: slab_cache.full.length,
+            empty_slabs: slab_cache.empty.length,
+            objects_in_use: slab_cache.full.length * per_slab + partial_in_use,
+            allocations: slab_cache.allocations,
+            frees: slab_cache.frees,
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn print_statistics(&self) {
+    //----------------------------------------------------------------------------------------------
+    // Print a line of statistics for every cache.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        println!(" size  partial  full  empty   in use   allocs    frees");
+        for cache in 0 .. CACHE_COUNT {
+            let stats = self.statistics(cache);
+            println!("{:>5}  {:>7}  {:>4}  {:>5}  {:>7}  {:>7}  {:>7}",
+                     stats.object_size, stats.partial_slabs, stats.full_slabs, stats.empty_slabs,
+                     stats.objects_in_use, stats.allocations, stats.frees);
+        }
+    }
+
+
+    //==============================================================================================
+    fn create_slab<A: FrameAllocator>(&mut self, cache: usize, active_table: &mut ActivePageTable,
+                                      allocator: &mut A) -> Option<usize> {
+    //----------------------------------------------------------------------------------------------
+    // Back a free slot with frames and thread every object of the new slab onto its free list.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   cache        -> index of the cache the slab will belong to
+    //          active_table -> page table to map the slab in
+    //          allocator    -> allocator to obtain the slab's frames from
+    //
+    // RETURNS: Some(...) -> slot holding the new slab, not yet in any list
+    //          None      -> slots or frames ran out
+    //==============================================================================================
+
+        let slot = if (self.free_slots.head != NO_SLOT) {
+            let slot = self.free_slots.head;
+            self.free_slots.remove(self.descriptors, slot);
+            slot
+        }
+        else if (self.next_unused_slot < SLOT_COUNT) {
+            self.next_unused_slot += 1;
+            self.next_unused_slot - 1
+        }
+        else {
+            return None;
+        };
+
+        let base = slot_address(slot);
+
+        for page_num in 0 .. pages_per_slab(cache) {
+            match allocator.allocate_frame() {
+                Some(frame) => {
+                    frame_table::set_usage(&frame, FrameUsage::Slab);
+                    let address = VirtualAddress::new(base + page_num * PAGE_SIZE);
+                    let page = Page::containing_address(address);
+                    active_table.map_page_to_frame(page, frame, WRITABLE | NO_EXEC, allocator);
+                },
+                None => {
+                    // Give back the pages mapped so far
+                    for mapped_num in 0 .. page_num {
+                        release_page(base + mapped_num * PAGE_SIZE, active_table, allocator);
+                    }
+                    self.free_slots.push(self.descriptors, slot);
+                    return None;
+                }
+            }
+        }
+
+        // Link every object to the one following it, ending the list with 0
+        let size = CACHE_SIZES[cache];
+        let count = objects_per_slab(cache);
+        for object_num in 0 .. count {
+            let object = base + object_num * size;
+            let next = if (object_num + 1 < count) { object + size } else { 0 };
+            unsafe { *(object as *mut usize) = next; }
+        }
+
+        self.descriptors[slot].cache = cache;
+        self.descriptors[slot].free_object = base;
+        self.descriptors[slot].in_use = 0;
+        self.descriptors[slot].allocated = [0; ALLOCATED_WORDS];
+        Some(slot)
+    }
+
+
+    //==============================================================================================
+    fn destroy_slab<A: FrameAllocator>(&mut self, slot: usize, active_table: &mut ActivePageTable,
+                                       allocator: &mut A) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Unmap an empty slab and hand its frames back to the frame allocator.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   slot         -> slot holding the slab, already removed from its cache's lists
+    //          active_table -> page table the slab is mapped in
+    //          allocator    -> allocator to return the slab's frames to
+    //
+    // RETURNS: number of frames returned
+    //==============================================================================================
+
+        let pages = pages_per_slab(self.descriptors[slot].cache);
+        let base = slot_address(slot);
+
+        for page_num in 0 .. pages {
+            release_page(base + page_num * PAGE_SIZE, active_table, allocator);
+        }
+
+        self.descriptors[slot] = EMPTY_DESCRIPTOR;
+        self.free_slots.push(self.descriptors, slot);
+        pages
+    }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn cache_for_size(size: usize) -> Option<usize> {
+//--------------------------------------------------------------------------------------------------
+// Find the smallest cache whose objects can hold size bytes.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   size -> number of bytes needed
+//
+// RETURNS: Some(...) -> index of the cache
+//          None      -> size exceeds the largest cache
+//==================================================================================================
+
+    CACHE_SIZES.iter().position(|&object_size| object_size >= size)
+}
+
+
+//==================================================================================================
+fn pages_per_slab(cache: usize) -> usize {
+//--------------------------------------------------------------------------------------------------
+// Obtain the number of pages backing every slab of a cache.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   cache -> index of the cache
+//
+// RETURNS: pages per slab
+//==================================================================================================
+
+    let bytes = CACHE_SIZES[cache] * MIN_OBJECTS_PER_SLAB;
+    if (bytes < PAGE_SIZE) { 1 } else { bytes / PAGE_SIZE }
+}
+
+
+//==================================================================================================
+fn objects_per_slab(cache: usize) -> usize {
+//--------------------------------------------------------------------------------------------------
+// Obtain the number of objects held by every slab of a cache.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   cache -> index of the cache
+//
+// RETURNS: objects per slab
+//==================================================================================================
+
+    pages_per_slab(cache) * PAGE_SIZE / CACHE_SIZES[cache]
+}
+
+
+//==================================================================================================
+fn bit_mask(object_num: usize) -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Obtain the mask selecting an object's bit within its slab's allocation bitmap word.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   object_num -> index of the object within its slab
+//
+// RETURNS: mask with only the object's bit set
+//==================================================================================================
+
+    1 << (object_num % BITS_PER_WORD)
+}
+
+
+//==================================================================================================
+fn slot_address(slot: usize) -> usize {
+//--------------------------------------------------------------------------------------------------
+// Obtain the first address of a slot in the slab window.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   slot -> index of the slot
+//
+// RETURNS: virtual address of the slot
+//==================================================================================================
+
+    SLAB_START + slot * SLOT_SIZE
+}
+
+
+//==================================================================================================
+fn release_page<A: FrameAllocator>(address: usize, active_table: &mut ActivePageTable,
+                                   allocator: &mut A) {
+//--------------------------------------------------------------------------------------------------
+// Unmap a slab page and return the frame behind it. Slab frames are never shared, and the frame
+// table only hands back general allocations, so the frame is freed here rather than by unmap.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address      -> virtual address of the page
+//          active_table -> page table the page is mapped in
+//          allocator    -> allocator to return the frame to
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let page = Page::containing_address(VirtualAddress::new(address));
+    let frame = active_table.unmap_shared(page, allocator);
+    allocator.deallocate_frame(frame);
+}
@@ -0,0 +1,309 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/memory: stack_allocator.rs                                                              #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::{MEMORY_CONTROLLER, FrameAllocator, PAGE_SIZE};
+use memory::paging::{PageRange, ActivePageTable, VirtualAddress};
+use memory::paging::entry::{WRITABLE, NO_EXEC};
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const STACK_START: usize = 0o177777_404_000_000_000_0000;  // Page map entry 260, higher half
+pub const MAX_STACK_PAGES: usize = SLOT_PAGES - 1;          // Leaves at least one guard page
+const SLOT_PAGES: usize = 64;                   // Virtual pages reserved for every stack
+const SLOT_COUNT: usize = 512;                  // Number of stacks that may exist at once
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Debug)]
+//==================================================================================================
+pub struct Stack {
+//--------------------------------------------------------------------------------------------------
+// Kernel stack handed out by the StackAllocator. The pages below the bottom are never mapped, so
+// an overflow faults instead of overwriting whatever lies beneath. The stack is unmapped and its
+// frames freed when dropped, which locks MEMORY_CONTROLLER; it must not be dropped while the lock
+// is held, or while running on it.
+//==================================================================================================
+
+    top: VirtualAddress,                // One past the highest usable address, where rsp starts
+    bottom: VirtualAddress,             // Lowest usable address
+}
+
+
+//==================================================================================================
+pub struct StackAllocator {
+//--------------------------------------------------------------------------------------------------
+// Allocator of kernel stacks. The region at STACK_START is divided into slots of SLOT_PAGES pages,
+// each holding one stack at its top and leaving the pages beneath it unmapped as a guard.
+//==================================================================================================
+
+    used: [bool; SLOT_COUNT],           // Whether each slot holds a stack
+}
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl Stack {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn top(&self) -> VirtualAddress {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the address the stack pointer starts at. Stacks grow down, so this is one past the
+    // highest usable address.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: top of the stack
+    //==============================================================================================
+
+        self.top
+    }
+
+
+    //==============================================================================================
+    pub fn bottom(&self) -> VirtualAddress {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the lowest usable address of the stack.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: bottom of the stack
+    //==============================================================================================
+
+        self.bottom
+    }
+
+
+    //==============================================================================================
+    pub fn size(&self) -> usize {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the usable size of the stack.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: size in bytes
+    //==============================================================================================
+
+        self.top - self.bottom
+    }
+
+
+    //==============================================================================================
+    pub unsafe fn switch_to(self, entry: fn() -> !) -> ! {
+    //----------------------------------------------------------------------------------------------
+    // Move the stack pointer onto this stack and continue in a function that never returns. The
+    // stack is never dropped. Unsafe, as nothing on the old stack may be used afterwards.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   entry -> function to run on the stack
+    //
+    // RETURNS: never
+    //==============================================================================================
+
+        let top = self.top.as_usize();
+        ::core::mem::forget(self);
+
+        asm!("mov rsp, $0
+              xor rbp, rbp
+              call $1"
+             :: "r" (top), "r" (entry)
+             :: "intel", "volatile");
+
+        unreachable!();
+    }
+}
+
+
+//==================================================================================================
+impl Drop for Stack {
+//==================================================================================================
+
+
+    //==============================================================================================
+    fn drop(&mut self) {
+    //----------------------------------------------------------------------------------------------
+    // Unmap the stack and return its frames and slot.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        let mut controller = MEMORY_CONTROLLER.lock();
+        let controller = controller.as_mut().expect("memory not initialized");
+
+        controller.stack_allocator.deallocate(self, &mut controller.active_table,
+                                              &mut controller.frame_allocator);
+    }
+}
+
+
+//==================================================================================================
+impl StackAllocator {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub const fn new() -> StackAllocator {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for a StackAllocator with every slot free.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: an empty StackAllocator
+    //==============================================================================================
+
+        StackAllocator { used: [false; SLOT_COUNT] }
+    }
+
+
+    //==============================================================================================
+    pub fn allocate<A: FrameAllocator>(&mut self, pages: usize, active_table: &mut ActivePageTable,
+                                       allocator: &mut A) -> Option<Stack> {
+    //----------------------------------------------------------------------------------------------
+    // Map a new stack into a free slot, leaving the rest of the slot below it unmapped. If frames
+    // run out partway, the pages already mapped are unmapped and the slot is freed again.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   pages        -> usable size of the stack in pages, at most MAX_STACK_PAGES
+    //          active_table -> page table to map the stack in
+    //          allocator    -> allocator to allocate frames and tables from
+    //
+    // RETURNS: Some(...) -> the new stack
+    //          None      -> size is out of range, every slot is taken, or frames ran out
+    //==============================================================================================
+
+        if (pages == 0 || pages > MAX_STACK_PAGES) {
+            return None;
+        }
+
+        let slot = match self.used.iter().position(|used| !*used) {
+            Some(slot) => slot,
+            None       => return None,
+        };
+        self.used[slot] = true;
+
+        let top = VirtualAddress::new(slot_address(slot) + SLOT_PAGES * PAGE_SIZE);
+        let bottom = top - pages * PAGE_SIZE;
+
+        for page in PageRange::containing(bottom, top) {
+            if (!active_table.try_map_page(page, WRITABLE | NO_EXEC, allocator)) {
+                active_table.unmap_range(PageRange::containing(bottom, page.starting_address()),
+                                         allocator);
+                self.used[slot] = false;
+                return None;
+            }
+        }
+
+        Some(Stack { top: top, bottom: bottom })
+    }
+
+
+    //==============================================================================================
+    fn deallocate<A: FrameAllocator>(&mut self, stack: &Stack, active_table: &mut ActivePageTable,
+                                     allocator: &mut A) {
+    //----------------------------------------------------------------------------------------------
+    // Unmap a stack and mark its slot free.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   stack        -> stack to free
+    //          active_table -> page table the stack is mapped in
+    //          allocator    -> allocator receiving the frames and any emptied tables
+    //
+    // RETURNS: nothing
+    //==============================================================================================
+
+        let slot = (stack.bottom.as_usize() - STACK_START) / (SLOT_PAGES * PAGE_SIZE);
+        assert!(self.used[slot], "stack freed twice");
+
+        active_table.unmap_range(PageRange::containing(stack.bottom, stack.top), allocator);
+        self.used[slot] = false;
+    }
+}
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn allocate_stack(pages: usize) -> Option<Stack> {
+//--------------------------------------------------------------------------------------------------
+// Allocate a kernel stack through MEMORY_CONTROLLER.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   pages -> usable size of the stack in pages, at most MAX_STACK_PAGES
+//
+// RETURNS: Some(...) -> the new stack
+//          None      -> size is out of range, every slot is taken, or frames ran out
+//==================================================================================================
+
+    let mut controller = MEMORY_CONTROLLER.lock();
+    let controller = controller.as_mut().expect("memory not initialized");
+
+    controller.stack_allocator.allocate(pages, &mut controller.active_table,
+                                        &mut controller.frame_allocator)
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn slot_address(slot: usize) -> usize {
+//--------------------------------------------------------------------------------------------------
+// Obtain the first address of a slot.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   slot -> index of the slot
+//
+// RETURNS: address of the slot's lowest page, which is always left unmapped
+//==================================================================================================
+
+    STACK_START + slot * SLOT_PAGES * PAGE_SIZE
+}
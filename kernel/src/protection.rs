@@ -0,0 +1,249 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel: protection.rs                                                                          #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::paging::{ActivePageTable, VirtualAddress};
+use memory::paging::entry::{WRITABLE, NO_EXEC};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::ptr;
+use cpu;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+const CR4_UMIP: u64 = 1 << 11;                  // User mode instruction prevention
+const CR4_SMEP: u64 = 1 << 20;                  // Supervisor mode execution prevention
+const CR4_SMAP: u64 = 1 << 21;                  // Supervisor mode access prevention
+
+const USER_END: usize = 0x0000_8000_0000_0000;  // One past the last lower half address
+const RECURSIVE_INDEX: usize = 511;             // Page map entry mapping the tables themselves
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+//==================================================================================================
+pub enum UserCopyError {
+//--------------------------------------------------------------------------------------------------
+// Reason a copy to or from user memory was refused.
+//==================================================================================================
+
+    OutsideUserHalf,                    // Range wraps or reaches into the kernel half
+}
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn init() {
+//--------------------------------------------------------------------------------------------------
+// Enable every CR4 protection the processor offers: SMEP keeps the kernel from executing user
+// pages, SMAP from touching them outside the copy helpers, and UMIP keeps user code from reading
+// descriptor table registers.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let mut cr4 = cpu::cr4();
+
+    if (cpu::supports_smep()) { cr4 |= CR4_SMEP; }
+    if (cpu::supports_smap()) { cr4 |= CR4_SMAP; }
+    if (cpu::supports_umip()) { cr4 |= CR4_UMIP; }
+
+    unsafe { cpu::cr4_write(cr4); }
+
+    SMAP_ENABLED.store(cr4 & CR4_SMAP != 0, Ordering::SeqCst);
+}
+
+
+//==================================================================================================
+pub fn copy_from_user(destination: &mut [u8], source: VirtualAddress)
+                      -> Result<(), UserCopyError> {
+//--------------------------------------------------------------------------------------------------
+// Copy bytes out of user memory. Pages of the range must be mapped or demand-paged; there is no
+// recovery from a fault part way through.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   destination -> kernel buffer to fill, its length giving the number of bytes
+//          source      -> user address to copy from
+//
+// RETURNS: Ok(())  -> the buffer was filled
+//          Err(..) -> reason the copy was refused
+//==================================================================================================
+
+    check_user_range(source, destination.len())?;
+
+    unsafe {
+        open_user_access();
+        ptr::copy_nonoverlapping(source.as_ptr::<u8>(), destination.as_mut_ptr(),
+                                 destination.len());
+        close_user_access();
+    }
+
+    Ok(())
+}
+
+
+//==================================================================================================
+pub fn copy_to_user(destination: VirtualAddress, source: &[u8]) -> Result<(), UserCopyError> {
+//--------------------------------------------------------------------------------------------------
+// Copy bytes into user memory. Pages of the range must be mapped writable or demand-paged; there
+// is no recovery from a fault part way through.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   destination -> user address to copy to
+//          source      -> kernel buffer to copy, its length giving the number of bytes
+//
+// RETURNS: Ok(())  -> the buffer was copied
+//          Err(..) -> reason the copy was refused
+//==================================================================================================
+
+    check_user_range(destination, source.len())?;
+
+    unsafe {
+        open_user_access();
+        ptr::copy_nonoverlapping(source.as_ptr(), destination.as_mut_ptr::<u8>(), source.len());
+        close_user_access();
+    }
+
+    Ok(())
+}
+
+
+//==================================================================================================
+pub fn audit_wx(active_table: &ActivePageTable) {
+//--------------------------------------------------------------------------------------------------
+// Walk the active tables and refuse to continue if any page is both writable and executable,
+// which would let a single stray write become code. The walk skips the recursive entry, so its
+// flags are checked on their own, as it maps every page table writable.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   active_table -> page table to audit
+//
+// RETURNS: nothing; panics on the first violation found, after listing every one
+//==================================================================================================
+
+    let mut violations = 0;
+
+    let recursive_flags = active_table.page_map()[RECURSIVE_INDEX].flags();
+    if (recursive_flags.contains(WRITABLE) && !recursive_flags.contains(NO_EXEC)) {
+        println!("W^X violation: recursive page map entry {}", RECURSIVE_INDEX);
+        violations += 1;
+    }
+
+    for mapping in active_table.mappings() {
+        if (mapping.flags.contains(WRITABLE) && !mapping.flags.contains(NO_EXEC)) {
+            println!("W^X violation: {}", mapping);
+            violations += 1;
+        }
+    }
+
+    if (violations != 0) {
+        panic!("{} mappings are both writable and executable", violations);
+    }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn check_user_range(address: VirtualAddress, length: usize) -> Result<(), UserCopyError> {
+//--------------------------------------------------------------------------------------------------
+// Check that a range lies entirely in the lower half.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address -> first address of the range
+//          length  -> length of the range in bytes
+//
+// RETURNS: Ok(())  -> the range may be accessed as user memory
+//          Err(..) -> the range reaches past the lower half
+//==================================================================================================
+
+    match address.as_usize().checked_add(length) {
+        Some(end) if (end <= USER_END) => Ok(()),
+        _                              => Err(UserCopyError::OutsideUserHalf),
+    }
+}
+
+
+//==================================================================================================
+unsafe fn open_user_access() {
+//--------------------------------------------------------------------------------------------------
+// Let the kernel touch user pages until close_user_access, by setting RFLAGS.AC.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    if (SMAP_ENABLED.load(Ordering::SeqCst)) {
+        asm!("stac" ::: "memory" : "volatile");
+    }
+}
+
+
+//==================================================================================================
+unsafe fn close_user_access() {
+//--------------------------------------------------------------------------------------------------
+// Forbid the kernel from touching user pages again, by clearing RFLAGS.AC.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    if (SMAP_ENABLED.load(Ordering::SeqCst)) {
+        asm!("clac" ::: "memory" : "volatile");
+    }
+}
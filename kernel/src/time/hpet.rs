@@ -0,0 +1,241 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/time: hpet.rs                                                                           #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use memory::{IoMapping, MemoryType, ioremap};
+use memory::paging::PhysicalAddress;
+use spin::Once;
+use super::scale;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const DEFAULT_ADDRESS: usize = 0xFED0_0000; // Where firmware usually places the HPET
+
+const CAPABILITIES: usize = 0x000;
+const CONFIGURATION: usize = 0x010;
+const MAIN_COUNTER: usize = 0x0F0;
+const REGISTER_WINDOW_SIZE: usize = 0x400;
+
+const CAP_COUNTER_64: u64 = 1 << 13;            // Main counter is 64 bits wide
+const CAP_PERIOD_SHIFT: u64 = 32;               // Counter period in femtoseconds, bits 32-63
+const CONFIG_ENABLE: u64 = 1 << 0;              // Main counter runs
+const CONFIG_LEGACY: u64 = 1 << 1;              // Timers 0 and 1 replace the PIT and RTC IRQs
+const MAX_PERIOD: u64 = 100_000_000;            // Longest valid period, 100 ns in femtoseconds
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub struct Hpet {
+//--------------------------------------------------------------------------------------------------
+// High precision event timer, whose main counter is used as a clock.
+//==================================================================================================
+
+    registers: IoMapping,               // Uncached register window
+    period: u64,                        // Femtoseconds between counter increments
+    wide: bool,                         // Main counter is 64 bits rather than 32
+}
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+static HPET: Once<Hpet> = Once::new();
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl Hpet {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn counter(&self) -> u64 {
+    //----------------------------------------------------------------------------------------------
+    // Read the main counter.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the counter, which wraps after 2^32 increments unless is_64_bit
+    //==============================================================================================
+
+        if (self.wide) {
+            self.registers.read::<u64>(MAIN_COUNTER)
+        }
+        else {
+            self.registers.read::<u32>(MAIN_COUNTER) as u64
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn elapsed(&self, start: u64) -> u64 {
+    //----------------------------------------------------------------------------------------------
+    // Count the increments since an earlier reading, allowing for one wrap of a 32-bit counter.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   start -> earlier value of counter
+    //
+    // RETURNS: increments since start
+    //==============================================================================================
+
+        let end = self.counter();
+
+        if (self.wide) {
+            end.wrapping_sub(start)
+        }
+        else {
+            (end as u32).wrapping_sub(start as u32) as u64
+        }
+    }
+
+
+    //==============================================================================================
+    pub fn period(&self) -> u64 {
+    //----------------------------------------------------------------------------------------------
+    // Obtain the time between increments of the main counter.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: the period in femtoseconds
+    //==============================================================================================
+
+        self.period
+    }
+
+
+    //==============================================================================================
+    pub fn is_64_bit(&self) -> bool {
+    //----------------------------------------------------------------------------------------------
+    // Determine whether the main counter is too wide to wrap while the kernel runs.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: true if the counter is 64 bits wide, false if it is 32
+    //==============================================================================================
+
+        self.wide
+    }
+
+
+    //==============================================================================================
+    pub fn to_nanoseconds(&self, increments: u64) -> u64 {
+    //----------------------------------------------------------------------------------------------
+    // Convert a number of counter increments to time.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   increments -> increments of the main counter
+    //
+    // RETURNS: the time they take, in nanoseconds
+    //==============================================================================================
+
+        scale(increments, self.period, FEMTOS_PER_NANO)
+    }
+}
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn init(address: PhysicalAddress) -> Option<&'static Hpet> {
+//--------------------------------------------------------------------------------------------------
+// Map the HPET's registers and start its main counter from zero, with legacy replacement off so
+// the PIT keeps IRQ 0. Nothing answers at an address without an HPET, so a period read as zero or
+// beyond the 100 ns the specification allows means there is none.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   address -> physical address of the register window
+//
+// RETURNS: Some(...) -> the HPET
+//          None      -> there is no HPET at the address
+//==================================================================================================
+
+    if let Some(hpet) = HPET.try() {
+        return Some(hpet);
+    }
+
+    let registers = match ioremap(address, REGISTER_WINDOW_SIZE, MemoryType::Uncacheable) {
+        Some(registers) => registers,
+        None            => return None,
+    };
+
+    let capabilities = registers.read::<u64>(CAPABILITIES);
+    let period = capabilities >> CAP_PERIOD_SHIFT;
+
+    if (period == 0 || period > MAX_PERIOD) {
+        return None;
+    }
+
+    let configuration = registers.read::<u64>(CONFIGURATION) & !(CONFIG_ENABLE | CONFIG_LEGACY);
+    registers.write::<u64>(CONFIGURATION, configuration);
+    registers.write::<u64>(MAIN_COUNTER, 0);
+    registers.write::<u64>(CONFIGURATION, configuration | CONFIG_ENABLE);
+
+    Some(HPET.call_once(|| Hpet {
+        registers: registers,
+        period: period,
+        wide: capabilities & CAP_COUNTER_64 != 0,
+    }))
+}
+
+
+//==================================================================================================
+pub fn hpet() -> Option<&'static Hpet> {
+//--------------------------------------------------------------------------------------------------
+// Obtain the HPET brought up by init.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: Some(...) -> the HPET
+//          None      -> init has not found one
+//==================================================================================================
+
+    HPET.try()
+}
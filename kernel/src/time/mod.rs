@@ -0,0 +1,677 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/time: mod.rs                                                                            #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//************************************* CRATES & SUBMODULES ****************************************
+//##################################################################################################
+
+
+pub mod hpet;
+pub mod pit;
+pub mod rtc;
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use interrupts::{self, apic, without_interrupts};
+use memory::paging::PhysicalAddress;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::{Mutex, Once};
+pub use self::rtc::DateTime;
+use cpu;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const NANOS_PER_SECOND: u64 = 1_000_000_000;
+pub const NANOS_PER_MILLI: u64 = 1_000_000;
+
+const TICK_HZ: u64 = 1000;                      // Rate of the clock event, which runs timers
+const TICK_NANOS: u64 = NANOS_PER_SECOND / TICK_HZ;
+const CALIBRATION_NANOS: u64 = 10 * NANOS_PER_MILLI;
+const CALIBRATION_RUNS: usize = 3;              // The shortest of these measurements is kept
+const MAX_TIMERS: usize = 64;
+const PIT_IRQ: u8 = 0;
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+//==================================================================================================
+pub enum ClockSource {
+//--------------------------------------------------------------------------------------------------
+// Counter now reads time from.
+//==================================================================================================
+
+    Tsc,                                // Invariant time stamp counter
+    Hpet,                               // 64-bit HPET main counter
+    Tick,                               // Count of clock events
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+//==================================================================================================
+pub enum ClockEvent {
+//--------------------------------------------------------------------------------------------------
+// Periodic interrupt timers are run from.
+//==================================================================================================
+
+    ApicTimer,                          // Local APIC timer
+    Pit,                                // PIT channel 0, on IRQ 0
+}
+
+
+//==================================================================================================
+struct Clock {
+//--------------------------------------------------------------------------------------------------
+// Clocks chosen and calibrated by init.
+//==================================================================================================
+
+    source: ClockSource,
+    event: ClockEvent,
+    base: u64,                          // Reading of the source at init, time zero
+    tsc_khz: u64,                       // TSC rate, 0 unless the TSC is invariant
+    tick_nanos: u64,                    // Nanoseconds between clock events
+    boot_time: u64,                     // Unix time read from the RTC at time zero
+}
+
+
+#[derive(Clone, Copy)]
+//==================================================================================================
+struct Timer {
+//--------------------------------------------------------------------------------------------------
+// Callback waiting for a time to pass.
+//==================================================================================================
+
+    deadline: u64,                      // Time since init at which the callback next runs
+    period: Option<u64>,                // Nanoseconds between runs, None if it only runs once
+    callback: TimerCallback,
+    serial: u64,                        // Tells this timer from others that held its slot
+}
+
+
+//==================================================================================================
+struct TimerTable {
+//--------------------------------------------------------------------------------------------------
+// Every pending timer.
+//==================================================================================================
+
+    timers: [Option<Timer>; MAX_TIMERS],
+    next_serial: u64,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+//==================================================================================================
+pub struct TimerId {
+//--------------------------------------------------------------------------------------------------
+// Handle to a timer, by which it may be cancelled.
+//==================================================================================================
+
+    slot: usize,
+    serial: u64,
+}
+
+
+//##################################################################################################
+//********************************** TYPE & STRUCT DEFINITIONS *************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub type TimerCallback = fn();
+//--------------------------------------------------------------------------------------------------
+// Function run by a timer. Runs in the clock event's interrupt handler, with interrupts disabled,
+// so it must be brief and must not sleep.
+//==================================================================================================
+
+
+//##################################################################################################
+//***************************************** STATIC DATA ********************************************
+//##################################################################################################
+
+
+static CLOCK: Once<Clock> = Once::new();
+
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+// Only locked with interrupts disabled, so that a clock event never finds it held on the same CPU
+static TIMERS: Mutex<TimerTable> = Mutex::new(TimerTable {
+    timers: [None; MAX_TIMERS],
+    next_serial: 0,
+});
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn init(hpet_address: PhysicalAddress, has_rtc: bool, century_register: Option<u8>) {
+//--------------------------------------------------------------------------------------------------
+// Choose and calibrate the clocks. The HPET, if present, is the reference, and the PIT otherwise.
+// Time is read from the invariant TSC where there is one, then from a 64-bit HPET, and failing
+// both from the count of clock events. Clock events come from the local APIC timer when the APICs
+// are in use, and from the PIT otherwise; they are delivered once interrupts are enabled. The
+// wall clock is the RTC's reading as time starts, advanced by the monotonic clock from then on,
+// or the Unix epoch if there is no RTC or it cannot be read.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   hpet_address     -> physical address of the HPET's registers
+//          has_rtc          -> whether a CMOS RTC is present, as the FADT says
+//          century_register -> CMOS register holding the century, if the FADT names one
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    CLOCK.call_once(|| {
+        let hpet_device = hpet::init(hpet_address);
+
+        let tsc_khz = if (cpu::supports_invariant_tsc()) {
+            calibrate(cpu::rdtsc) * NANOS_PER_MILLI / CALIBRATION_NANOS
+        }
+        else {
+            0
+        };
+
+        let boot_time = if (has_rtc) {
+            rtc::read(century_register).map_or(0, |date_time| date_time.to_unix())
+        }
+        else {
+            0
+        };
+
+        let (source, base) = if (tsc_khz != 0) {
+            (ClockSource::Tsc, cpu::rdtsc())
+        }
+        else if (hpet_device.map_or(false, |hpet| hpet.is_64_bit())) {
+            (ClockSource::Hpet, hpet_device.unwrap().counter())
+        }
+        else {
+            (ClockSource::Tick, 0)
+        };
+
+        let (event, tick_nanos) = match start_apic_timer() {
+            Some(tick_nanos) => (ClockEvent::ApicTimer, tick_nanos),
+            None             => {
+                let tick_nanos = pit::start_periodic(TICK_HZ);
+                interrupts::register_irq_handler(PIT_IRQ, tick_handler);
+                (ClockEvent::Pit, tick_nanos)
+            },
+        };
+
+        Clock {
+            source: source,
+            event: event,
+            base: base,
+            tsc_khz: tsc_khz,
+            tick_nanos: tick_nanos,
+            boot_time: boot_time,
+        }
+    });
+}
+
+
+//==================================================================================================
+pub fn now() -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Read the monotonic clock.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nanoseconds since init, or 0 before it
+//==================================================================================================
+
+    let clock = match CLOCK.try() {
+        Some(clock) => clock,
+        None        => return 0,
+    };
+
+    match clock.source {
+        ClockSource::Tsc  => scale(cpu::rdtsc() - clock.base, NANOS_PER_MILLI, clock.tsc_khz),
+        ClockSource::Hpet => {
+            let hpet = hpet::hpet().unwrap();
+            hpet.to_nanoseconds(hpet.counter() - clock.base)
+        },
+        ClockSource::Tick => TICKS.load(Ordering::SeqCst) as u64 * clock.tick_nanos,
+    }
+}
+
+
+//==================================================================================================
+pub fn unix_time() -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Read the wall clock.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: seconds since the Unix epoch
+//==================================================================================================
+
+    unix_time_nanos() / NANOS_PER_SECOND
+}
+
+
+//==================================================================================================
+pub fn unix_time_nanos() -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Read the wall clock to the resolution of the monotonic clock. It only ever moves forward, as it
+// is not corrected by later reads of the RTC.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nanoseconds since the Unix epoch
+//==================================================================================================
+
+    let clock = CLOCK.try().expect("time not initialized");
+    clock.boot_time * NANOS_PER_SECOND + now()
+}
+
+
+//==================================================================================================
+pub fn date_time() -> DateTime {
+//--------------------------------------------------------------------------------------------------
+// Read the wall clock as a calendar date and time.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: the date and time in UTC
+//==================================================================================================
+
+    DateTime::from_unix(unix_time())
+}
+
+
+//==================================================================================================
+pub fn sleep(nanoseconds: u64) {
+//--------------------------------------------------------------------------------------------------
+// Wait for a time to pass, halting between clock events while more than one is still to come and
+// spinning for the remainder. With interrupts disabled there are no clock events, so the wait is
+// spent spinning.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nanoseconds -> time to wait
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let clock = CLOCK.try().expect("time not initialized");
+
+    if (clock.source == ClockSource::Tick && !interrupts::are_enabled()) {
+        // The tick count cannot advance, so measure the wait on the PIT instead
+        pit::wait(nanoseconds);
+        return;
+    }
+
+    let deadline = now() + nanoseconds;
+
+    loop {
+        let current = now();
+        if (current >= deadline) {
+            break;
+        }
+
+        if (deadline - current > clock.tick_nanos && interrupts::are_enabled()) {
+            unsafe { asm!("hlt" :::: "volatile"); }
+        }
+        else {
+            unsafe { asm!("pause" :::: "volatile"); }
+        }
+    }
+}
+
+
+//==================================================================================================
+pub fn one_shot(delay: u64, callback: TimerCallback) -> Option<TimerId> {
+//--------------------------------------------------------------------------------------------------
+// Run a callback once, from the first clock event after a delay has passed.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   delay    -> nanoseconds to wait
+//          callback -> function to run
+//
+// RETURNS: Some(...) -> handle by which the timer may be cancelled
+//          None      -> too many timers are pending
+//==================================================================================================
+
+    add_timer(now() + delay, None, callback)
+}
+
+
+//==================================================================================================
+pub fn periodic(interval: u64, callback: TimerCallback) -> Option<TimerId> {
+//--------------------------------------------------------------------------------------------------
+// Run a callback repeatedly until cancelled. Runs that fall due while an earlier one is still
+// waiting are skipped rather than made up, and intervals shorter than the tick are lengthened to
+// it in effect.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   interval -> nanoseconds between runs, the first after one interval
+//          callback -> function to run
+//
+// RETURNS: Some(...) -> handle by which the timer may be cancelled
+//          None      -> too many timers are pending
+//==================================================================================================
+
+    assert!(interval > 0, "periodic timer needs a non-zero interval");
+    add_timer(now() + interval, Some(interval), callback)
+}
+
+
+//==================================================================================================
+pub fn cancel(id: TimerId) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Stop a timer before it next runs.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   id -> handle returned by one_shot or periodic
+//
+// RETURNS: true  -> the timer was pending and will not run again
+//          false -> the timer had already run its course or been cancelled
+//==================================================================================================
+
+    without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        let pending = timers.timers[id.slot].map_or(false, |timer| timer.serial == id.serial);
+
+        if (pending) {
+            timers.timers[id.slot] = None;
+        }
+
+        pending
+    })
+}
+
+
+//==================================================================================================
+pub fn print_clocks() {
+//--------------------------------------------------------------------------------------------------
+// Print the clocks chosen by init.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let clock = CLOCK.try().expect("time not initialized");
+
+    match clock.source {
+        ClockSource::Tsc  => println!("Clock source: invariant TSC at {} kHz", clock.tsc_khz),
+        ClockSource::Hpet => println!("Clock source: HPET with a {} fs period",
+                                      hpet::hpet().unwrap().period()),
+        ClockSource::Tick => println!("Clock source: clock events"),
+    }
+
+    println!("Clock event: {:?}, every {} ns", clock.event, clock.tick_nanos);
+    println!("Wall clock: {}", date_time());
+}
+
+
+//##################################################################################################
+//******************************************* HANDLERS *********************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn tick_handler() {
+//--------------------------------------------------------------------------------------------------
+// Count a clock event and run every timer that has fallen due. Each callback runs with the table
+// unlocked, so that it may add or cancel timers.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    TICKS.fetch_add(1, Ordering::SeqCst);
+
+    loop {
+        let current = now();
+
+        let callback = {
+            let mut guard = TIMERS.lock();
+            let table = &mut *guard;
+
+            let slot = match (0 .. MAX_TIMERS).find(|slot| {
+                table.timers[*slot].map_or(false, |timer| timer.deadline <= current)
+            }) {
+                Some(slot) => slot,
+                None       => return,
+            };
+
+            let timer = table.timers[slot].unwrap();
+
+            table.timers[slot] = timer.period.map(|period| {
+                let missed = (current - timer.deadline) / period;
+                Timer { deadline: timer.deadline + (missed + 1) * period, .. timer }
+            });
+
+            timer.callback
+        };
+
+        callback();
+    }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn add_timer(deadline: u64, period: Option<u64>, callback: TimerCallback) -> Option<TimerId> {
+//--------------------------------------------------------------------------------------------------
+// Place a timer in the first free slot of the table.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   deadline -> time at which the callback first runs
+//          period   -> nanoseconds between later runs, if any
+//          callback -> function to run
+//
+// RETURNS: Some(...) -> handle to the timer
+//          None      -> every slot is taken
+//==================================================================================================
+
+    without_interrupts(|| {
+        let mut guard = TIMERS.lock();
+        let table = &mut *guard;
+
+        let slot = match (0 .. MAX_TIMERS).find(|slot| table.timers[*slot].is_none()) {
+            Some(slot) => slot,
+            None       => return None,
+        };
+
+        let serial = table.next_serial;
+        table.next_serial += 1;
+
+        table.timers[slot] = Some(Timer {
+            deadline: deadline,
+            period: period,
+            callback: callback,
+            serial: serial,
+        });
+
+        Some(TimerId { slot: slot, serial: serial })
+    })
+}
+
+
+//==================================================================================================
+fn start_apic_timer() -> Option<u64> {
+//--------------------------------------------------------------------------------------------------
+// Calibrate the local APIC timer against the reference, then make it raise a clock event every
+// tick on a vector of its own.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nothing
+//
+// RETURNS: Some(...) -> nanoseconds between clock events
+//          None      -> the APICs are not in use, or no vector was free
+//==================================================================================================
+
+    let local_apic = match apic::local_apic() {
+        Some(local_apic) => local_apic,
+        None             => return None,
+    };
+
+    let vector = match interrupts::allocate_vector(tick_handler) {
+        Some(vector) => vector,
+        None         => return None,
+    };
+
+    local_apic.write(apic::LVT_TIMER, apic::LVT_MASKED);
+    local_apic.write(apic::TIMER_DIVIDE, apic::TIMER_DIVIDE_16);
+    local_apic.write(apic::TIMER_INITIAL_COUNT, u32::max_value());
+
+    let counts = calibrate(|| {
+        (u32::max_value() - local_apic.read(apic::TIMER_CURRENT_COUNT)) as u64
+    });
+    let initial_count = scale(counts, TICK_NANOS, CALIBRATION_NANOS);
+
+    local_apic.write(apic::LVT_TIMER, vector as u32 | apic::LVT_TIMER_PERIODIC);
+    local_apic.write(apic::TIMER_INITIAL_COUNT, initial_count as u32);
+
+    Some(TICK_NANOS)
+}
+
+
+//==================================================================================================
+fn calibrate<F: Fn() -> u64>(read: F) -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Measure how far a counter advances over the calibration interval. An SMI or a slow reference
+// read only lengthens a measurement, so the shortest of several is kept.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   read -> closure reading the counter
+//
+// RETURNS: the counter's advance over CALIBRATION_NANOS
+//==================================================================================================
+
+    (0 .. CALIBRATION_RUNS).map(|_| {
+        let start = read();
+        reference_wait(CALIBRATION_NANOS);
+        read().wrapping_sub(start)
+    }).min().unwrap()
+}
+
+
+//==================================================================================================
+fn reference_wait(nanoseconds: u64) {
+//--------------------------------------------------------------------------------------------------
+// Busy-wait on the HPET, if there is one, or on the PIT.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nanoseconds -> time to wait
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    match hpet::hpet() {
+        Some(hpet) => {
+            let start = hpet.counter();
+            while (hpet.to_nanoseconds(hpet.elapsed(start)) < nanoseconds) {}
+        },
+        None => pit::wait(nanoseconds),
+    }
+}
+
+
+//==================================================================================================
+fn scale(value: u64, numerator: u64, denominator: u64) -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Multiply by a ratio without overflowing for as long as the result fits, by dividing first and
+// multiplying only the remainder in full.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   value       -> quantity to scale
+//          numerator   -> top of the ratio
+//          denominator -> bottom of the ratio
+//
+// RETURNS: value * numerator / denominator, rounded down
+//==================================================================================================
+
+    value / denominator * numerator + value % denominator * numerator / denominator
+}
+
+
+//##################################################################################################
+//******************************************** TESTS ***********************************************
+//##################################################################################################
+
+
+#[cfg(test)]
+mod tests {
+
+
+    use super::*;
+
+
+    #[test]
+    //==============================================================================================
+    fn scale_is_exact_for_whole_ratios() {
+    //----------------------------------------------------------------------------------------------
+    // A ratio that divides the value evenly loses nothing.
+    //==============================================================================================
+
+        assert_eq!(scale(10, 3, 5), 6);
+        assert_eq!(scale(CALIBRATION_NANOS, NANOS_PER_SECOND, NANOS_PER_MILLI),
+                   CALIBRATION_NANOS * 1000);
+    }
+
+
+    #[test]
+    //==============================================================================================
+    fn scale_rounds_down() {
+    //----------------------------------------------------------------------------------------------
+    // Any fraction left over is dropped, not rounded to nearest.
+    //==============================================================================================
+
+        assert_eq!(scale(10, 1, 3), 3);
+        assert_eq!(scale(11, 2, 3), 7);
+        assert_eq!(scale(0, 7, 3), 0);
+    }
+
+
+    #[test]
+    //==============================================================================================
+    fn scale_does_not_overflow_while_the_result_fits() {
+    //----------------------------------------------------------------------------------------------
+    // The product value * numerator would overflow 64 bits, but the result does not.
+    //==============================================================================================
+
+        let value = u64::max_value() / 2;
+        assert_eq!(scale(value, NANOS_PER_SECOND, NANOS_PER_SECOND), value);
+        assert_eq!(scale(u64::max_value(), 2, 4), u64::max_value() / 2);
+        assert_eq!(scale(u64::max_value(), 1, u64::max_value()), 1);
+    }
+}
@@ -0,0 +1,145 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/time: pit.rs                                                                            #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use x86::shared::io::{inb, outb};
+use super::{NANOS_PER_SECOND, scale};
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+pub const FREQUENCY: u64 = 1_193_182;           // Input clock of every channel, in Hz
+
+const CHANNEL0: u16 = 0x40;                     // Wired to IRQ 0
+const CHANNEL2: u16 = 0x42;                     // Wired to the PC speaker, gated by port 0x61
+const COMMAND: u16 = 0x43;
+const GATE_PORT: u16 = 0x61;
+
+const CHANNEL0_RATE_GENERATOR: u8 = 0x34;       // Channel 0, low then high byte, mode 2
+const CHANNEL2_ONE_SHOT: u8 = 0xB0;             // Channel 2, low then high byte, mode 0
+const GATE_CHANNEL2: u8 = 1 << 0;               // Channel 2 counts while set
+const GATE_SPEAKER: u8 = 1 << 1;                // Channel 2 output drives the speaker
+const GATE_OUT2: u8 = 1 << 5;                   // Channel 2 output, set on reaching zero
+
+const MAX_COUNT: u64 = 0x1_0000;                // Reload value written as 0
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn start_periodic(hz: u64) -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Make channel 0 raise IRQ 0 at a regular rate, as near the one requested as the divisor allows.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   hz -> rate at which to raise IRQ 0
+//
+// RETURNS: the nanoseconds actually between interrupts
+//==================================================================================================
+
+    let divisor = clamp_count(FREQUENCY / hz);
+
+    unsafe {
+        outb(COMMAND, CHANNEL0_RATE_GENERATOR);
+        outb(CHANNEL0, divisor as u8);
+        outb(CHANNEL0, (divisor >> 8) as u8);
+    }
+
+    scale(divisor, NANOS_PER_SECOND, FREQUENCY)
+}
+
+
+//==================================================================================================
+pub fn wait(nanoseconds: u64) {
+//--------------------------------------------------------------------------------------------------
+// Busy-wait by polling channel 2, which needs neither interrupts nor calibration. Each pass counts
+// down at most 65536 ticks, some 55 ms, so longer waits are made of several.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   nanoseconds -> time to wait
+//
+// RETURNS: nothing
+//==================================================================================================
+
+    let mut remaining = scale(nanoseconds, FREQUENCY, NANOS_PER_SECOND);
+
+    while (remaining > 0) {
+        let count = clamp_count(remaining);
+        remaining -= count;
+
+        unsafe {
+            let gate = inb(GATE_PORT) & !GATE_SPEAKER;
+            outb(GATE_PORT, gate | GATE_CHANNEL2);
+
+            outb(COMMAND, CHANNEL2_ONE_SHOT);
+            outb(CHANNEL2, count as u8);
+            outb(CHANNEL2, (count >> 8) as u8);
+
+            while (inb(GATE_PORT) & GATE_OUT2 == 0) {}
+        }
+    }
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn clamp_count(count: u64) -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Bring a count within what a channel can be loaded with. Writing 0 loads the largest count.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   count -> ticks wanted
+//
+// RETURNS: the count to use, 1 to 65536
+//==================================================================================================
+
+    if (count == 0) {
+        1
+    }
+    else if (count > MAX_COUNT) {
+        MAX_COUNT
+    }
+    else {
+        count
+    }
+}
@@ -0,0 +1,514 @@
+//##################################################################################################
+//#                                                                                                #
+//# Kernel/time: rtc.rs                                                                            #
+//#                                                                                                #
+//# AUTHOR: Eric S. Collins <ericscollins@protonmail.com>                                          #
+//#                                                                                                #
+//#                                                                                                #
+//# MIT LICENSE                                                                                    #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//# Copyright 2017 Eric S. Collins                                                                 #
+//#                                                                                                #
+//# Permission is hereby granted, free of charge, to any person obtaining a copy of this software  #
+//# and associated documentation files (the "Software"), to deal in the Software without           #
+//# restriction, including without limitation the rights to use, copy, modify, merge, publish,     #
+//# distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the  #
+//# Software is furnished to do so, subject to the following conditions:                           #
+//#                                                                                                #
+//# The above copyright notice and this permission notice shall be included in all copies or       #
+//# substantial portions of the Software.                                                          #
+//#                                                                                                #
+//# THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING  #
+//# BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND     #
+//# NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,   #
+//# DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, #
+//# OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.        #
+//#                                                                                                #
+//# ---------------------------------------------------------------------------------------------- #
+//#                                                                                                #
+//##################################################################################################
+
+
+//##################################################################################################
+//***************************************** DEPENDENCIES *******************************************
+//##################################################################################################
+
+
+use x86::shared::io::{inb, outb};
+use interrupts::without_interrupts;
+use super::reference_wait;
+use core::fmt;
+
+
+//##################################################################################################
+//****************************************** CONSTANTS *********************************************
+//##################################################################################################
+
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+const NMI_DISABLE: u8 = 1 << 7;                 // Set in the index to hold off NMIs meanwhile
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0A;
+const STATUS_B: u8 = 0x0B;
+
+const A_UPDATE_IN_PROGRESS: u8 = 1 << 7;        // Registers are about to change
+const B_24_HOUR: u8 = 1 << 1;                   // Hours run 0-23 rather than 1-12
+const B_BINARY: u8 = 1 << 2;                    // Values are binary rather than BCD
+const HOUR_PM: u8 = 1 << 7;                     // Afternoon, in 12-hour mode
+
+const UPDATE_POLL_NANOS: u64 = 100_000;         // Wait between polls of an update in progress
+const UPDATE_POLLS: u32 = 30;                   // An update takes under 2 ms, so 3 ms gives up
+
+const EPOCH_YEAR: u16 = 1970;
+const PIVOT_YEAR: u8 = 70;                      // Without a century, years below are 20xx
+const SECONDS_PER_DAY: u64 = 86_400;
+const DAYS_BEFORE_MONTH: [u16; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+
+
+//##################################################################################################
+//************************************* STRUCT DECLARATIONS ****************************************
+//##################################################################################################
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+//==================================================================================================
+pub struct DateTime {
+//--------------------------------------------------------------------------------------------------
+// Calendar date and time of day, in UTC.
+//==================================================================================================
+
+    pub year: u16,
+    pub month: u8,                      // 1-12
+    pub day: u8,                        // 1-31
+    pub hour: u8,                       // 0-23
+    pub minute: u8,
+    pub second: u8,
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+//==================================================================================================
+struct RawTime {
+//--------------------------------------------------------------------------------------------------
+// Registers of the RTC as read, before any conversion.
+//==================================================================================================
+
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+
+//##################################################################################################
+//************************************ STRUCT IMPLEMENTATIONS **************************************
+//##################################################################################################
+
+
+//==================================================================================================
+impl DateTime {
+//==================================================================================================
+
+
+    //==============================================================================================
+    pub fn to_unix(&self) -> u64 {
+    //----------------------------------------------------------------------------------------------
+    // Convert to seconds since the Unix epoch. Dates before 1970, or with no such month or day,
+    // count as the epoch itself.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   nothing
+    //
+    // RETURNS: seconds since 1970-01-01 00:00:00 UTC
+    //==============================================================================================
+
+        if (self.year < EPOCH_YEAR || self.month == 0 || self.month > 12 || self.day == 0) {
+            return 0;
+        }
+
+        let mut days = (EPOCH_YEAR .. self.year).map(|year| days_in_year(year)).sum::<u64>();
+        days += DAYS_BEFORE_MONTH[(self.month - 1) as usize] as u64 + (self.day - 1) as u64;
+
+        if (self.month > 2 && is_leap_year(self.year)) {
+            days += 1;
+        }
+
+        days * SECONDS_PER_DAY + self.hour as u64 * 3600 + self.minute as u64 * 60
+            + self.second as u64
+    }
+
+
+    //==============================================================================================
+    pub fn from_unix(seconds: u64) -> DateTime {
+    //----------------------------------------------------------------------------------------------
+    // Pseudo-constructor for DateTime, from seconds since the Unix epoch.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   seconds -> seconds since 1970-01-01 00:00:00 UTC
+    //
+    // RETURNS: the date and time
+    //==============================================================================================
+
+        let mut days = seconds / SECONDS_PER_DAY;
+        let time = seconds % SECONDS_PER_DAY;
+
+        let mut year = EPOCH_YEAR;
+        while (days >= days_in_year(year)) {
+            days -= days_in_year(year);
+            year += 1;
+        }
+
+        let leap = if (is_leap_year(year)) { 1 } else { 0 };
+        let month = (1 .. 13).rev().find(|month| {
+            let leap_day = if (*month > 2) { leap } else { 0 };
+            days >= DAYS_BEFORE_MONTH[*month - 1] as u64 + leap_day
+        }).unwrap();
+        let leap_day = if (month > 2) { leap } else { 0 };
+
+        DateTime {
+            year: year,
+            month: month as u8,
+            day: (days - DAYS_BEFORE_MONTH[month - 1] as u64 - leap_day) as u8 + 1,
+            hour: (time / 3600) as u8,
+            minute: (time % 3600 / 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+}
+
+
+//==================================================================================================
+impl fmt::Display for DateTime {
+//==================================================================================================
+
+
+    //==============================================================================================
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    //----------------------------------------------------------------------------------------------
+    // Format as an ISO 8601 date and time.
+    //----------------------------------------------------------------------------------------------
+    // TAKES:   f -> formatter to write to
+    //
+    // RETURNS: whether the write succeeded
+    //==============================================================================================
+
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", self.year, self.month, self.day,
+               self.hour, self.minute, self.second)
+    }
+}
+
+
+//##################################################################################################
+//*************************************** PUBLIC FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+pub fn read(century_register: Option<u8>) -> Option<DateTime> {
+//--------------------------------------------------------------------------------------------------
+// Read the date and time from the CMOS RTC. Each read waits out any update in progress, and reads
+// are repeated until two agree, so an update landing midway cannot mix two seconds. The registers
+// are converted from BCD and 12-hour mode wherever status register B says they are in them.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   century_register -> CMOS register holding the century, as named by the ACPI FADT. If
+//                              None, years 70-99 are taken as 19xx and the rest as 20xx.
+//
+// RETURNS: Some(...) -> the date and time in UTC, which the RTC is assumed to keep
+//          None      -> an update never finished, as when no RTC answers and the port reads 0xFF
+//==================================================================================================
+
+    without_interrupts(|| -> Option<DateTime> {
+        let mut raw = read_raw(century_register)?;
+
+        loop {
+            let again = read_raw(century_register)?;
+            if (again == raw) {
+                break;
+            }
+            raw = again;
+        }
+
+        let status = read_register(STATUS_B);
+        let binary = status & B_BINARY != 0;
+        let decode = |value: u8| if (binary) { value } else { from_bcd(value) };
+
+        let mut hour = decode(raw.hour & !HOUR_PM);
+        if (status & B_24_HOUR == 0) {
+            hour = to_24_hour(hour, raw.hour & HOUR_PM != 0);
+        }
+
+        let year = decode(raw.year);
+        let century = match century_register {
+            Some(_)                      => decode(raw.century) as u16,
+            None if (year < PIVOT_YEAR)  => 20,
+            None                         => 19,
+        };
+
+        Some(DateTime {
+            year: century * 100 + year as u16,
+            month: decode(raw.month),
+            day: decode(raw.day),
+            hour: hour,
+            minute: decode(raw.minute),
+            second: decode(raw.second),
+        })
+    })
+}
+
+
+//##################################################################################################
+//************************************** PRIVATE FUNCTIONS *****************************************
+//##################################################################################################
+
+
+//==================================================================================================
+fn read_raw(century_register: Option<u8>) -> Option<RawTime> {
+//--------------------------------------------------------------------------------------------------
+// Read every time register once no update is in progress, giving up if one outlasts the longest
+// an update can take.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   century_register -> CMOS register holding the century, if any
+//
+// RETURNS: Some(...) -> the registers as read
+//          None      -> an update was still in progress after UPDATE_POLLS polls
+//==================================================================================================
+
+    let mut polls = 0;
+    while (read_register(STATUS_A) & A_UPDATE_IN_PROGRESS != 0) {
+        if (polls == UPDATE_POLLS) {
+            return None;
+        }
+        reference_wait(UPDATE_POLL_NANOS);
+        polls += 1;
+    }
+
+    Some(RawTime {
+        second: read_register(SECONDS),
+        minute: read_register(MINUTES),
+        hour: read_register(HOURS),
+        day: read_register(DAY),
+        month: read_register(MONTH),
+        year: read_register(YEAR),
+        century: century_register.map_or(0, read_register),
+    })
+}
+
+
+//==================================================================================================
+fn read_register(register: u8) -> u8 {
+//--------------------------------------------------------------------------------------------------
+// Read a CMOS register, with NMIs held off while it is selected and let through again after.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   register -> CMOS register to read
+//
+// RETURNS: its value
+//==================================================================================================
+
+    unsafe {
+        outb(CMOS_INDEX, NMI_DISABLE | register);
+        let value = inb(CMOS_DATA);
+        outb(CMOS_INDEX, register);
+        value
+    }
+}
+
+
+//==================================================================================================
+fn from_bcd(value: u8) -> u8 {
+//--------------------------------------------------------------------------------------------------
+// Convert a binary-coded decimal byte to binary.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   value -> two BCD digits
+//
+// RETURNS: the number they encode
+//==================================================================================================
+
+    (value >> 4) * 10 + (value & 0xF)
+}
+
+
+//==================================================================================================
+fn to_24_hour(hour: u8, pm: bool) -> u8 {
+//--------------------------------------------------------------------------------------------------
+// Convert an hour of the 12-hour clock to the 24-hour clock. 12 AM is midnight and 12 PM noon.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   hour -> hour of the 12-hour clock, 1-12
+//          pm   -> whether the hour is after noon
+//
+// RETURNS: the hour, 0-23
+//==================================================================================================
+
+    hour % 12 + if (pm) { 12 } else { 0 }
+}
+
+
+//==================================================================================================
+fn is_leap_year(year: u16) -> bool {
+//--------------------------------------------------------------------------------------------------
+// Determine whether a year of the Gregorian calendar has 29 February.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   year -> the year
+//
+// RETURNS: true if it is a leap year, false otherwise
+//==================================================================================================
+
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+
+//==================================================================================================
+fn days_in_year(year: u16) -> u64 {
+//--------------------------------------------------------------------------------------------------
+// Count the days of a year of the Gregorian calendar.
+//--------------------------------------------------------------------------------------------------
+// TAKES:   year -> the year
+//
+// RETURNS: 366 for a leap year, 365 otherwise
+//==================================================================================================
+
+    if (is_leap_year(year)) { 366 } else { 365 }
+}
+
+
+//##################################################################################################
+//******************************************** TESTS ***********************************************
+//##################################################################################################
+
+
+#[cfg(test)]
+mod tests {
+
+
+    use super::*;
+
+
+    //==============================================================================================
+    fn date_time(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+    //----------------------------------------------------------------------------------------------
+    // Shorthand for building a DateTime.
+    //==============================================================================================
+
+        DateTime { year: year, month: month, day: day, hour: hour, minute: minute, second: second }
+    }
+
+
+    #[test]
+    //==============================================================================================
+    fn to_unix_counts_leap_days() {
+    //----------------------------------------------------------------------------------------------
+    // 2000 is a leap year, divisible by 400; 2100 is not, divisible by 100 only.
+    //==============================================================================================
+
+        assert_eq!(date_time(1970, 1, 1, 0, 0, 0).to_unix(), 0);
+        assert_eq!(date_time(2000, 2, 29, 0, 0, 0).to_unix(), 951_782_400);
+        assert_eq!(date_time(2000, 3, 1, 0, 0, 0).to_unix(), 951_868_800);
+        assert_eq!(date_time(2100, 2, 28, 0, 0, 0).to_unix(), 4_107_456_000);
+        assert_eq!(date_time(2100, 3, 1, 0, 0, 0).to_unix(), 4_107_542_400);
+        assert_eq!(date_time(2038, 1, 19, 3, 14, 7).to_unix(), 2_147_483_647);
+    }
+
+
+    #[test]
+    //==============================================================================================
+    fn to_unix_clamps_invalid_dates_to_the_epoch() {
+    //----------------------------------------------------------------------------------------------
+    // Years before the epoch and months or days of zero have no Unix time.
+    //==============================================================================================
+
+        assert_eq!(date_time(1969, 12, 31, 23, 59, 59).to_unix(), 0);
+        assert_eq!(date_time(2000, 0, 1, 0, 0, 0).to_unix(), 0);
+        assert_eq!(date_time(2000, 13, 1, 0, 0, 0).to_unix(), 0);
+        assert_eq!(date_time(2000, 1, 0, 0, 0, 0).to_unix(), 0);
+    }
+
+
+    #[test]
+    //==============================================================================================
+    fn from_unix_lands_on_leap_days() {
+    //----------------------------------------------------------------------------------------------
+    // The day after 28 February is 29 February only in leap years.
+    //==============================================================================================
+
+        assert_eq!(DateTime::from_unix(0), date_time(1970, 1, 1, 0, 0, 0));
+        assert_eq!(DateTime::from_unix(951_782_400), date_time(2000, 2, 29, 0, 0, 0));
+        assert_eq!(DateTime::from_unix(951_868_799), date_time(2000, 2, 29, 23, 59, 59));
+        assert_eq!(DateTime::from_unix(4_107_542_400), date_time(2100, 3, 1, 0, 0, 0));
+        assert_eq!(DateTime::from_unix(4_107_542_399), date_time(2100, 2, 28, 23, 59, 59));
+        assert_eq!(DateTime::from_unix(1_735_689_599), date_time(2024, 12, 31, 23, 59, 59));
+    }
+
+
+    #[test]
+    //==============================================================================================
+    fn unix_time_round_trips() {
+    //----------------------------------------------------------------------------------------------
+    // Every second converts to a date and back to itself, across month, year and leap boundaries.
+    //==============================================================================================
+
+        let mut seconds = 0;
+        while (seconds < 4_200_000_000) {
+            assert_eq!(DateTime::from_unix(seconds).to_unix(), seconds);
+            seconds += 86_399;
+        }
+
+        for seconds in 951_782_300 .. 951_782_500 {
+            assert_eq!(DateTime::from_unix(seconds).to_unix(), seconds);
+        }
+    }
+
+
+    #[test]
+    //==============================================================================================
+    fn twelve_hour_clock_maps_midnight_and_noon() {
+    //----------------------------------------------------------------------------------------------
+    // 12 AM is hour 0 and 12 PM hour 12; other hours after noon gain 12.
+    //==============================================================================================
+
+        assert_eq!(to_24_hour(12, false), 0);
+        assert_eq!(to_24_hour(1, false), 1);
+        assert_eq!(to_24_hour(11, false), 11);
+        assert_eq!(to_24_hour(12, true), 12);
+        assert_eq!(to_24_hour(1, true), 13);
+        assert_eq!(to_24_hour(11, true), 23);
+    }
+
+
+    #[test]
+    //==============================================================================================
+    fn from_bcd_decodes_both_digits() {
+    //----------------------------------------------------------------------------------------------
+    // Each nibble is one decimal digit.
+    //==============================================================================================
+
+        assert_eq!(from_bcd(0x00), 0);
+        assert_eq!(from_bcd(0x09), 9);
+        assert_eq!(from_bcd(0x10), 10);
+        assert_eq!(from_bcd(0x59), 59);
+        assert_eq!(from_bcd(0x99), 99);
+    }
+
+
+    #[test]
+    //==============================================================================================
+    fn leap_years_follow_the_gregorian_rules() {
+    //----------------------------------------------------------------------------------------------
+    // Every fourth year, except centuries not divisible by 400.
+    //==============================================================================================
+
+        assert!(is_leap_year(2000));
+        assert!(is_leap_year(2024));
+        assert!(!is_leap_year(2023));
+        assert!(!is_leap_year(1900));
+        assert!(!is_leap_year(2100));
+        assert!(is_leap_year(2400));
+    }
+}
@@ -46,6 +46,7 @@ use core::ptr::Unique;
 use volatile::Volatile;
 use spin::Mutex;
 use core::fmt;
+use memory::paging::KERNEL_OFFSET;
 
 
 //##################################################################################################
@@ -53,7 +54,7 @@ use core::fmt;
 //##################################################################################################
 
 
-pub const VGA_BUFFER_START : u32    = 0xB8000;
+pub const VGA_BUFFER_START : usize  = 0xB8000;   // Physical address, mapped at KERNEL_OFFSET
 const VGA_NUM_ROWS     : usize  = 25;
 const VGA_NUM_COLS     : usize  = 80;
 
@@ -65,7 +66,7 @@ pub static WRITER: Mutex<Writer> = Mutex::new(Writer {
     col_position: 0,
     row_position: 0,
     color_fmt: ColorCode::new(VGAColor::Yellow, VGAColor::Black),
-    buffer: unsafe { Unique::new(VGA_BUFFER_START as *mut _) },
+    buffer: unsafe { Unique::new_unchecked((KERNEL_OFFSET + VGA_BUFFER_START) as *mut _) },
 });
 
 
@@ -303,7 +304,7 @@ impl Writer {
     // RETURNS: Mutable reference to VGA buffer    
     //==============================================================================================
     
-        unsafe {self.buffer.get_mut()}
+        unsafe {self.buffer.as_mut()}
     }
 }
 